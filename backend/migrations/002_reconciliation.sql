@@ -0,0 +1,10 @@
+-- Receiving side of a collateral transfer; `transfer` rows are the sending side
+ALTER TYPE transaction_type ADD VALUE 'transfer_in';
+
+-- Which comparison produced a reconciliation row, and how it was resolved
+ALTER TABLE reconciliation_logs
+    ADD COLUMN check_type VARCHAR(32) NOT NULL DEFAULT 'ledger',
+    ADD COLUMN resolved_at TIMESTAMPTZ,
+    ADD COLUMN resolution_note TEXT;
+
+CREATE INDEX idx_reconciliation_logs_open ON reconciliation_logs(vault, check_type) WHERE NOT resolved;
@@ -0,0 +1,29 @@
+-- Vault events decoded from confirmed program transactions. An instruction
+-- can emit more than one event, so event_index orders them within it.
+CREATE TABLE vault_events (
+    signature VARCHAR(88) NOT NULL,
+    instruction_index SMALLINT NOT NULL,
+    event_index SMALLINT NOT NULL,
+    slot BIGINT NOT NULL,
+    block_time TIMESTAMPTZ,
+    event_type VARCHAR(32) NOT NULL,
+    vault VARCHAR(44) NOT NULL,
+    counterparty_vault VARCHAR(44),
+    amount BIGINT NOT NULL,
+    sequence BIGINT NOT NULL,
+    indexed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (signature, instruction_index, event_index)
+);
+
+CREATE INDEX idx_vault_events_vault ON vault_events(vault);
+CREATE INDEX idx_vault_events_slot ON vault_events(slot DESC);
+
+-- Resumption point of background workers such as the event indexer
+CREATE TABLE indexer_cursors (
+    name VARCHAR(32) PRIMARY KEY,
+    last_signature VARCHAR(88) NOT NULL,
+    last_slot BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_transactions_signature ON transactions(signature);
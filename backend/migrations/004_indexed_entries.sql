@@ -0,0 +1,13 @@
+-- Balance changes made by settle_batch, collect_fee and cover_shortfall.
+-- `settlement` rows are the paying side of a settlement, `settlement_in` the
+-- receiving side.
+ALTER TYPE transaction_type ADD VALUE 'settlement';
+ALTER TYPE transaction_type ADD VALUE 'settlement_in';
+ALTER TYPE transaction_type ADD VALUE 'fee';
+ALTER TYPE transaction_type ADD VALUE 'shortfall_cover';
+
+-- Batch and settlement events touch several vaults, and a transfer touches
+-- two; each vault gets its own row, ordered by entry_index within the event.
+ALTER TABLE vault_events ADD COLUMN entry_index SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE vault_events DROP CONSTRAINT vault_events_pkey;
+ALTER TABLE vault_events ADD PRIMARY KEY (signature, instruction_index, event_index, entry_index);
@@ -0,0 +1,14 @@
+-- The submit paths and the event indexer both record a confirmed
+-- transaction, in whichever order they see it. One row per signature, vault
+-- and type keeps the ledger from counting it twice; rows without a signature
+-- are never deduplicated.
+DELETE FROM transactions a
+    USING transactions b
+    WHERE a.signature = b.signature
+      AND a.vault = b.vault
+      AND a.transaction_type = b.transaction_type
+      AND a.ctid > b.ctid;
+
+DROP INDEX idx_transactions_signature;
+CREATE UNIQUE INDEX idx_transactions_signature_vault_type
+    ON transactions(signature, vault, transaction_type);
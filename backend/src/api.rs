@@ -1,42 +1,173 @@
 use axum::{
-    extract::Path,
-    http::StatusCode,
-    response::Json,
+    extract::{ws::WebSocketUpgrade, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{Json, Response},
     routing::{get, post},
     Router,
 };
 use crate::models::*;
 use crate::vault_manager::VaultManager;
-use crate::vault_monitor::VaultMonitor;
+use crate::cpi_manager::CPIManager;
 use crate::database::Database;
+use crate::vault_monitor::VaultMonitor;
+use crate::websocket::WebSocketManager;
 use std::sync::Arc;
 use solana_sdk::pubkey::Pubkey;
 use std::str::FromStr;
 
 pub fn create_router(
     vault_manager: Arc<VaultManager>,
-    vault_monitor: Arc<VaultMonitor>,
     database: Arc<Database>,
+    cpi_manager: Arc<CPIManager>,
+    vault_monitor: Arc<VaultMonitor>,
+    websocket: WebSocketManager,
+    cpi_api_key: Option<String>,
+    admin_api_key: Option<String>,
 ) -> Router {
     Router::new()
         .route("/vault/initialize", post(initialize_vault))
         .route("/vault/deposit", post(deposit))
         .route("/vault/withdraw", post(withdraw))
+        .route("/vault/deposit-sol", post(deposit_sol))
+        .route("/vault/withdraw-sol", post(withdraw_sol))
+        .route("/vault/initialize/build", post(build_initialize_vault))
+        .route("/vault/deposit/build", post(build_deposit))
+        .route("/vault/withdraw/build", post(build_withdraw))
+        .route("/vault/deposit-sol/build", post(build_deposit_sol))
+        .route("/vault/withdraw-sol/build", post(build_withdraw_sol))
+        .route("/tx/submit", post(submit_transaction))
+        .route("/cpi/lock", post(lock_collateral))
+        .route("/cpi/unlock", post(unlock_collateral))
+        .route("/cpi/transfer", post(transfer_collateral))
+        .route("/admin/reconciliation", get(get_reconciliation_logs))
+        .route("/admin/reconciliation/:id/resolve", post(resolve_reconciliation_log))
         .route("/vault/balance/:user", get(get_balance))
         .route("/vault/transactions/:user", get(get_transactions))
         .route("/vault/tvl", get(get_tvl))
+        .route("/insurance/:mint", get(get_insurance_fund))
+        .route("/ws", get(websocket_handler))
         .with_state(AppState {
             vault_manager,
-            vault_monitor,
             database,
+            cpi_manager,
+            vault_monitor,
+            websocket,
+            cpi_api_key: cpi_api_key.map(Arc::from),
+            admin_api_key: admin_api_key.map(Arc::from),
         })
 }
 
 #[derive(Clone)]
 struct AppState {
     vault_manager: Arc<VaultManager>,
-    vault_monitor: Arc<VaultMonitor>,
     database: Arc<Database>,
+    cpi_manager: Arc<CPIManager>,
+    vault_monitor: Arc<VaultMonitor>,
+    websocket: WebSocketManager,
+    // Bearer tokens for the /cpi and /admin routes; each group rejects every
+    // request when its key is unset
+    cpi_api_key: Option<Arc<str>>,
+    admin_api_key: Option<Arc<str>>,
+}
+
+fn require_bearer(
+    expected: Option<&str>,
+    headers: &HeaderMap,
+) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let authorized = match (expected, token) {
+        (Some(expected), Some(token)) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+        _ => false,
+    };
+
+    if authorized {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "success": false,
+                "error": "Unauthorized"
+            }))
+        ))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn cpi_response(
+    result: crate::error::Result<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match result {
+        Ok(signature) => Ok(Json(serde_json::json!({
+            "success": true,
+            "signature": signature
+        }))),
+        Err(e) => {
+            let status = match e {
+                crate::error::Error::InvalidAccount(_) => StatusCode::BAD_REQUEST,
+                crate::error::Error::Unauthorized => StatusCode::FORBIDDEN,
+                crate::error::Error::CallerNotConfigured => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.to_string()
+                }))
+            ))
+        }
+    }
+}
+
+fn parse_user(user: &str) -> Result<Pubkey, (StatusCode, Json<serde_json::Value>)> {
+    Pubkey::from_str(user).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "success": false,
+                "error": format!("Invalid user pubkey: {}", e)
+            }))
+        )
+    })
+}
+
+async fn lock_collateral(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<LockCollateralRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    require_bearer(state.cpi_api_key.as_deref(), &headers)?;
+    let user = parse_user(&req.user)?;
+    cpi_response(state.cpi_manager.lock_collateral(&user, req.amount).await)
+}
+
+async fn unlock_collateral(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<UnlockCollateralRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    require_bearer(state.cpi_api_key.as_deref(), &headers)?;
+    let user = parse_user(&req.user)?;
+    cpi_response(state.cpi_manager.unlock_collateral(&user, req.amount).await)
+}
+
+async fn transfer_collateral(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Json(req): Json<TransferCollateralRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    require_bearer(state.cpi_api_key.as_deref(), &headers)?;
+    let from_user = parse_user(&req.from_user)?;
+    let to_user = parse_user(&req.to_user)?;
+    cpi_response(state.cpi_manager.transfer_collateral(&from_user, &to_user, req.amount).await)
 }
 
 async fn initialize_vault(
@@ -123,14 +254,217 @@ async fn withdraw(
     }
 }
 
+async fn deposit_sol(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<SolDepositRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match state.vault_manager.deposit_sol(&req.user, req.lamports).await {
+        Ok(signature) => Ok(Json(serde_json::json!({
+            "success": true,
+            "signature": signature
+        }))),
+        Err(e) => {
+            let status = if e.to_string().contains("Vault not found") {
+                StatusCode::NOT_FOUND
+            } else if e.to_string().contains("native SOL")
+                || e.to_string().contains("requires user's wallet")
+            {
+                StatusCode::BAD_REQUEST
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Err((
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.to_string()
+                }))
+            ))
+        }
+    }
+}
+
+async fn withdraw_sol(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<SolWithdrawRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match state.vault_manager.withdraw_sol(&req.user, req.lamports).await {
+        Ok(signature) => Ok(Json(serde_json::json!({
+            "success": true,
+            "signature": signature
+        }))),
+        Err(e) => {
+            let status = if e.to_string().contains("Vault not found") {
+                StatusCode::NOT_FOUND
+            } else if e.to_string().contains("Insufficient")
+                || e.to_string().contains("native SOL")
+                || e.to_string().contains("requires user's wallet")
+            {
+                StatusCode::BAD_REQUEST
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Err((
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.to_string()
+                }))
+            ))
+        }
+    }
+}
+
+// Build endpoints return the unsigned transaction itself on success
+fn unsigned_transaction_response(
+    result: crate::error::Result<UnsignedTransaction>,
+) -> Result<Json<UnsignedTransaction>, (StatusCode, Json<serde_json::Value>)> {
+    match result {
+        Ok(transaction) => Ok(Json(transaction)),
+        Err(e) => {
+            let status = match e {
+                crate::error::Error::VaultNotFound => StatusCode::NOT_FOUND,
+                crate::error::Error::InvalidAccount(_) | crate::error::Error::InsufficientBalance => {
+                    StatusCode::BAD_REQUEST
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.to_string()
+                }))
+            ))
+        }
+    }
+}
+
+async fn build_initialize_vault(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<InitializeVaultRequest>,
+) -> Result<Json<UnsignedTransaction>, (StatusCode, Json<serde_json::Value>)> {
+    unsigned_transaction_response(state.vault_manager.build_initialize_vault(&req.user).await)
+}
+
+async fn build_deposit(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<DepositRequest>,
+) -> Result<Json<UnsignedTransaction>, (StatusCode, Json<serde_json::Value>)> {
+    unsigned_transaction_response(state.vault_manager.build_deposit(&req.user, req.amount).await)
+}
+
+async fn build_withdraw(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<WithdrawRequest>,
+) -> Result<Json<UnsignedTransaction>, (StatusCode, Json<serde_json::Value>)> {
+    unsigned_transaction_response(state.vault_manager.build_withdraw(&req.user, req.amount).await)
+}
+
+async fn build_deposit_sol(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<SolDepositRequest>,
+) -> Result<Json<UnsignedTransaction>, (StatusCode, Json<serde_json::Value>)> {
+    unsigned_transaction_response(state.vault_manager.build_deposit_sol(&req.user, req.lamports).await)
+}
+
+async fn build_withdraw_sol(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<SolWithdrawRequest>,
+) -> Result<Json<UnsignedTransaction>, (StatusCode, Json<serde_json::Value>)> {
+    unsigned_transaction_response(state.vault_manager.build_withdraw_sol(&req.user, req.lamports).await)
+}
+
+async fn submit_transaction(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Json(req): Json<SubmitTransactionRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    match state.vault_manager.submit_transaction(&req.transaction).await {
+        Ok(submitted) => Ok(Json(serde_json::json!({
+            "success": true,
+            "signature": submitted.signature,
+            "vault": submitted.vault,
+            "transaction_type": submitted.transaction_type,
+            "amount": submitted.amount
+        }))),
+        Err(e) => {
+            let status = match e {
+                crate::error::Error::InvalidAccount(_) => StatusCode::BAD_REQUEST,
+                crate::error::Error::Unauthorized => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Err((
+                status,
+                Json(serde_json::json!({
+                    "success": false,
+                    "error": e.to_string()
+                }))
+            ))
+        }
+    }
+}
+
+async fn get_reconciliation_logs(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ReconciliationQuery>,
+) -> Result<Json<Vec<ReconciliationLog>>, (StatusCode, Json<serde_json::Value>)> {
+    require_bearer(state.admin_api_key.as_deref(), &headers)?;
+
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    state.database.get_reconciliation_logs(query.resolved, limit).await
+        .map(Json)
+        .map_err(|e| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "success": false,
+                "error": e.to_string()
+            }))
+        ))
+}
+
+async fn resolve_reconciliation_log(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<uuid::Uuid>,
+    body: Option<Json<ResolveReconciliationRequest>>,
+) -> Result<Json<ReconciliationLog>, (StatusCode, Json<serde_json::Value>)> {
+    require_bearer(state.admin_api_key.as_deref(), &headers)?;
+
+    let note = body.and_then(|Json(req)| req.note);
+    match state.database.resolve_reconciliation_log(id, note.as_deref()).await {
+        Ok(Some(log)) => Ok(Json(log)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "success": false,
+                "error": "Reconciliation log not found"
+            }))
+        )),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "success": false,
+                "error": e.to_string()
+            }))
+        )),
+    }
+}
+
 async fn get_balance(
     axum::extract::State(state): axum::extract::State<AppState>,
     Path(user): Path<String>,
-) -> Result<Json<VaultInfo>, StatusCode> {
-    match state.vault_manager.get_vault_info(&user).await {
-        Ok(info) => Ok(Json(info)),
-        Err(_) => Err(StatusCode::NOT_FOUND),
-    }
+) -> Result<Json<VaultBalanceResponse>, StatusCode> {
+    let vault = state.vault_manager.get_vault_info(&user).await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    let deposit_headroom = state.vault_manager.get_deposit_headroom(&vault).await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(VaultBalanceResponse {
+        vault,
+        deposit_headroom,
+    }))
 }
 
 async fn get_transactions(
@@ -150,14 +484,50 @@ async fn get_transactions(
     }
 }
 
+async fn get_insurance_fund(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Path(mint): Path<String>,
+) -> Result<Json<InsuranceFundInfo>, StatusCode> {
+    match state.vault_manager.get_insurance_fund(&mint).await {
+        Ok(fund) => Ok(Json(fund)),
+        Err(crate::error::Error::InvalidAccount(_)) => Err(StatusCode::BAD_REQUEST),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
 async fn get_tvl(
     axum::extract::State(state): axum::extract::State<AppState>,
-) -> Json<TVLResponse> {
-    let tvl = state.vault_monitor.get_tvl().await.unwrap_or(0);
-    Json(TVLResponse {
-        total_value_locked: tvl,
-        total_vaults: 0,
+) -> Result<Json<TVLResponse>, StatusCode> {
+    // Read from the on-chain MintStats accounts
+    let stats = state.vault_manager.get_protocol_stats().await.map_err(|e| {
+        tracing::warn!("Failed to read protocol stats: {}", e);
+        StatusCode::SERVICE_UNAVAILABLE
+    })?;
+    // Cross-check against the vault monitor's latest scan; the counters are
+    // still served when the scan is unavailable
+    let scan = state.vault_monitor.get_tvl().await
+        .map_err(|e| tracing::warn!("Failed to read the vault scan: {}", e))
+        .ok();
+
+    Ok(Json(TVLResponse {
+        total_vaults: stats.vault_count,
+        mints: stats.mints
+            .into_iter()
+            .map(|stats| MintTvlResponse {
+                tvl_headroom: stats.tvl_headroom(),
+                scan: scan.as_ref().and_then(|scan| {
+                    scan.mints.iter().find(|totals| totals.mint == stats.mint).cloned()
+                }),
+                stats,
+            })
+            .collect(),
         timestamp: chrono::Utc::now().timestamp(),
-    })
+    }))
 }
 
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    axum::extract::State(state): axum::extract::State<AppState>,
+) -> Response {
+    ws.on_upgrade(move |socket| async move { state.websocket.handle_socket(socket).await })
+}
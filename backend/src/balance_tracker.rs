@@ -1,10 +1,15 @@
 use solana_sdk::pubkey::Pubkey;
+use solana_client::rpc_client::RpcClient;
 use crate::database::Database;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::models::{ReconciliationCheck, ReconciliationLog};
+use crate::vault_manager::decode_vault;
+use std::str::FromStr;
+use std::sync::Arc;
 use tokio::time::{interval, Duration};
 
 pub struct BalanceTracker {
-    #[allow(dead_code)]
+    rpc_client: Arc<RpcClient>,
     database: Database,
     #[allow(dead_code)]
     program_id: Pubkey,
@@ -12,7 +17,11 @@ pub struct BalanceTracker {
 
 impl BalanceTracker {
     pub fn new(database: Database, program_id: Pubkey) -> Self {
+        let rpc_url = std::env::var("RPC_URL")
+            .unwrap_or_else(|_| "http://localhost:8899".to_string());
+
         Self {
+            rpc_client: Arc::new(RpcClient::new(rpc_url)),
             database,
             program_id,
         }
@@ -22,19 +31,132 @@ impl BalanceTracker {
         Ok(())
     }
 
-    pub async fn reconcile_balance(&self, _vault: &str) -> Result<()> {
-        // Compare on-chain balance with database
-        // Log discrepancies
-        Ok(())
+    /// Checks the vault's `total_balance` against the sum of its balance
+    /// fields, its token account amount and the database ledger, logging a
+    /// reconciliation row for each mismatch. A mismatch that already has an
+    /// open row with the same discrepancy is not logged again. The ledger is
+    /// only compared once the indexer has stored every sequence step of the
+    /// vault, since a missed event would otherwise read as a discrepancy.
+    /// Returns the rows inserted.
+    pub async fn reconcile_balance(&self, vault: &str) -> Result<Vec<ReconciliationLog>> {
+        let vault_pubkey = Pubkey::from_str(vault)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid vault pubkey: {}", e)))?;
+
+        let rpc_client = self.rpc_client.clone();
+        let account = tokio::task::spawn_blocking(move || {
+            rpc_client.get_account(&vault_pubkey)
+                .map_err(|e| Error::SolanaClient(format!("Failed to fetch vault account: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let vault_info = decode_vault(vault_pubkey, &account.data)?;
+        let token_account = Pubkey::from_str(&vault_info.token_account)
+            .map_err(|_| Error::SolanaClient("Failed to parse token_account".to_string()))?;
+
+        let rpc_client = self.rpc_client.clone();
+        let token_amount = tokio::task::spawn_blocking(move || {
+            rpc_client.get_token_account_balance(&token_account)
+                .map_err(|e| Error::SolanaClient(format!("Failed to fetch token account: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+        let token_amount = token_amount.amount.parse::<u64>()
+            .map_err(|e| Error::SolanaClient(format!("Failed to parse token amount: {}", e)))?;
+
+        let components = vault_info.available_balance
+            .saturating_add(vault_info.locked_balance)
+            .saturating_add(vault_info.sub_account_balance);
+        let mut checks = vec![
+            (ReconciliationCheck::VaultFields, components as i64),
+            (ReconciliationCheck::TokenAccount, token_amount as i64),
+        ];
+
+        let indexed = self.database.get_indexed_sequences(vault).await?;
+        if ledger_is_complete(indexed, vault_info.sequence) {
+            checks.push((ReconciliationCheck::Ledger, self.database.get_ledger_balance(vault).await?));
+        } else {
+            tracing::debug!(
+                "Skipping ledger check for {}: sequence {} not fully indexed",
+                vault,
+                vault_info.sequence
+            );
+        }
+
+        let mut logs = Vec::new();
+        for (check_type, compared) in checks {
+            let on_chain = vault_info.total_balance as i64;
+            if on_chain == compared {
+                continue;
+            }
+
+            let open = self.database.get_open_reconciliation_log(vault, check_type).await?;
+            if open.is_some_and(|log| log.discrepancy == on_chain - compared) {
+                continue;
+            }
+
+            tracing::warn!(
+                "Reconciliation mismatch for {} ({}): on-chain {} vs {}",
+                vault,
+                check_type.as_str(),
+                on_chain,
+                compared
+            );
+            logs.push(
+                self.database
+                    .create_reconciliation_log(vault, check_type, on_chain, compared)
+                    .await?,
+            );
+        }
+
+        Ok(logs)
     }
 
+    /// Reconciles every known vault once a minute. Failures for one vault
+    /// are logged and do not stop the others.
     pub async fn start_monitoring(&self) {
         let mut interval = interval(Duration::from_secs(60));
-        
+
         loop {
             interval.tick().await;
-            // Monitor all vaults
+
+            let vaults = match self.database.get_all_vaults().await {
+                Ok(vaults) => vaults,
+                Err(e) => {
+                    tracing::warn!("Failed to list vaults for reconciliation: {}", e);
+                    continue;
+                }
+            };
+
+            for vault in vaults {
+                if let Err(e) = self.reconcile_balance(&vault).await {
+                    tracing::warn!("Failed to reconcile {}: {}", vault, e);
+                }
+            }
         }
     }
 }
 
+// Whether `vault_events` holds every sequence step from initialization (0)
+// to `sequence`. Events indexed before an event kind was supported, or not
+// indexed yet, leave gaps or a lower maximum.
+fn ledger_is_complete((count, max): (i64, Option<i64>), sequence: u64) -> bool {
+    max == Some(sequence as i64) && count == sequence as i64 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_the_ledger_only_when_every_sequence_is_indexed() {
+        assert!(ledger_is_complete((1, Some(0)), 0));
+        assert!(ledger_is_complete((8, Some(7)), 7));
+        // Nothing indexed yet
+        assert!(!ledger_is_complete((0, None), 0));
+        // The indexer has not caught up with the account
+        assert!(!ledger_is_complete((7, Some(6)), 7));
+        // A step went through an event that was not indexed
+        assert!(!ledger_is_complete((7, Some(7)), 7));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Highest compute unit limit a transaction may request
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// Transactions the backend builds, each with its own compute budget policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    InitializeVault,
+    Deposit,
+    Withdraw,
+    DepositSol,
+    WithdrawSol,
+    LockCollateral,
+    UnlockCollateral,
+    TransferCollateral,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 8] = [
+        Operation::InitializeVault,
+        Operation::Deposit,
+        Operation::Withdraw,
+        Operation::DepositSol,
+        Operation::WithdrawSol,
+        Operation::LockCollateral,
+        Operation::UnlockCollateral,
+        Operation::TransferCollateral,
+    ];
+
+    /// Suffix of the environment variables overriding this operation's policy
+    pub fn env_suffix(&self) -> &'static str {
+        match self {
+            Operation::InitializeVault => "INITIALIZE_VAULT",
+            Operation::Deposit => "DEPOSIT",
+            Operation::Withdraw => "WITHDRAW",
+            Operation::DepositSol => "DEPOSIT_SOL",
+            Operation::WithdrawSol => "WITHDRAW_SOL",
+            Operation::LockCollateral => "LOCK",
+            Operation::UnlockCollateral => "UNLOCK",
+            Operation::TransferCollateral => "TRANSFER",
+        }
+    }
+}
+
+/// How the compute unit limit and priority fee of one operation are chosen.
+/// Fees are in micro-lamports per compute unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ComputeBudgetPolicy {
+    /// Limit used when the transaction cannot be simulated
+    pub default_unit_limit: u32,
+    /// Headroom added on top of the simulated units, in percent
+    pub unit_margin_percent: u32,
+    /// Percentile of the recent prioritization fees to pay
+    pub fee_percentile: u8,
+    pub min_priority_fee: u64,
+    pub max_priority_fee: u64,
+}
+
+impl Default for ComputeBudgetPolicy {
+    fn default() -> Self {
+        Self {
+            default_unit_limit: 200_000,
+            unit_margin_percent: 20,
+            fee_percentile: 75,
+            min_priority_fee: 0,
+            max_priority_fee: 1_000_000,
+        }
+    }
+}
+
+impl ComputeBudgetPolicy {
+    /// Limit for a transaction that consumed `units_consumed` in simulation.
+    pub fn unit_limit(&self, units_consumed: u64) -> u32 {
+        let limit = units_consumed.saturating_mul(100 + self.unit_margin_percent as u64) / 100;
+        limit.min(MAX_COMPUTE_UNIT_LIMIT as u64) as u32
+    }
+
+    /// The configured percentile of `recent_fees`, held between the floor
+    /// and the ceiling. No recent fees means the floor.
+    pub fn priority_fee(&self, recent_fees: &[u64]) -> u64 {
+        let mut fees = recent_fees.to_vec();
+        fees.sort_unstable();
+
+        let percentile = self.fee_percentile.min(100) as usize;
+        let fee = match fees.len() {
+            0 => 0,
+            len => fees[(len - 1) * percentile / 100],
+        };
+        fee.max(self.min_priority_fee).min(self.max_priority_fee)
+    }
+}
+
+/// Compute budget policies for every operation.
+#[derive(Debug, Clone, Default)]
+pub struct ComputeBudgetConfig {
+    policies: HashMap<Operation, ComputeBudgetPolicy>,
+}
+
+impl ComputeBudgetConfig {
+    /// Reads `COMPUTE_UNIT_LIMIT`, `COMPUTE_UNIT_MARGIN_PERCENT`,
+    /// `PRIORITY_FEE_PERCENTILE`, `PRIORITY_FEE_FLOOR` and
+    /// `PRIORITY_FEE_CEILING`. Each can be overridden for one operation by
+    /// appending its suffix, e.g. `PRIORITY_FEE_FLOOR_WITHDRAW`.
+    pub fn from_env() -> Self {
+        let policies = Operation::ALL
+            .into_iter()
+            .map(|operation| {
+                let defaults = ComputeBudgetPolicy::default();
+                let min_priority_fee = env_value("PRIORITY_FEE_FLOOR", operation)
+                    .unwrap_or(defaults.min_priority_fee);
+                let policy = ComputeBudgetPolicy {
+                    default_unit_limit: env_value("COMPUTE_UNIT_LIMIT", operation)
+                        .unwrap_or(defaults.default_unit_limit)
+                        .min(MAX_COMPUTE_UNIT_LIMIT),
+                    unit_margin_percent: env_value("COMPUTE_UNIT_MARGIN_PERCENT", operation)
+                        .unwrap_or(defaults.unit_margin_percent),
+                    fee_percentile: env_value("PRIORITY_FEE_PERCENTILE", operation)
+                        .unwrap_or(defaults.fee_percentile)
+                        .min(100),
+                    min_priority_fee,
+                    max_priority_fee: env_value("PRIORITY_FEE_CEILING", operation)
+                        .unwrap_or(defaults.max_priority_fee)
+                        .max(min_priority_fee),
+                };
+                (operation, policy)
+            })
+            .collect();
+
+        Self { policies }
+    }
+
+    pub fn policy(&self, operation: Operation) -> ComputeBudgetPolicy {
+        self.policies.get(&operation).copied().unwrap_or_default()
+    }
+
+    pub fn with_policy(mut self, operation: Operation, policy: ComputeBudgetPolicy) -> Self {
+        self.policies.insert(operation, policy);
+        self
+    }
+}
+
+// The operation-specific variable if set, else the shared one
+fn env_value<T: FromStr>(key: &str, operation: Operation) -> Option<T> {
+    std::env::var(format!("{}_{}", key, operation.env_suffix()))
+        .or_else(|_| std::env::var(key))
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_limit_adds_the_margin_and_stays_under_the_maximum() {
+        let policy = ComputeBudgetPolicy { unit_margin_percent: 20, ..ComputeBudgetPolicy::default() };
+
+        assert_eq!(policy.unit_limit(50_000), 60_000);
+        assert_eq!(policy.unit_limit(0), 0);
+        assert_eq!(policy.unit_limit(1_300_000), MAX_COMPUTE_UNIT_LIMIT);
+    }
+
+    #[test]
+    fn priority_fee_takes_the_percentile_between_floor_and_ceiling() {
+        let policy = ComputeBudgetPolicy {
+            fee_percentile: 75,
+            min_priority_fee: 1_000,
+            max_priority_fee: 50_000,
+            ..ComputeBudgetPolicy::default()
+        };
+        let fees = [0, 0, 2_000, 8_000, 40_000, 5_000, 0, 3_000, 90_000];
+
+        assert_eq!(policy.priority_fee(&fees), 8_000);
+        assert_eq!(policy.priority_fee(&[]), 1_000);
+        assert_eq!(policy.priority_fee(&[0, 0, 0]), 1_000);
+        assert_eq!(policy.priority_fee(&[200_000, 300_000]), 50_000);
+        assert_eq!(ComputeBudgetPolicy { fee_percentile: 100, ..policy }.priority_fee(&fees), 50_000);
+    }
+
+    #[test]
+    fn operations_fall_back_to_the_default_policy() {
+        let custom = ComputeBudgetPolicy { min_priority_fee: 10_000, ..ComputeBudgetPolicy::default() };
+        let config = ComputeBudgetConfig::default().with_policy(Operation::Withdraw, custom);
+
+        assert_eq!(config.policy(Operation::Withdraw), custom);
+        assert_eq!(config.policy(Operation::Deposit), ComputeBudgetPolicy::default());
+    }
+}
@@ -1,38 +1,217 @@
 use solana_sdk::pubkey::Pubkey;
-use crate::error::Result;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use std::sync::Arc;
+use crate::compute_budget::{ComputeBudgetConfig, Operation};
+use crate::database::Database;
+use crate::error::{Error, Result};
+use crate::models::TransactionType;
+use crate::transaction_builder::TransactionBuilder;
+use crate::vault_manager::load_payer;
 
+/// Locks, unlocks and transfers collateral by calling `caller_program`'s
+/// keeper instructions (`batch_lock`, `batch_unlock`, `transfer_collateral`),
+/// signed and paid for by the admin wallet as keeper.
+///
+/// Every vault instruction that takes a `caller_program` also requires a
+/// signature from that program's `[b"vault_caller"]` PDA, which only the
+/// program itself can produce in a CPI. These operations therefore go
+/// through the caller program and its own checks; a top-level vault
+/// instruction naming it would be rejected.
+/// `caller_program` must implement the reference position manager's keeper
+/// interface; without a configured caller every request fails with
+/// `CallerNotConfigured`.
 pub struct CPIManager {
-    #[allow(dead_code)]
-    program_id: Pubkey,
+    rpc_client: Arc<RpcClient>,
+    caller_program: Option<Pubkey>,
     authorized_programs: Vec<Pubkey>,
+    database: Database,
+    mint: Pubkey,
+    payer: Arc<Keypair>,
+    tx_builder: TransactionBuilder,
 }
 
 impl CPIManager {
-    pub fn new(program_id: Pubkey, authorized_programs: Vec<Pubkey>) -> Self {
-        Self {
-            program_id,
-            authorized_programs,
+    /// Fails if `caller_program` is the vault program itself: the vault
+    /// never CPIs into its own lock instructions, so listing it would only
+    /// serve to let wallets claim its authority.
+    pub fn new(
+        program_id: Pubkey,
+        caller_program: Option<Pubkey>,
+        authorized_programs: Vec<Pubkey>,
+        database: Database,
+        mint: Pubkey,
+    ) -> Result<Self> {
+        if caller_program == Some(program_id) {
+            return Err(Error::InvalidAccount(
+                "Caller program must be the integrating program, not the vault program".to_string(),
+            ));
         }
+
+        let rpc_url = std::env::var("RPC_URL")
+            .unwrap_or_else(|_| "http://localhost:8899".to_string());
+
+        Ok(Self {
+            rpc_client: Arc::new(RpcClient::new(rpc_url)),
+            caller_program,
+            authorized_programs,
+            database,
+            mint,
+            payer: load_payer(),
+            tx_builder: TransactionBuilder::new(program_id)
+                .with_compute_budget(ComputeBudgetConfig::from_env()),
+        })
+    }
+
+    pub fn caller_program(&self) -> Option<&Pubkey> {
+        self.caller_program.as_ref()
     }
 
     pub async fn lock_collateral(
         &self,
-        _user: &Pubkey,
-        _amount: u64,
+        user: &Pubkey,
+        amount: u64,
     ) -> Result<String> {
-        Ok("transaction_signature".to_string())
+        let caller_program = self.check_request(amount)?;
+
+        let vault = self.tx_builder.vault_address(user);
+        let instruction = self.tx_builder.build_keeper_lock_instruction(
+            caller_program,
+            self.payer.pubkey(),
+            self.mint,
+            *user,
+            amount,
+        );
+
+        let signature = self.send(vec![instruction], Operation::LockCollateral).await?;
+        self.database.create_transaction(
+            &vault.to_string(),
+            TransactionType::Lock,
+            amount,
+            Some(&signature),
+        ).await?;
+
+        Ok(signature)
     }
 
     pub async fn unlock_collateral(
         &self,
-        _user: &Pubkey,
-        _amount: u64,
+        user: &Pubkey,
+        amount: u64,
+    ) -> Result<String> {
+        let caller_program = self.check_request(amount)?;
+
+        let vault = self.tx_builder.vault_address(user);
+        let instruction = self.tx_builder.build_keeper_unlock_instruction(
+            caller_program,
+            self.payer.pubkey(),
+            self.mint,
+            *user,
+            amount,
+        );
+
+        let signature = self.send(vec![instruction], Operation::UnlockCollateral).await?;
+        self.database.create_transaction(
+            &vault.to_string(),
+            TransactionType::Unlock,
+            amount,
+            Some(&signature),
+        ).await?;
+
+        Ok(signature)
+    }
+
+    /// Moves available collateral of the configured mint between two vaults
+    /// with `transfer_collateral`. Recorded as `Transfer` on the sending
+    /// vault and `TransferIn` on the receiving one, as the indexer records
+    /// the `TransferEvent`.
+    pub async fn transfer_collateral(
+        &self,
+        from_user: &Pubkey,
+        to_user: &Pubkey,
+        amount: u64,
     ) -> Result<String> {
-        Ok("transaction_signature".to_string())
+        let caller_program = self.check_request(amount)?;
+        if from_user == to_user {
+            return Err(Error::InvalidAccount("Cannot transfer to the same vault".to_string()));
+        }
+
+        let from_vault = self.tx_builder.vault_address(from_user);
+        let to_vault = self.tx_builder.vault_address(to_user);
+        let instruction = self.tx_builder.build_keeper_transfer_instruction(
+            caller_program,
+            self.payer.pubkey(),
+            self.mint,
+            *from_user,
+            *to_user,
+            amount,
+        );
+
+        let signature = self.send(vec![instruction], Operation::TransferCollateral).await?;
+        for (vault, transaction_type) in [
+            (from_vault, TransactionType::Transfer),
+            (to_vault, TransactionType::TransferIn),
+        ] {
+            self.database.create_transaction(
+                &vault.to_string(),
+                transaction_type,
+                amount,
+                Some(&signature),
+            ).await?;
+        }
+
+        Ok(signature)
     }
 
     pub fn is_authorized(&self, program: &Pubkey) -> bool {
         self.authorized_programs.contains(program)
     }
-}
 
+    // The caller program to name. The local list only avoids paying for a
+    // transaction the program would reject; it is not an access control.
+    fn check_request(&self, amount: u64) -> Result<Pubkey> {
+        let caller_program = self.caller_program.ok_or(Error::CallerNotConfigured)?;
+        if amount == 0 {
+            return Err(Error::InvalidAccount("Amount must be greater than 0".to_string()));
+        }
+        if !self.is_authorized(&caller_program) {
+            return Err(Error::Unauthorized);
+        }
+        Ok(caller_program)
+    }
+
+    async fn send(&self, instructions: Vec<Instruction>, operation: Operation) -> Result<String> {
+        let instructions = self.tx_builder
+            .add_compute_budget(&self.rpc_client, self.payer.pubkey(), instructions, operation)
+            .await?;
+
+        let recent_blockhash = tokio::task::spawn_blocking({
+            let rpc_client = self.rpc_client.clone();
+            move || rpc_client.get_latest_blockhash()
+                .map_err(|e| Error::SolanaClient(format!("Failed to get blockhash: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&self.payer.pubkey()),
+            &[&*self.payer],
+            recent_blockhash,
+        );
+
+        let rpc_client = self.rpc_client.clone();
+        let signature = tokio::task::spawn_blocking(move || {
+            rpc_client.send_and_confirm_transaction(&transaction)
+                .map_err(|e| Error::TransactionFailed(format!("Failed to send transaction: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::TransactionFailed(format!("Task join error: {}", e)))??;
+
+        Ok(signature.to_string())
+    }
+}
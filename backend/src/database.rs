@@ -1,6 +1,9 @@
 use sqlx::PgPool;
+use std::collections::HashMap;
 use chrono::Utc;
-use crate::models::{TransactionRecord, TransactionType, BalanceSnapshot};
+use crate::models::{
+    BalanceSnapshot, IndexedEvent, ReconciliationCheck, ReconciliationLog, TransactionRecord, TransactionType,
+};
 use crate::error::Result;
 
 pub struct Database {
@@ -12,39 +15,34 @@ impl Database {
         Self { pool }
     }
 
+    /// Records a transaction the backend sent. Returns `false` without
+    /// writing anything if a row with the same signature, vault and type
+    /// exists, e.g. because the event indexer stored it first.
     pub async fn create_transaction(
         &self,
         vault: &str,
         transaction_type: TransactionType,
         amount: u64,
         signature: Option<&str>,
-    ) -> Result<TransactionRecord> {
-        let id = uuid::Uuid::new_v4();
-        let timestamp = Utc::now();
-        
-        sqlx::query(
+    ) -> Result<bool> {
+        let inserted = sqlx::query(
             r#"
             INSERT INTO transactions (id, vault, transaction_type, amount, signature, timestamp)
             VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (signature, vault, transaction_type) DO NOTHING
             "#,
         )
-        .bind(id)
+        .bind(uuid::Uuid::new_v4())
         .bind(vault)
         .bind(transaction_type as TransactionType)
         .bind(amount as i64)
         .bind(signature)
-        .bind(timestamp)
+        .bind(Utc::now())
         .execute(&self.pool)
-        .await?;
+        .await?
+        .rows_affected() == 1;
 
-        Ok(TransactionRecord {
-            id: Some(id),
-            vault: vault.to_string(),
-            transaction_type,
-            amount,
-            signature: signature.map(|s| s.to_string()),
-            timestamp,
-        })
+        Ok(inserted)
     }
 
     pub async fn get_transactions(
@@ -163,8 +161,9 @@ impl Database {
     pub async fn get_all_vaults(&self) -> Result<Vec<String>> {
         let rows = sqlx::query_as::<_, (String,)>(
             r#"
-            SELECT DISTINCT vault
-            FROM transactions
+            SELECT vault FROM transactions
+            UNION
+            SELECT vault FROM vaults
             "#,
         )
         .fetch_all(&self.pool)
@@ -172,5 +171,321 @@ impl Database {
 
         Ok(rows.into_iter().map(|(vault,)| vault).collect())
     }
+
+    /// Inserts or refreshes a vault's metadata row, keyed by owner
+    pub async fn upsert_vault(
+        &self,
+        owner: &str,
+        vault: &str,
+        token_account: &str,
+        created_at: chrono::DateTime<Utc>,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO vaults (owner, vault, token_account, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, NOW())
+            ON CONFLICT (owner) DO UPDATE
+            SET vault = EXCLUDED.vault, token_account = EXCLUDED.token_account, updated_at = NOW()
+            "#,
+        )
+        .bind(owner)
+        .bind(vault)
+        .bind(token_account)
+        .bind(created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Stores an indexed event and the `transactions` row it implies, if
+    /// any, in one database transaction. Returns `false` without writing
+    /// anything if the event was already stored. The `transactions` row is
+    /// skipped when one with the same signature, vault and type exists, e.g.
+    /// because the backend submitted the transaction itself and recorded it
+    /// first.
+    pub async fn insert_indexed_event(
+        &self,
+        event: &IndexedEvent,
+        ledger: Option<TransactionType>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let inserted = sqlx::query(
+            r#"
+            INSERT INTO vault_events (signature, instruction_index, event_index, entry_index, slot, block_time, event_type, vault, counterparty_vault, amount, sequence)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (signature, instruction_index, event_index, entry_index) DO NOTHING
+            "#,
+        )
+        .bind(&event.signature)
+        .bind(event.instruction_index as i16)
+        .bind(event.event_index as i16)
+        .bind(event.entry_index as i16)
+        .bind(event.slot as i64)
+        .bind(event.block_time)
+        .bind(&event.event_type)
+        .bind(&event.vault)
+        .bind(&event.counterparty_vault)
+        .bind(event.amount as i64)
+        .bind(event.sequence as i64)
+        .execute(&mut tx)
+        .await?
+        .rows_affected() == 1;
+
+        if !inserted {
+            return Ok(false);
+        }
+
+        if let Some(transaction_type) = ledger {
+            sqlx::query(
+                r#"
+                INSERT INTO transactions (id, vault, transaction_type, amount, signature, timestamp)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ON CONFLICT (signature, vault, transaction_type) DO NOTHING
+                "#,
+            )
+            .bind(uuid::Uuid::new_v4())
+            .bind(&event.vault)
+            .bind(transaction_type)
+            .bind(event.amount as i64)
+            .bind(&event.signature)
+            .bind(event.block_time.unwrap_or_else(Utc::now))
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Owners of the given vaults that are in the `vaults` table, keyed by
+    /// vault
+    pub async fn get_vault_owners(&self, vaults: &[String]) -> Result<HashMap<String, String>> {
+        let rows = sqlx::query_as::<_, (String, String)>(
+            r#"
+            SELECT vault, owner FROM vaults
+            WHERE vault = ANY($1)
+            "#,
+        )
+        .bind(vaults)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Last signature processed by the named worker, if it has run before
+    pub async fn get_cursor(&self, name: &str) -> Result<Option<String>> {
+        let row = sqlx::query_as::<_, (String,)>(
+            r#"
+            SELECT last_signature
+            FROM indexer_cursors
+            WHERE name = $1
+            "#,
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(signature,)| signature))
+    }
+
+    pub async fn set_cursor(&self, name: &str, signature: &str, slot: u64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO indexer_cursors (name, last_signature, last_slot, updated_at)
+            VALUES ($1, $2, $3, NOW())
+            ON CONFLICT (name) DO UPDATE
+            SET last_signature = EXCLUDED.last_signature, last_slot = EXCLUDED.last_slot, updated_at = NOW()
+            "#,
+        )
+        .bind(name)
+        .bind(signature)
+        .bind(slot as i64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Number of distinct sequence numbers indexed for a vault and the
+    /// highest of them
+    pub async fn get_indexed_sequences(&self, vault: &str) -> Result<(i64, Option<i64>)> {
+        let row = sqlx::query_as::<_, (i64, Option<i64>)>(
+            r#"
+            SELECT COUNT(DISTINCT sequence), MAX(sequence)
+            FROM vault_events
+            WHERE vault = $1
+            "#,
+        )
+        .bind(vault)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row)
+    }
+
+    /// Net balance implied by a vault's rows in `transactions`; locks and
+    /// unlocks move collateral within the vault and do not count.
+    pub async fn get_ledger_balance(&self, vault: &str) -> Result<i64> {
+        let sums = sqlx::query_as::<_, (TransactionType, i64)>(
+            r#"
+            SELECT transaction_type, COALESCE(SUM(amount), 0)::BIGINT
+            FROM transactions
+            WHERE vault = $1
+            GROUP BY transaction_type
+            "#,
+        )
+        .bind(vault)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(sums
+            .into_iter()
+            .map(|(transaction_type, sum)| transaction_type.ledger_sign() * sum)
+            .sum())
+    }
+
+    pub async fn create_reconciliation_log(
+        &self,
+        vault: &str,
+        check_type: ReconciliationCheck,
+        on_chain_balance: i64,
+        database_balance: i64,
+    ) -> Result<ReconciliationLog> {
+        let id = uuid::Uuid::new_v4();
+        let timestamp = Utc::now();
+        let discrepancy = on_chain_balance - database_balance;
+
+        sqlx::query(
+            r#"
+            INSERT INTO reconciliation_logs (id, vault, check_type, on_chain_balance, database_balance, discrepancy, resolved, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6, FALSE, $7)
+            "#,
+        )
+        .bind(id)
+        .bind(vault)
+        .bind(check_type.as_str())
+        .bind(on_chain_balance)
+        .bind(database_balance)
+        .bind(discrepancy)
+        .bind(timestamp)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(ReconciliationLog {
+            id,
+            vault: vault.to_string(),
+            check_type,
+            on_chain_balance,
+            database_balance,
+            discrepancy,
+            resolved: false,
+            resolved_at: None,
+            resolution_note: None,
+            timestamp,
+        })
+    }
+
+    /// Latest unresolved row for a vault and check, if any
+    pub async fn get_open_reconciliation_log(
+        &self,
+        vault: &str,
+        check_type: ReconciliationCheck,
+    ) -> Result<Option<ReconciliationLog>> {
+        let row = sqlx::query_as::<_, ReconciliationRow>(
+            r#"
+            SELECT id, vault, check_type, on_chain_balance, database_balance, discrepancy,
+                   COALESCE(resolved, FALSE), resolved_at, resolution_note, timestamp
+            FROM reconciliation_logs
+            WHERE vault = $1 AND check_type = $2 AND NOT COALESCE(resolved, FALSE)
+            ORDER BY timestamp DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(vault)
+        .bind(check_type.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.and_then(reconciliation_log))
+    }
+
+    pub async fn get_reconciliation_logs(
+        &self,
+        resolved: Option<bool>,
+        limit: i64,
+    ) -> Result<Vec<ReconciliationLog>> {
+        let rows = sqlx::query_as::<_, ReconciliationRow>(
+            r#"
+            SELECT id, vault, check_type, on_chain_balance, database_balance, discrepancy,
+                   COALESCE(resolved, FALSE), resolved_at, resolution_note, timestamp
+            FROM reconciliation_logs
+            WHERE $1::BOOLEAN IS NULL OR COALESCE(resolved, FALSE) = $1
+            ORDER BY timestamp DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(resolved)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().filter_map(reconciliation_log).collect())
+    }
+
+    /// Marks a row resolved; `None` if no row has this id
+    pub async fn resolve_reconciliation_log(
+        &self,
+        id: uuid::Uuid,
+        note: Option<&str>,
+    ) -> Result<Option<ReconciliationLog>> {
+        let row = sqlx::query_as::<_, ReconciliationRow>(
+            r#"
+            UPDATE reconciliation_logs
+            SET resolved = TRUE, resolved_at = NOW(), resolution_note = $2
+            WHERE id = $1
+            RETURNING id, vault, check_type, on_chain_balance, database_balance, discrepancy,
+                      COALESCE(resolved, FALSE), resolved_at, resolution_note, timestamp
+            "#,
+        )
+        .bind(id)
+        .bind(note)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.and_then(reconciliation_log))
+    }
 }
 
+type ReconciliationRow = (
+    uuid::Uuid,
+    String,
+    String,
+    i64,
+    i64,
+    i64,
+    bool,
+    Option<chrono::DateTime<Utc>>,
+    Option<String>,
+    chrono::DateTime<Utc>,
+);
+
+// Rows with an unknown check type (written by a newer version) are skipped
+fn reconciliation_log(
+    (id, vault, check_type, on_chain_balance, database_balance, discrepancy, resolved, resolved_at, resolution_note, timestamp): ReconciliationRow,
+) -> Option<ReconciliationLog> {
+    Some(ReconciliationLog {
+        id,
+        vault,
+        check_type: ReconciliationCheck::parse(&check_type)?,
+        on_chain_balance,
+        database_balance,
+        discrepancy,
+        resolved,
+        resolved_at,
+        resolution_note,
+        timestamp,
+    })
+}
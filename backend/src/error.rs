@@ -28,6 +28,9 @@ pub enum Error {
     
     #[error("Unauthorized")]
     Unauthorized,
+
+    #[error("CALLER_PROGRAM_ID is not configured")]
+    CallerNotConfigured,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,124 @@
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status_client_types::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta, UiInstruction,
+};
+use std::str::FromStr;
+use crate::error::{Error, Result};
+
+// The program emits events with emit_cpi!, i.e. as a self-CPI whose data is
+// EVENT_IX_TAG_LE, the 8-byte event discriminator and the borsh-encoded event.
+// These live in inner instructions, which unlike log lines are never truncated.
+
+pub use collateral_vault::{
+    BatchEntry, BatchLockEvent, BatchUnlockEvent, DepositEvent, FeeCollectedEvent, LockEvent,
+    SettlementEntry, SettlementEvent, ShortfallCoveredEvent, SubAccountLockEvent,
+    SubAccountTransferEvent, SubAccountUnlockEvent, TransferEvent, UnlockEvent, VaultInitialized,
+    WithdrawEvent,
+};
+
+pub enum VaultEvent {
+    VaultInitialized(VaultInitialized),
+    Deposit(DepositEvent),
+    Withdraw(WithdrawEvent),
+    Lock(LockEvent),
+    Unlock(UnlockEvent),
+    Transfer(TransferEvent),
+    SubAccountTransfer(SubAccountTransferEvent),
+    SubAccountLock(SubAccountLockEvent),
+    SubAccountUnlock(SubAccountUnlockEvent),
+    BatchLock(BatchLockEvent),
+    BatchUnlock(BatchUnlockEvent),
+    Settlement(SettlementEvent),
+    FeeCollected(FeeCollectedEvent),
+    ShortfallCovered(ShortfallCoveredEvent),
+}
+
+/// An event together with the top-level instruction that produced it.
+pub struct DecodedEvent {
+    pub instruction_index: u8,
+    pub event: VaultEvent,
+}
+
+/// Decodes the data of a single emit_cpi! self-invocation. Returns `None` for
+/// instructions that are not vault events.
+pub fn decode_event_instruction(data: &[u8]) -> Option<VaultEvent> {
+    let payload = data.strip_prefix(anchor_lang::event::EVENT_IX_TAG_LE)?;
+
+    decode_event(payload).map(VaultEvent::VaultInitialized)
+        .or_else(|| decode_event(payload).map(VaultEvent::Deposit))
+        .or_else(|| decode_event(payload).map(VaultEvent::Withdraw))
+        .or_else(|| decode_event(payload).map(VaultEvent::Lock))
+        .or_else(|| decode_event(payload).map(VaultEvent::Unlock))
+        .or_else(|| decode_event(payload).map(VaultEvent::Transfer))
+        .or_else(|| decode_event(payload).map(VaultEvent::SubAccountTransfer))
+        .or_else(|| decode_event(payload).map(VaultEvent::SubAccountLock))
+        .or_else(|| decode_event(payload).map(VaultEvent::SubAccountUnlock))
+        .or_else(|| decode_event(payload).map(VaultEvent::BatchLock))
+        .or_else(|| decode_event(payload).map(VaultEvent::BatchUnlock))
+        .or_else(|| decode_event(payload).map(VaultEvent::Settlement))
+        .or_else(|| decode_event(payload).map(VaultEvent::FeeCollected))
+        .or_else(|| decode_event(payload).map(VaultEvent::ShortfallCovered))
+}
+
+// The event, if `payload` starts with `T`'s discriminator
+fn decode_event<T: Discriminator + AnchorDeserialize>(payload: &[u8]) -> Option<T> {
+    let mut body = payload.strip_prefix(T::DISCRIMINATOR)?;
+    T::deserialize(&mut body).ok()
+}
+
+/// Extracts every vault event from the inner instructions of a transaction
+/// fetched with a binary (base58/base64) encoding. Failed transactions yield
+/// no events.
+pub fn decode_transaction_events(
+    transaction: &EncodedConfirmedTransactionWithStatusMeta,
+    program_id: &Pubkey,
+) -> Result<Vec<DecodedEvent>> {
+    let meta = transaction.transaction.meta.as_ref()
+        .ok_or_else(|| Error::SolanaClient("Transaction has no status meta".to_string()))?;
+
+    if meta.err.is_some() {
+        return Ok(vec![]);
+    }
+
+    let versioned = transaction.transaction.transaction.decode()
+        .ok_or_else(|| Error::SolanaClient("Transaction must be fetched with a binary encoding".to_string()))?;
+
+    // Inner instructions index into static keys followed by loaded addresses
+    let mut account_keys = versioned.message.static_account_keys().to_vec();
+    if let OptionSerializer::Some(loaded) = &meta.loaded_addresses {
+        for key in loaded.writable.iter().chain(loaded.readonly.iter()) {
+            account_keys.push(Pubkey::from_str(key)
+                .map_err(|e| Error::SolanaClient(format!("Invalid loaded address: {}", e)))?);
+        }
+    }
+
+    let inner_instructions = match &meta.inner_instructions {
+        OptionSerializer::Some(inner) => inner,
+        _ => return Ok(vec![]),
+    };
+
+    let mut events = vec![];
+    for inner in inner_instructions {
+        for instruction in &inner.instructions {
+            let UiInstruction::Compiled(compiled) = instruction else {
+                continue;
+            };
+            if account_keys.get(compiled.program_id_index as usize) != Some(program_id) {
+                continue;
+            }
+
+            let data = bs58::decode(&compiled.data).into_vec()
+                .map_err(|e| Error::SolanaClient(format!("Invalid instruction data: {}", e)))?;
+
+            if let Some(event) = decode_event_instruction(&data) {
+                events.push(DecodedEvent {
+                    instruction_index: inner.index,
+                    event,
+                });
+            }
+        }
+    }
+
+    Ok(events)
+}
@@ -0,0 +1,512 @@
+use crate::database::Database;
+use crate::error::{Error, Result};
+use crate::events::{decode_transaction_events, DecodedEvent, VaultEvent};
+use crate::log_subscriber::LogSignal;
+use crate::models::{IndexedEvent, SettlementActivity, StreamEvent, TransactionType, VaultActivity};
+use crate::websocket::WebSocketManager;
+use solana_client::rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_transaction_status_client_types::UiTransactionEncoding;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+
+const CURSOR_NAME: &str = "event_indexer";
+// Maximum page size of getSignaturesForAddress
+const SIGNATURE_PAGE_SIZE: usize = 1000;
+
+/// Walks the program's confirmed transactions oldest-first and stores their
+/// vault events, so transactions submitted straight from a wallet reach the
+/// database too. Progress is persisted after every transaction.
+pub struct EventIndexer {
+    rpc_client: Arc<RpcClient>,
+    database: Database,
+    program_id: Pubkey,
+    poll_interval: Duration,
+    websocket: Option<WebSocketManager>,
+}
+
+impl EventIndexer {
+    pub fn new(database: Database, program_id: Pubkey, poll_interval: Duration) -> Self {
+        let rpc_url = std::env::var("RPC_URL")
+            .unwrap_or_else(|_| "http://localhost:8899".to_string());
+
+        Self {
+            rpc_client: Arc::new(RpcClient::new_with_commitment(rpc_url, CommitmentConfig::finalized())),
+            database,
+            program_id,
+            poll_interval,
+            websocket: None,
+        }
+    }
+
+    /// Pushes every newly stored event to `/ws` subscribers.
+    pub fn with_websocket(mut self, websocket: WebSocketManager) -> Self {
+        self.websocket = Some(websocket);
+        self
+    }
+
+    pub async fn run(&self) {
+        let mut interval = interval(self.poll_interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = self.index_new_transactions().await {
+                tracing::warn!("Event indexing failed: {}", e);
+            }
+        }
+    }
+
+    /// Like `run`, but also indexes transactions reported by a
+    /// `LogSubscriber` as they land and backfills from the cursor on every
+    /// (re)subscription. Streamed transactions are confirmed rather than
+    /// finalized, so they never move the cursor; the polling backfill does.
+    pub async fn run_with_logs(&self, mut signals: mpsc::Receiver<LogSignal>) {
+        let mut interval = interval(self.poll_interval);
+
+        loop {
+            let result = tokio::select! {
+                _ = interval.tick() => self.index_new_transactions().await,
+                signal = signals.recv() => match signal {
+                    Some(LogSignal::Subscribed) => self.index_new_transactions().await,
+                    Some(LogSignal::Transaction { signature, .. }) => {
+                        self.index_transaction(&signature, CommitmentConfig::confirmed()).await
+                    }
+                    None => return self.run().await,
+                },
+            };
+
+            if let Err(e) = result {
+                tracing::warn!("Event indexing failed: {}", e);
+            }
+        }
+    }
+
+    /// Indexes every transaction after the persisted cursor and returns the
+    /// number of new events stored.
+    pub async fn index_new_transactions(&self) -> Result<usize> {
+        let until = match self.database.get_cursor(CURSOR_NAME).await? {
+            Some(signature) => Some(Signature::from_str(&signature)
+                .map_err(|e| Error::InvalidAccount(format!("Invalid cursor signature: {}", e)))?),
+            None => None,
+        };
+
+        // getSignaturesForAddress returns newest first; page back to the
+        // cursor, then process in chronological order
+        let mut pending = Vec::new();
+        let mut before = None;
+        loop {
+            let rpc_client = self.rpc_client.clone();
+            let program_id = self.program_id;
+            let config = GetConfirmedSignaturesForAddress2Config {
+                before,
+                until,
+                limit: Some(SIGNATURE_PAGE_SIZE),
+                commitment: Some(CommitmentConfig::finalized()),
+            };
+            let page = tokio::task::spawn_blocking(move || {
+                rpc_client.get_signatures_for_address_with_config(&program_id, config)
+                    .map_err(|e| Error::SolanaClient(format!("Failed to fetch signatures: {}", e)))
+            })
+            .await
+            .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+            let Some(oldest) = page.last() else {
+                break;
+            };
+            before = Some(Signature::from_str(&oldest.signature)
+                .map_err(|e| Error::SolanaClient(format!("Invalid signature: {}", e)))?);
+
+            let full_page = page.len() == SIGNATURE_PAGE_SIZE;
+            pending.extend(page);
+            if !full_page {
+                break;
+            }
+        }
+
+        let mut stored = 0;
+        for status in pending.into_iter().rev() {
+            if status.err.is_none() {
+                stored += self.index_transaction(&status.signature, CommitmentConfig::finalized()).await?;
+            }
+            self.database.set_cursor(CURSOR_NAME, &status.signature, status.slot).await?;
+        }
+
+        Ok(stored)
+    }
+
+    async fn index_transaction(&self, signature: &str, commitment: CommitmentConfig) -> Result<usize> {
+        let parsed = Signature::from_str(signature)
+            .map_err(|e| Error::SolanaClient(format!("Invalid signature: {}", e)))?;
+
+        let rpc_client = self.rpc_client.clone();
+        let transaction = tokio::task::spawn_blocking(move || {
+            rpc_client.get_transaction_with_config(&parsed, RpcTransactionConfig {
+                encoding: Some(UiTransactionEncoding::Base64),
+                commitment: Some(commitment),
+                max_supported_transaction_version: Some(0),
+            })
+            .map_err(|e| Error::SolanaClient(format!("Failed to fetch transaction {}: {}", parsed, e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let slot = transaction.slot;
+        let block_time = transaction.block_time
+            .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0));
+        let events = decode_transaction_events(&transaction, &self.program_id)?;
+
+        let mut stored = 0;
+        let mut event_index = 0u8;
+        let mut previous_instruction = None;
+        for decoded in events {
+            if previous_instruction != Some(decoded.instruction_index) {
+                event_index = 0;
+                previous_instruction = Some(decoded.instruction_index);
+            }
+
+            let mut inserted = false;
+            for (event, ledger) in indexed_events(signature, slot, block_time, event_index, &decoded) {
+                if self.database.insert_indexed_event(&event, ledger).await? {
+                    stored += 1;
+                    inserted = true;
+                }
+            }
+
+            // Only first inserts are pushed, so a transaction seen both
+            // streamed and finalized is announced once
+            if let (true, Some(websocket)) = (inserted, &self.websocket) {
+                let vaults = entry_vaults(&decoded.event);
+                let owners = if vaults.is_empty() {
+                    HashMap::new()
+                } else {
+                    self.database.get_vault_owners(&vaults).await.unwrap_or_else(|e| {
+                        tracing::warn!("Failed to look up vault owners for {}: {}", signature, e);
+                        HashMap::new()
+                    })
+                };
+                for event in stream_events(signature, slot, &decoded, &owners) {
+                    websocket.broadcast(event);
+                }
+            }
+            event_index = event_index.saturating_add(1);
+        }
+
+        Ok(stored)
+    }
+}
+
+// Maps a vault event to one stored row per vault it touches, each with the
+// `transactions` row it implies. Entries are bounded by the accounts a
+// transaction can name, so their positions fit in a u8.
+fn indexed_events(
+    signature: &str,
+    slot: u64,
+    block_time: Option<chrono::DateTime<chrono::Utc>>,
+    event_index: u8,
+    decoded: &DecodedEvent,
+) -> Vec<(IndexedEvent, Option<TransactionType>)> {
+    let row = |entry_index: usize, event_type: &str, vault: Pubkey, counterparty_vault: Option<Pubkey>, amount, sequence| {
+        IndexedEvent {
+            signature: signature.to_string(),
+            instruction_index: decoded.instruction_index,
+            event_index,
+            entry_index: entry_index as u8,
+            slot,
+            block_time,
+            event_type: event_type.to_string(),
+            vault: vault.to_string(),
+            counterparty_vault: counterparty_vault.map(|vault| vault.to_string()),
+            amount,
+            sequence,
+        }
+    };
+
+    match &decoded.event {
+        VaultEvent::VaultInitialized(e) => vec![(row(0, "vault_initialized", e.vault, None, 0, e.sequence), None)],
+        VaultEvent::Deposit(e) => vec![(
+            row(0, "deposit", e.vault, None, e.amount, e.sequence),
+            Some(TransactionType::Deposit),
+        )],
+        VaultEvent::Withdraw(e) => vec![(
+            row(0, "withdraw", e.vault, None, e.amount, e.sequence),
+            Some(TransactionType::Withdrawal),
+        )],
+        VaultEvent::Lock(e) => vec![(
+            row(0, "lock", e.vault, None, e.amount, e.sequence),
+            Some(TransactionType::Lock),
+        )],
+        VaultEvent::Unlock(e) => vec![(
+            row(0, "unlock", e.vault, None, e.amount, e.sequence),
+            Some(TransactionType::Unlock),
+        )],
+        VaultEvent::Transfer(e) => vec![
+            (
+                row(0, "transfer", e.from_vault, Some(e.to_vault), e.amount, e.from_sequence),
+                Some(TransactionType::Transfer),
+            ),
+            (
+                row(1, "transfer_in", e.to_vault, Some(e.from_vault), e.amount, e.to_sequence),
+                Some(TransactionType::TransferIn),
+            ),
+        ],
+        // Sub-account moves stay inside the vault's total balance
+        VaultEvent::SubAccountTransfer(e) => {
+            vec![(row(0, "sub_account_transfer", e.vault, None, e.amount, e.sequence), None)]
+        }
+        VaultEvent::SubAccountLock(e) => {
+            vec![(row(0, "sub_account_lock", e.vault, None, e.amount, e.sequence), None)]
+        }
+        VaultEvent::SubAccountUnlock(e) => {
+            vec![(row(0, "sub_account_unlock", e.vault, None, e.amount, e.sequence), None)]
+        }
+        VaultEvent::BatchLock(e) => e.entries.iter().enumerate()
+            .map(|(index, entry)| (
+                row(index, "batch_lock", entry.vault, None, entry.amount, entry.sequence),
+                Some(TransactionType::Lock),
+            ))
+            .collect(),
+        VaultEvent::BatchUnlock(e) => e.entries.iter().enumerate()
+            .map(|(index, entry)| (
+                row(index, "batch_unlock", entry.vault, None, entry.amount, entry.sequence),
+                Some(TransactionType::Unlock),
+            ))
+            .collect(),
+        VaultEvent::Settlement(e) => e.entries.iter().enumerate()
+            .map(|(index, entry)| {
+                let (event_type, transaction_type) = if entry.delta < 0 {
+                    ("settlement", TransactionType::Settlement)
+                } else {
+                    ("settlement_in", TransactionType::SettlementIn)
+                };
+                (
+                    row(index, event_type, entry.vault, None, entry.delta.unsigned_abs(), entry.sequence),
+                    Some(transaction_type),
+                )
+            })
+            .collect(),
+        VaultEvent::FeeCollected(e) => vec![(
+            row(0, "fee_collected", e.vault, None, e.amount, e.sequence),
+            Some(TransactionType::Fee),
+        )],
+        VaultEvent::ShortfallCovered(e) => vec![(
+            row(0, "shortfall_covered", e.vault, None, e.amount, e.sequence),
+            Some(TransactionType::ShortfallCover),
+        )],
+    }
+}
+
+// Vaults of a multi-vault event, whose owners the event does not name
+fn entry_vaults(event: &VaultEvent) -> Vec<String> {
+    match event {
+        VaultEvent::BatchLock(e) => e.entries.iter().map(|entry| entry.vault.to_string()).collect(),
+        VaultEvent::BatchUnlock(e) => e.entries.iter().map(|entry| entry.vault.to_string()).collect(),
+        VaultEvent::Settlement(e) => e.entries.iter().map(|entry| entry.vault.to_string()).collect(),
+        _ => vec![],
+    }
+}
+
+// The `/ws` form of an event, one message per vault for batch and settlement
+// events. `owners` maps the vaults of those events to their owners.
+fn stream_events(
+    signature: &str,
+    slot: u64,
+    decoded: &DecodedEvent,
+    owners: &HashMap<String, String>,
+) -> Vec<StreamEvent> {
+    let activity = |vault: Pubkey, owner: Option<String>, amount, sequence, timestamp| VaultActivity {
+        signature: signature.to_string(),
+        slot,
+        vault: vault.to_string(),
+        owner,
+        counterparty_vault: None,
+        counterparty_owner: None,
+        amount,
+        sequence,
+        timestamp,
+    };
+    let owner_of = |vault: &Pubkey| owners.get(&vault.to_string()).cloned();
+
+    match &decoded.event {
+        VaultEvent::Deposit(e) => vec![StreamEvent::Deposit(
+            activity(e.vault, Some(e.user.to_string()), e.amount, e.sequence, e.timestamp),
+        )],
+        VaultEvent::Withdraw(e) => vec![StreamEvent::Withdrawal(
+            activity(e.vault, Some(e.user.to_string()), e.amount, e.sequence, e.timestamp),
+        )],
+        VaultEvent::Lock(e) => vec![StreamEvent::Lock(
+            activity(e.vault, Some(e.user.to_string()), e.amount, e.sequence, e.timestamp),
+        )],
+        VaultEvent::Unlock(e) => vec![StreamEvent::Unlock(
+            activity(e.vault, Some(e.user.to_string()), e.amount, e.sequence, e.timestamp),
+        )],
+        VaultEvent::Transfer(e) => vec![StreamEvent::Transfer(VaultActivity {
+            counterparty_vault: Some(e.to_vault.to_string()),
+            counterparty_owner: Some(e.to_user.to_string()),
+            ..activity(e.from_vault, Some(e.from_user.to_string()), e.amount, e.from_sequence, e.timestamp)
+        })],
+        VaultEvent::BatchLock(e) => e.entries.iter()
+            .map(|entry| StreamEvent::Lock(
+                activity(entry.vault, owner_of(&entry.vault), entry.amount, entry.sequence, e.timestamp),
+            ))
+            .collect(),
+        VaultEvent::BatchUnlock(e) => e.entries.iter()
+            .map(|entry| StreamEvent::Unlock(
+                activity(entry.vault, owner_of(&entry.vault), entry.amount, entry.sequence, e.timestamp),
+            ))
+            .collect(),
+        VaultEvent::Settlement(e) => e.entries.iter()
+            .map(|entry| StreamEvent::Settlement(SettlementActivity {
+                activity: activity(
+                    entry.vault,
+                    owner_of(&entry.vault),
+                    entry.delta.unsigned_abs(),
+                    entry.sequence,
+                    e.timestamp,
+                ),
+                delta: entry.delta,
+            }))
+            .collect(),
+        VaultEvent::FeeCollected(e) => vec![StreamEvent::Fee(
+            activity(e.vault, Some(e.user.to_string()), e.amount, e.sequence, e.timestamp),
+        )],
+        VaultEvent::ShortfallCovered(e) => vec![StreamEvent::ShortfallCover(
+            activity(e.vault, Some(e.user.to_string()), e.amount, e.sequence, e.timestamp),
+        )],
+        VaultEvent::VaultInitialized(_)
+        | VaultEvent::SubAccountTransfer(_)
+        | VaultEvent::SubAccountLock(_)
+        | VaultEvent::SubAccountUnlock(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{
+        BatchEntry, BatchLockEvent, BatchUnlockEvent, DepositEvent, FeeCollectedEvent, SettlementEntry,
+        SettlementEvent, ShortfallCoveredEvent, SubAccountTransferEvent, TransferEvent,
+    };
+
+    fn batch_entry(vault: Pubkey, amount: u64, locked_balance: u64, sequence: u64) -> BatchEntry {
+        BatchEntry { vault, amount, locked_balance, sequence }
+    }
+
+    fn settlement_entry(vault: Pubkey, delta: i64, total_balance: u64, sequence: u64) -> SettlementEntry {
+        SettlementEntry { vault, delta, total_balance, sequence }
+    }
+
+    // Two vaults through every balance-changing event kind, ending at 650
+    // and 825 on chain
+    fn history(alice: Pubkey, alice_vault: Pubkey, bob: Pubkey, bob_vault: Pubkey) -> Vec<VaultEvent> {
+        let caller_program = Pubkey::new_unique();
+        vec![
+            VaultEvent::Deposit(DepositEvent {
+                user: alice, vault: alice_vault, amount: 1000, new_balance: 1000, sequence: 1, timestamp: 0,
+            }),
+            VaultEvent::Deposit(DepositEvent {
+                user: bob, vault: bob_vault, amount: 500, new_balance: 500, sequence: 1, timestamp: 0,
+            }),
+            VaultEvent::Transfer(TransferEvent {
+                from_user: alice, to_user: bob, from_vault: alice_vault, to_vault: bob_vault,
+                amount: 100, from_sequence: 2, to_sequence: 2, timestamp: 0,
+            }),
+            VaultEvent::BatchLock(BatchLockEvent {
+                caller_program,
+                total_amount: 500,
+                entries: vec![batch_entry(alice_vault, 300, 300, 3), batch_entry(bob_vault, 200, 200, 3)],
+                timestamp: 0,
+            }),
+            VaultEvent::Settlement(SettlementEvent {
+                caller_program,
+                mint: Pubkey::new_unique(),
+                entries: vec![settlement_entry(alice_vault, -200, 700, 4), settlement_entry(bob_vault, 200, 800, 4)],
+                transfer_count: 1,
+                timestamp: 0,
+            }),
+            VaultEvent::BatchUnlock(BatchUnlockEvent {
+                caller_program,
+                total_amount: 500,
+                entries: vec![batch_entry(alice_vault, 300, 0, 5), batch_entry(bob_vault, 200, 0, 5)],
+                timestamp: 0,
+            }),
+            VaultEvent::FeeCollected(FeeCollectedEvent {
+                user: alice, vault: alice_vault, amount: 50, insurance_share: 10, fund_balance: 10,
+                sequence: 6, timestamp: 0,
+            }),
+            VaultEvent::ShortfallCovered(ShortfallCoveredEvent {
+                user: bob, vault: bob_vault, amount: 25, fund_balance: 0, sequence: 6, timestamp: 0,
+            }),
+            VaultEvent::SubAccountTransfer(SubAccountTransferEvent {
+                user: alice, vault: alice_vault, from_index: None, to_index: Some(0), amount: 100,
+                sequence: 7, timestamp: 0,
+            }),
+        ]
+    }
+
+    #[test]
+    fn ledger_rows_reconcile_with_on_chain_balances() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (alice_vault, bob_vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut rows = Vec::new();
+        for (instruction_index, event) in history(alice, alice_vault, bob, bob_vault).into_iter().enumerate() {
+            let decoded = DecodedEvent { instruction_index: instruction_index as u8, event };
+            rows.extend(indexed_events("sig", 1, None, 0, &decoded));
+        }
+
+        let mut keys: Vec<_> = rows.iter()
+            .map(|(event, _)| (event.instruction_index, event.event_index, event.entry_index))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), rows.len(), "every row needs its own key");
+
+        // What get_ledger_balance sums
+        let ledger_balance = |vault: Pubkey| -> i64 {
+            rows.iter()
+                .filter(|(event, _)| event.vault == vault.to_string())
+                .filter_map(|(event, ledger)| ledger.map(|t| t.ledger_sign() * event.amount as i64))
+                .sum()
+        };
+        assert_eq!(ledger_balance(alice_vault), 650);
+        assert_eq!(ledger_balance(bob_vault), 825);
+
+        // Every sequence step of both vaults is stored
+        for (vault, last) in [(alice_vault, 7), (bob_vault, 6)] {
+            let mut sequences: Vec<u64> = rows.iter()
+                .filter(|(event, _)| event.vault == vault.to_string())
+                .map(|(event, _)| event.sequence)
+                .collect();
+            sequences.sort_unstable();
+            assert_eq!(sequences, (1..=last).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn streams_one_message_per_settlement_entry() {
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (alice_vault, bob_vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let settlement = history(alice, alice_vault, bob, bob_vault).remove(4);
+        let decoded = DecodedEvent { instruction_index: 0, event: settlement };
+        // Bob's vault is not in the `vaults` table yet
+        let owners = HashMap::from([(alice_vault.to_string(), alice.to_string())]);
+
+        let events = stream_events("sig", 1, &decoded, &owners);
+        let [StreamEvent::Settlement(paid), StreamEvent::Settlement(received)] = events.as_slice() else {
+            panic!("expected two settlement messages, got {:?}", events);
+        };
+        assert_eq!(paid.activity.vault, alice_vault.to_string());
+        assert_eq!(paid.activity.owner, Some(alice.to_string()));
+        assert_eq!((paid.activity.amount, paid.delta), (200, -200));
+        assert_eq!(received.activity.vault, bob_vault.to_string());
+        assert_eq!(received.activity.owner, None);
+        assert_eq!((received.activity.amount, received.delta), (200, 200));
+    }
+}
@@ -1,10 +1,14 @@
 pub mod vault_manager;
 pub mod balance_tracker;
 pub mod transaction_builder;
+pub mod compute_budget;
 pub mod cpi_manager;
 pub mod vault_monitor;
 pub mod api;
 pub mod websocket;
+pub mod events;
+pub mod indexer;
+pub mod log_subscriber;
 pub mod database;
 pub mod models;
 pub mod error;
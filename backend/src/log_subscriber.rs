@@ -0,0 +1,299 @@
+use crate::error::{Error, Result};
+use futures_util::StreamExt;
+use solana_client::nonblocking::pubsub_client::PubsubClient;
+use solana_client::rpc_config::{RpcTransactionLogsConfig, RpcTransactionLogsFilter};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+
+/// What the log subscription reports to the indexer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogSignal {
+    /// A (re)subscription is live; transactions since the last indexed
+    /// signature must be backfilled to close any gap
+    Subscribed,
+    /// A successful transaction that mentions the program
+    Transaction { signature: String, slot: u64 },
+}
+
+/// `logsSubscribe` source for the program's transactions. Reconnects with
+/// exponential backoff whenever the socket drops.
+pub struct LogSubscriber {
+    ws_url: String,
+    program_id: Pubkey,
+    commitment: CommitmentConfig,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl LogSubscriber {
+    pub fn new(ws_url: String, program_id: Pubkey) -> Self {
+        Self {
+            ws_url,
+            program_id,
+            commitment: CommitmentConfig::confirmed(),
+            min_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    pub fn with_backoff(mut self, min_backoff: Duration, max_backoff: Duration) -> Self {
+        self.min_backoff = min_backoff;
+        self.max_backoff = max_backoff.max(min_backoff);
+        self
+    }
+
+    /// Streams signals until `signals` is closed.
+    pub async fn run(&self, signals: mpsc::Sender<LogSignal>) {
+        let mut backoff = self.min_backoff;
+
+        loop {
+            match self.stream(&signals).await {
+                Ok(()) => {
+                    tracing::warn!("Log subscription to {} closed", self.ws_url);
+                    backoff = self.min_backoff;
+                }
+                Err(e) => tracing::warn!("Log subscription failed: {}", e),
+            }
+
+            if signals.is_closed() {
+                return;
+            }
+
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    // One connection: subscribe, ask for a backfill, then forward
+    // notifications until the socket or the receiver closes. Subscribing
+    // before the backfill means nothing landing in between is missed.
+    async fn stream(&self, signals: &mpsc::Sender<LogSignal>) -> Result<()> {
+        let client = PubsubClient::new(&self.ws_url)
+            .await
+            .map_err(|e| Error::SolanaClient(format!("Failed to connect to {}: {}", self.ws_url, e)))?;
+
+        let (mut notifications, _unsubscribe) = client
+            .logs_subscribe(
+                RpcTransactionLogsFilter::Mentions(vec![self.program_id.to_string()]),
+                RpcTransactionLogsConfig {
+                    commitment: Some(self.commitment),
+                },
+            )
+            .await
+            .map_err(|e| Error::SolanaClient(format!("Failed to subscribe to logs: {}", e)))?;
+
+        if signals.send(LogSignal::Subscribed).await.is_err() {
+            return Ok(());
+        }
+
+        loop {
+            let response = tokio::select! {
+                _ = signals.closed() => break,
+                response = notifications.next() => match response {
+                    Some(response) => response,
+                    None => break,
+                },
+            };
+            if response.value.err.is_some() {
+                continue;
+            }
+
+            let signal = LogSignal::Transaction {
+                signature: response.value.signature,
+                slot: response.context.slot,
+            };
+            if signals.send(signal).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+    use axum::routing::get;
+    use axum::Router;
+    use serde_json::{json, Value};
+    use solana_sdk::signature::Signature;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::time::timeout;
+
+    enum Session {
+        // Answer the subscription, send these notifications, then hang up
+        Notify(Vec<Value>),
+        // Hang up before answering the subscription
+        Reject,
+    }
+
+    struct MockServer {
+        url: String,
+        connections: Arc<AtomicUsize>,
+    }
+
+    // Serves the scripted sessions in order, one per connection; later
+    // connections subscribe and then stay idle
+    async fn mock_pubsub_server(program_id: Pubkey, sessions: Vec<Session>) -> MockServer {
+        let sessions = Arc::new(tokio::sync::Mutex::new(sessions.into_iter()));
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let app = Router::new().route(
+            "/",
+            get({
+                let connections = connections.clone();
+                move |ws: WebSocketUpgrade| async move {
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    let session = sessions.lock().await.next();
+                    ws.on_upgrade(move |socket| serve_session(socket, program_id, session))
+                }
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        MockServer { url, connections }
+    }
+
+    async fn serve_session(mut socket: WebSocket, program_id: Pubkey, session: Option<Session>) {
+        let request: Value = loop {
+            match socket.recv().await {
+                Some(Ok(Message::Text(text))) => break serde_json::from_str(&text).unwrap(),
+                Some(Ok(_)) => continue,
+                _ => return,
+            }
+        };
+        assert_eq!(request["method"], "logsSubscribe");
+        assert_eq!(request["params"][0], json!({ "mentions": [program_id.to_string()] }));
+        assert_eq!(request["params"][1]["commitment"], "confirmed");
+
+        let notifications = match session {
+            Some(Session::Reject) => return,
+            Some(Session::Notify(notifications)) => Some(notifications),
+            None => None,
+        };
+
+        let subscription = 7;
+        let reply = json!({ "jsonrpc": "2.0", "result": subscription, "id": request["id"] });
+        socket.send(Message::Text(reply.to_string())).await.unwrap();
+
+        let Some(notifications) = notifications else {
+            // Idle until the client goes away
+            while let Some(Ok(_)) = socket.recv().await {}
+            return;
+        };
+
+        for mut notification in notifications {
+            notification["params"]["subscription"] = json!(subscription);
+            socket.send(Message::Text(notification.to_string())).await.unwrap();
+        }
+        let _ = socket.send(Message::Close(None)).await;
+    }
+
+    fn logs_notification(signature: &str, slot: u64, err: Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "logsNotification",
+            "params": {
+                "result": {
+                    "context": { "slot": slot },
+                    "value": { "signature": signature, "err": err, "logs": [] }
+                }
+            }
+        })
+    }
+
+    fn transaction(signature: &str, slot: u64) -> LogSignal {
+        LogSignal::Transaction { signature: signature.to_string(), slot }
+    }
+
+    async fn next_signal(signals: &mut mpsc::Receiver<LogSignal>) -> LogSignal {
+        timeout(Duration::from_secs(10), signals.recv())
+            .await
+            .expect("timed out waiting for a log signal")
+            .expect("subscriber stopped")
+    }
+
+    fn subscriber(url: &str, program_id: Pubkey) -> LogSubscriber {
+        LogSubscriber::new(url.to_string(), program_id)
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(50))
+    }
+
+    #[tokio::test]
+    async fn forwards_successful_transactions_and_backfills_after_reconnect() {
+        let program_id = Pubkey::new_unique();
+        let first = Signature::new_unique().to_string();
+        let failed = Signature::new_unique().to_string();
+        let second = Signature::new_unique().to_string();
+
+        let server = mock_pubsub_server(program_id, vec![
+            Session::Notify(vec![
+                logs_notification(&first, 10, Value::Null),
+                logs_notification(&failed, 11, json!({ "InstructionError": [0, { "Custom": 6001 }] })),
+            ]),
+            Session::Notify(vec![logs_notification(&second, 12, Value::Null)]),
+        ])
+        .await;
+
+        let (sender, mut signals) = mpsc::channel(16);
+        let subscriber = subscriber(&server.url, program_id);
+        tokio::spawn(async move { subscriber.run(sender).await });
+
+        assert_eq!(next_signal(&mut signals).await, LogSignal::Subscribed);
+        assert_eq!(next_signal(&mut signals).await, transaction(&first, 10));
+        // The failed transaction is skipped and the dropped socket triggers a
+        // resubscription, which asks for a backfill before new notifications
+        assert_eq!(next_signal(&mut signals).await, LogSignal::Subscribed);
+        assert_eq!(next_signal(&mut signals).await, transaction(&second, 12));
+        assert_eq!(next_signal(&mut signals).await, LogSignal::Subscribed);
+        assert_eq!(server.connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_when_the_subscription_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let signature = Signature::new_unique().to_string();
+
+        let server = mock_pubsub_server(program_id, vec![
+            Session::Reject,
+            Session::Reject,
+            Session::Notify(vec![logs_notification(&signature, 42, Value::Null)]),
+        ])
+        .await;
+
+        let (sender, mut signals) = mpsc::channel(16);
+        let subscriber = subscriber(&server.url, program_id);
+        tokio::spawn(async move { subscriber.run(sender).await });
+
+        assert_eq!(next_signal(&mut signals).await, LogSignal::Subscribed);
+        assert_eq!(server.connections.load(Ordering::SeqCst), 3);
+        assert_eq!(next_signal(&mut signals).await, transaction(&signature, 42));
+    }
+
+    #[tokio::test]
+    async fn stops_when_the_receiver_is_dropped() {
+        let program_id = Pubkey::new_unique();
+        let server = mock_pubsub_server(program_id, vec![]).await;
+
+        let (sender, mut signals) = mpsc::channel(16);
+        let subscriber = subscriber(&server.url, program_id);
+        let handle = tokio::spawn(async move { subscriber.run(sender).await });
+
+        assert_eq!(next_signal(&mut signals).await, LogSignal::Subscribed);
+        drop(signals);
+
+        // The session is idle, so only the closed receiver can end the run
+        timeout(Duration::from_secs(10), handle)
+            .await
+            .expect("subscriber kept running")
+            .unwrap();
+        assert_eq!(server.connections.load(Ordering::SeqCst), 1);
+    }
+}
@@ -6,6 +6,11 @@ use std::str::FromStr;
 use backend::vault_manager::VaultManager;
 use backend::database::Database;
 use backend::vault_monitor::VaultMonitor;
+use backend::cpi_manager::CPIManager;
+use backend::balance_tracker::BalanceTracker;
+use backend::indexer::EventIndexer;
+use backend::log_subscriber::LogSubscriber;
+use backend::websocket::WebSocketManager;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -51,13 +56,89 @@ async fn main() -> Result<()> {
         mint,
     ));
     
-    let vault_monitor = Arc::new(VaultMonitor::new(Database::new(pool.clone())));
+    // Shared by the indexer and monitor, which publish, and the /ws route
+    let websocket = WebSocketManager::new();
+
+    let snapshot_interval = std::env::var("SNAPSHOT_INTERVAL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(300);
+    // Also read by /vault/tvl to cross-check the on-chain counters
+    let vault_monitor = Arc::new(VaultMonitor::new(
+        Database::new(pool.clone()),
+        program_id,
+        std::time::Duration::from_secs(snapshot_interval),
+    ).with_websocket(websocket.clone()));
+    let monitor = vault_monitor.clone();
+    tokio::spawn(async move { monitor.monitor_vaults().await });
+
+    let indexer_interval = std::env::var("INDEXER_POLL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(10);
+    let event_indexer = EventIndexer::new(
+        Database::new(pool.clone()),
+        program_id,
+        std::time::Duration::from_secs(indexer_interval),
+    ).with_websocket(websocket.clone());
+    // With WS_URL set, logsSubscribe notifications are indexed as they land
+    // and polling only closes gaps
+    match std::env::var("WS_URL") {
+        Ok(ws_url) => {
+            let (signals, receiver) = tokio::sync::mpsc::channel(1024);
+            let log_subscriber = LogSubscriber::new(ws_url, program_id);
+            tokio::spawn(async move { log_subscriber.run(signals).await });
+            tokio::spawn(async move { event_indexer.run_with_logs(receiver).await });
+        }
+        Err(_) => {
+            tokio::spawn(async move { event_indexer.run().await });
+        }
+    }
+
+    let balance_tracker = Arc::new(BalanceTracker::new(Database::new(pool.clone()), program_id));
+    tokio::spawn(async move { balance_tracker.start_monitoring().await });
+
+    // Integrating program /cpi/* calls through its keeper instructions; must
+    // be in the on-chain VaultAuthority's authorized_programs. The routes are
+    // disabled when unset.
+    let caller_program = match std::env::var("CALLER_PROGRAM_ID") {
+        Ok(caller) => Some(
+            Pubkey::from_str(&caller)
+                .map_err(|e| Error::InvalidAccount(format!("Invalid caller program: {}", e)))?,
+        ),
+        Err(_) => {
+            tracing::warn!("CALLER_PROGRAM_ID is not set; /cpi routes are disabled");
+            None
+        }
+    };
+    let authorized_programs = match std::env::var("AUTHORIZED_PROGRAMS") {
+        Ok(programs) => programs
+            .split(',')
+            .map(|program| Pubkey::from_str(program.trim()))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidAccount(format!("Invalid authorized program: {}", e)))?,
+        Err(_) => caller_program.into_iter().collect(),
+    };
+
+    let cpi_manager = Arc::new(CPIManager::new(
+        program_id,
+        caller_program,
+        authorized_programs,
+        Database::new(pool.clone()),
+        mint,
+    )?);
 
     // Create API router
     let app = api::create_router(
         vault_manager,
-        vault_monitor,
         Arc::new(Database::new(pool_clone)),
+        cpi_manager,
+        vault_monitor,
+        websocket,
+        std::env::var("CPI_API_KEY").ok(),
+        std::env::var("ADMIN_API_KEY").ok(),
     );
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await
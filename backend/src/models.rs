@@ -12,6 +12,8 @@ pub struct VaultInfo {
     pub total_deposited: u64,
     pub total_withdrawn: u64,
     pub created_at: i64,
+    pub sequence: u64,
+    pub sub_account_balance: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,7 +33,61 @@ pub enum TransactionType {
     Withdrawal,
     Lock,
     Unlock,
+    /// Sending side of a vault-to-vault transfer
     Transfer,
+    /// Receiving side of a vault-to-vault transfer
+    #[sqlx(rename = "transfer_in")]
+    TransferIn,
+    /// Paying side of a `settle_batch`
+    Settlement,
+    /// Receiving side of a `settle_batch`
+    #[sqlx(rename = "settlement_in")]
+    SettlementIn,
+    /// Fee charged through `collect_fee`
+    Fee,
+    /// Insurance fund payout through `cover_shortfall`
+    #[sqlx(rename = "shortfall_cover")]
+    ShortfallCover,
+}
+
+impl TransactionType {
+    /// Direction in which a row of this type moves the vault's
+    /// `total_balance`; locks and unlocks only move it between books.
+    pub fn ledger_sign(self) -> i64 {
+        match self {
+            TransactionType::Deposit
+            | TransactionType::TransferIn
+            | TransactionType::SettlementIn
+            | TransactionType::ShortfallCover => 1,
+            TransactionType::Withdrawal
+            | TransactionType::Transfer
+            | TransactionType::Settlement
+            | TransactionType::Fee => -1,
+            TransactionType::Lock | TransactionType::Unlock => 0,
+        }
+    }
+}
+
+/// A vault event read from a confirmed transaction by the event indexer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedEvent {
+    pub signature: String,
+    /// Top-level instruction that emitted the event
+    pub instruction_index: u8,
+    /// Position among the events of that instruction
+    pub event_index: u8,
+    /// Position among the vaults of a batch, settlement or transfer event;
+    /// 0 for single-vault events
+    pub entry_index: u8,
+    pub slot: u64,
+    pub block_time: Option<DateTime<Utc>>,
+    pub event_type: String,
+    pub vault: String,
+    /// Other vault of a transfer
+    pub counterparty_vault: Option<String>,
+    pub amount: u64,
+    /// The vault's sequence after the event
+    pub sequence: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -44,6 +100,64 @@ pub struct BalanceSnapshot {
     pub timestamp: DateTime<Utc>,
 }
 
+/// Comparison a reconciliation row came from. `on_chain_balance` is always the
+/// vault's `total_balance`; `database_balance` is the value it was checked against.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconciliationCheck {
+    /// available + locked + sub-account balances of the vault account
+    VaultFields,
+    /// Amount held by the vault's token account
+    TokenAccount,
+    /// Net of the vault's rows in `transactions`
+    Ledger,
+}
+
+impl ReconciliationCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReconciliationCheck::VaultFields => "vault_fields",
+            ReconciliationCheck::TokenAccount => "token_account",
+            ReconciliationCheck::Ledger => "ledger",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "vault_fields" => Some(ReconciliationCheck::VaultFields),
+            "token_account" => Some(ReconciliationCheck::TokenAccount),
+            "ledger" => Some(ReconciliationCheck::Ledger),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationLog {
+    pub id: uuid::Uuid,
+    pub vault: String,
+    pub check_type: ReconciliationCheck,
+    pub on_chain_balance: i64,
+    pub database_balance: i64,
+    /// `on_chain_balance - database_balance`
+    pub discrepancy: i64,
+    pub resolved: bool,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolution_note: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationQuery {
+    pub resolved: Option<bool>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResolveReconciliationRequest {
+    pub note: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DepositRequest {
     pub user: String,
@@ -56,15 +170,241 @@ pub struct WithdrawRequest {
     pub amount: u64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolDepositRequest {
+    pub user: String,
+    pub lamports: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SolWithdrawRequest {
+    pub user: String,
+    pub lamports: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InitializeVaultRequest {
     pub user: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockCollateralRequest {
+    pub user: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnlockCollateralRequest {
+    pub user: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferCollateralRequest {
+    pub from_user: String,
+    pub to_user: String,
+    pub amount: u64,
+}
+
+/// Transaction built by the server for the user's wallet to sign.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnsignedTransaction {
+    /// Base64 of the bincode-serialized legacy transaction, signatures empty
+    pub transaction: String,
+    pub fee_payer: String,
+    pub recent_blockhash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitTransactionRequest {
+    /// Base64 of the wallet-signed transaction returned by a build endpoint
+    pub transaction: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmittedTransaction {
+    pub signature: String,
+    pub vault: String,
+    pub transaction_type: Option<TransactionType>,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// TVL is only reported per mint: raw amounts of mints with different
+/// decimals cannot be added up.
 pub struct TVLResponse {
-    pub total_value_locked: u64,
     pub total_vaults: u64,
+    #[serde(default)]
+    pub mints: Vec<MintTvlResponse>,
     pub timestamp: i64,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MintTvl {
+    pub mint: String,
+    pub vault_count: u64,
+    pub tvl: u64,
+    /// Sum of the mint's vaults' locked balances
+    pub total_locked: u64,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    /// 0 means no cap
+    pub max_vault_balance: u64,
+    /// 0 means no cap
+    pub max_tvl: u64,
+}
+
+impl MintTvl {
+    /// Remaining room under the mint's TVL cap; `None` when uncapped.
+    pub fn tvl_headroom(&self) -> Option<u64> {
+        (self.max_tvl > 0).then(|| self.max_tvl.saturating_sub(self.tvl))
+    }
+}
+
+/// Totals from the latest `getProgramAccounts` scan of all vaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultScanSummary {
+    pub total_vaults: u64,
+    pub mints: Vec<MintScanTotals>,
+    pub timestamp: i64,
+}
+
+/// Scanned vaults of one mint, in the mint's raw units.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MintScanTotals {
+    pub mint: String,
+    pub vault_count: u64,
+    pub tvl: u64,
+    pub locked: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MintTvlResponse {
+    #[serde(flatten)]
+    pub stats: MintTvl,
+    pub tvl_headroom: Option<u64>,
+    /// Totals of the mint's vaults from the latest vault scan, to compare
+    /// with the on-chain counters; `None` when no scan is available
+    pub scan: Option<MintScanTotals>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultBalanceResponse {
+    #[serde(flatten)]
+    pub vault: VaultInfo,
+    /// Largest deposit the caps currently allow; `None` when uncapped
+    pub deposit_headroom: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolStats {
+    pub vault_count: u64,
+    pub mints: Vec<MintTvl>,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsuranceFundInfo {
+    pub mint: String,
+    pub insurance_fund: String,
+    pub token_account: String,
+    pub treasury: String,
+    pub fee_share_bps: u16,
+    pub balance: u64,
+    pub total_collected: u64,
+    pub total_covered: u64,
+}
+
+/// Kinds of events a `/ws` client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamEventType {
+    Deposit,
+    Withdrawal,
+    Lock,
+    Unlock,
+    Transfer,
+    Settlement,
+    Fee,
+    ShortfallCover,
+    Tvl,
+}
+
+/// A vault event decoded by the indexer, as pushed over `/ws`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultActivity {
+    pub signature: String,
+    pub slot: u64,
+    pub vault: String,
+    /// `None` when the event does not name the owner and the vault is not
+    /// in the `vaults` table yet
+    pub owner: Option<String>,
+    /// Receiving vault of a transfer
+    pub counterparty_vault: Option<String>,
+    /// Owner of the receiving vault of a transfer
+    pub counterparty_owner: Option<String>,
+    pub amount: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+/// Message pushed to `/ws` subscribers, tagged with its `type`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    Deposit(VaultActivity),
+    Withdrawal(VaultActivity),
+    Lock(VaultActivity),
+    Unlock(VaultActivity),
+    Transfer(VaultActivity),
+    Settlement(SettlementActivity),
+    Fee(VaultActivity),
+    ShortfallCover(VaultActivity),
+    Tvl(VaultScanSummary),
+}
+
+/// One vault's side of a `settle_batch`; `amount` is the magnitude of
+/// `delta`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlementActivity {
+    #[serde(flatten)]
+    pub activity: VaultActivity,
+    /// Signed change of the vault's total balance
+    pub delta: i64,
+}
+
+impl StreamEvent {
+    pub fn event_type(&self) -> StreamEventType {
+        match self {
+            StreamEvent::Deposit(_) => StreamEventType::Deposit,
+            StreamEvent::Withdrawal(_) => StreamEventType::Withdrawal,
+            StreamEvent::Lock(_) => StreamEventType::Lock,
+            StreamEvent::Unlock(_) => StreamEventType::Unlock,
+            StreamEvent::Transfer(_) => StreamEventType::Transfer,
+            StreamEvent::Settlement(_) => StreamEventType::Settlement,
+            StreamEvent::Fee(_) => StreamEventType::Fee,
+            StreamEvent::ShortfallCover(_) => StreamEventType::ShortfallCover,
+            StreamEvent::Tvl(_) => StreamEventType::Tvl,
+        }
+    }
+}
+
+/// Vaults, owners and event types named in a `/ws` subscribe or
+/// unsubscribe message.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamSubscription {
+    #[serde(default)]
+    pub vaults: Vec<String>,
+    #[serde(default)]
+    pub owners: Vec<String>,
+    #[serde(default)]
+    pub event_types: Vec<StreamEventType>,
+}
+
+/// Message sent by a `/ws` client, tagged with its `action`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum StreamRequest {
+    Subscribe(StreamSubscription),
+    Unsubscribe(StreamSubscription),
+}
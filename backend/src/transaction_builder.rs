@@ -1,103 +1,599 @@
+use anchor_lang::{AnchorDeserialize, Discriminator, InstructionData, ToAccountMetas};
+use collateral_vault as program;
+use collateral_vault::CALLER_AUTHORITY_SEED;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::instruction::Instruction;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::transaction::Transaction;
+use spl_associated_token_account::get_associated_token_address;
+use std::collections::HashSet;
+use std::sync::Arc;
+use crate::compute_budget::{ComputeBudgetConfig, Operation, MAX_COMPUTE_UNIT_LIMIT};
+use crate::error::{Error, Result};
+use crate::models::TransactionType;
 
-const INITIALIZE_VAULT_DISCRIMINATOR: [u8; 8] = [48, 191, 163, 44, 71, 129, 63, 164];
-const DEPOSIT_DISCRIMINATOR: [u8; 8] = [242, 35, 198, 137, 82, 225, 242, 182];
-const WITHDRAW_DISCRIMINATOR: [u8; 8] = [183, 18, 70, 156, 148, 109, 161, 34];
+/// Vault instruction recognised in a client-signed transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultInstruction {
+    InitializeVault,
+    Deposit { amount: u64 },
+    Withdraw { amount: u64 },
+    DepositSol { lamports: u64 },
+    WithdrawSol { lamports: u64 },
+}
+
+impl VaultInstruction {
+    /// Row type in `transactions`; `None` for vault creation, which is not recorded
+    pub fn transaction_type(&self) -> Option<TransactionType> {
+        match self {
+            VaultInstruction::InitializeVault => None,
+            VaultInstruction::Deposit { .. } | VaultInstruction::DepositSol { .. } => {
+                Some(TransactionType::Deposit)
+            }
+            VaultInstruction::Withdraw { .. } | VaultInstruction::WithdrawSol { .. } => {
+                Some(TransactionType::Withdrawal)
+            }
+        }
+    }
+
+    pub fn amount(&self) -> u64 {
+        match *self {
+            VaultInstruction::InitializeVault => 0,
+            VaultInstruction::Deposit { amount } | VaultInstruction::Withdraw { amount } => amount,
+            VaultInstruction::DepositSol { lamports } | VaultInstruction::WithdrawSol { lamports } => lamports,
+        }
+    }
+}
 
 pub struct TransactionBuilder {
     program_id: Pubkey,
+    event_authority: Pubkey,
+    protocol_stats: Pubkey,
+    vault_authority: Pubkey,
+    compute_budget: ComputeBudgetConfig,
 }
 
 impl TransactionBuilder {
     pub fn new(program_id: Pubkey) -> Self {
-        Self { program_id }
+        let (event_authority, _) = Pubkey::find_program_address(
+            &[b"__event_authority"],
+            &program_id,
+        );
+        let (protocol_stats, _) = Pubkey::find_program_address(
+            &[b"protocol_stats"],
+            &program_id,
+        );
+        let (vault_authority, _) = Pubkey::find_program_address(
+            &[b"vault_authority"],
+            &program_id,
+        );
+
+        Self {
+            program_id,
+            event_authority,
+            protocol_stats,
+            vault_authority,
+            compute_budget: ComputeBudgetConfig::default(),
+        }
     }
 
-    pub fn build_initialize_vault_instruction(
+    pub fn with_compute_budget(mut self, config: ComputeBudgetConfig) -> Self {
+        self.compute_budget = config;
+        self
+    }
+
+    /// Prepends compute budget instructions to `instructions` following the
+    /// policy of `operation`. The unit limit comes from simulating the
+    /// transaction, the priority fee from the recent prioritization fees
+    /// paid on the vault accounts it writes. If either RPC call fails the
+    /// policy's default limit or fee floor is used instead; a simulation
+    /// that fails on-chain is returned as an error.
+    pub async fn add_compute_budget(
         &self,
-        user: Pubkey,
-        vault: Pubkey,
-        vault_token_account: Pubkey,
-        mint: Pubkey,
-        vault_authority_pda: Pubkey,
-        vault_authority: Pubkey,
-    ) -> Instruction {
-        let data = INITIALIZE_VAULT_DISCRIMINATOR.to_vec();
-        // No additional args for initialize_vault
+        rpc_client: &Arc<RpcClient>,
+        payer: Pubkey,
+        instructions: Vec<Instruction>,
+        operation: Operation,
+    ) -> Result<Vec<Instruction>> {
+        let policy = self.compute_budget.policy(operation);
 
+        // Fee markets are per writable account; signers only pay
+        let mut seen = HashSet::new();
+        let accounts: Vec<Pubkey> = instructions
+            .iter()
+            .filter(|ix| ix.program_id == self.program_id)
+            .flat_map(|ix| &ix.accounts)
+            .filter(|meta| meta.is_writable && !meta.is_signer && seen.insert(meta.pubkey))
+            .map(|meta| meta.pubkey)
+            .collect();
+
+        let rpc = rpc_client.clone();
+        let recent_fees = tokio::task::spawn_blocking(move || {
+            rpc.get_recent_prioritization_fees(&accounts)
+                .map_err(|e| Error::SolanaClient(e.to_string()))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?;
+
+        let priority_fee = match recent_fees {
+            Ok(fees) => {
+                let fees: Vec<u64> = fees.iter().map(|fee| fee.prioritization_fee).collect();
+                policy.priority_fee(&fees)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to get recent prioritization fees: {}", e);
+                policy.min_priority_fee
+            }
+        };
+
+        let mut simulated = vec![ComputeBudgetInstruction::set_compute_unit_limit(MAX_COMPUTE_UNIT_LIMIT)];
+        simulated.extend(instructions.iter().cloned());
+        let transaction = Transaction::new_with_payer(&simulated, Some(&payer));
+
+        let rpc = rpc_client.clone();
+        let simulation = tokio::task::spawn_blocking(move || {
+            rpc.simulate_transaction_with_config(
+                &transaction,
+                RpcSimulateTransactionConfig {
+                    sig_verify: false,
+                    replace_recent_blockhash: true,
+                    ..RpcSimulateTransactionConfig::default()
+                },
+            )
+            .map_err(|e| Error::SolanaClient(e.to_string()))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?;
+
+        let unit_limit = match simulation {
+            Ok(response) => {
+                if let Some(err) = response.value.err {
+                    let logs = response.value.logs.unwrap_or_default().join("\n");
+                    return Err(Error::TransactionFailed(format!("Simulation failed: {}\n{}", err, logs)));
+                }
+                response.value.units_consumed
+                    .map(|units| policy.unit_limit(units))
+                    .unwrap_or(policy.default_unit_limit)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to simulate transaction: {}", e);
+                policy.default_unit_limit
+            }
+        };
+
+        let mut budgeted = vec![ComputeBudgetInstruction::set_compute_unit_limit(unit_limit)];
+        if priority_fee > 0 {
+            budgeted.push(ComputeBudgetInstruction::set_compute_unit_price(priority_fee));
+        }
+        budgeted.extend(instructions);
+        Ok(budgeted)
+    }
+
+    fn instruction(&self, accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
         Instruction {
             program_id: self.program_id,
-            accounts: vec![
-                solana_sdk::instruction::AccountMeta::new(user, true),
-                solana_sdk::instruction::AccountMeta::new(vault, false),
-                solana_sdk::instruction::AccountMeta::new(vault_token_account, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(mint, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(vault_authority_pda, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(vault_authority, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(spl_token::ID, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(spl_associated_token_account::ID, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
-            ],
-            data,
+            accounts: accounts.to_account_metas(None),
+            data: data.data(),
         }
     }
 
-    pub fn build_deposit_instruction(
+    /// Decodes the user-facing vault instructions this builder produces;
+    /// anything else (including CPI-only instructions) yields `None`.
+    pub fn decode_instruction(data: &[u8]) -> Option<VaultInstruction> {
+        use program::instruction as ix;
+
+        if decode_args::<ix::InitializeVault>(data).is_some() {
+            return Some(VaultInstruction::InitializeVault);
+        }
+        decode_args::<ix::Deposit>(data)
+            .map(|args| VaultInstruction::Deposit { amount: args.amount })
+            .or_else(|| decode_args::<ix::Withdraw>(data).map(|args| VaultInstruction::Withdraw { amount: args.amount }))
+            .or_else(|| decode_args::<ix::DepositSol>(data).map(|args| VaultInstruction::DepositSol { lamports: args.amount }))
+            .or_else(|| decode_args::<ix::WithdrawSol>(data).map(|args| VaultInstruction::WithdrawSol { lamports: args.amount }))
+    }
+
+    /// Rebuilds `instruction` for `user`, as the matching `build_*` method
+    /// would; `mint` is ignored by the SOL instructions.
+    pub fn build_user_instruction(&self, instruction: VaultInstruction, user: Pubkey, mint: Pubkey) -> Instruction {
+        match instruction {
+            VaultInstruction::InitializeVault => self.build_initialize_vault_instruction(user, mint),
+            VaultInstruction::Deposit { amount } => self.build_deposit_instruction(user, mint, amount),
+            VaultInstruction::Withdraw { amount } => self.build_withdraw_instruction(user, mint, amount),
+            VaultInstruction::DepositSol { lamports } => self.build_deposit_sol_instruction(user, lamports),
+            VaultInstruction::WithdrawSol { lamports } => self.build_withdraw_sol_instruction(user, lamports),
+        }
+    }
+
+    /// The collateral vault program
+    pub fn program_id(&self) -> Pubkey {
+        self.program_id
+    }
+
+    /// Global registry of the mints with a `MintStats` account
+    pub fn protocol_stats(&self) -> Pubkey {
+        self.protocol_stats
+    }
+
+    /// Global `VaultAuthority` holding the authorized caller programs
+    pub fn vault_authority(&self) -> Pubkey {
+        self.vault_authority
+    }
+
+    /// Vault PDA of `owner`; also the token authority of the vault's token account
+    pub fn vault_address(&self, owner: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"vault", owner.as_ref()], &self.program_id).0
+    }
+
+    /// Token account holding the collateral of `owner`'s vault
+    pub fn vault_token_account(&self, owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+        get_associated_token_address(&self.vault_address(owner), mint)
+    }
+
+    /// Per-mint totals and deposit caps of `mint`
+    pub fn mint_stats(&self, mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"mint_stats", mint.as_ref()], &self.program_id).0
+    }
+
+    // Allowlist entry the program checks on vault creation and deposits
+    fn allowed_mint(&self, mint: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"allowed_mint", mint.as_ref()], &self.program_id).0
+    }
+
+    pub fn build_initialize_vault_authority_instruction(
         &self,
-        user: Pubkey,
-        vault: Pubkey,
-        user_token_account: Pubkey,
-        vault_token_account: Pubkey,
+        admin: Pubkey,
+        authorized_programs: Vec<Pubkey>,
+    ) -> Instruction {
+        self.instruction(
+            program::accounts::InitializeVaultAuthority {
+                admin,
+                vault_authority: self.vault_authority,
+                protocol_stats: self.protocol_stats,
+                system_program: solana_sdk::system_program::ID,
+            },
+            program::instruction::InitializeVaultAuthority { authorized_programs },
+        )
+    }
+
+    pub fn build_initialize_vault_instruction(&self, user: Pubkey, mint: Pubkey) -> Instruction {
+        let vault = self.vault_address(&user);
+
+        self.instruction(
+            program::accounts::InitializeVault {
+                user,
+                vault,
+                vault_token_account: get_associated_token_address(&vault, &mint),
+                mint,
+                allowed_mint: self.allowed_mint(&mint),
+                vault_authority_pda: vault,
+                vault_authority: self.vault_authority,
+                mint_stats: self.mint_stats(&mint),
+                token_program: spl_token::ID,
+                associated_token_program: spl_associated_token_account::ID,
+                system_program: solana_sdk::system_program::ID,
+                event_authority: self.event_authority,
+                program: self.program_id,
+            },
+            program::instruction::InitializeVault {},
+        )
+    }
+
+    /// Deposits from `user`'s associated token account of `mint`, which must
+    /// already exist.
+    pub fn build_deposit_instruction(&self, user: Pubkey, mint: Pubkey, amount: u64) -> Instruction {
+        let vault = self.vault_address(&user);
+
+        // `vault_delegate` is left out when the user signs as the vault owner
+        self.instruction(
+            program::accounts::Deposit {
+                user,
+                vault,
+                user_token_account: get_associated_token_address(&user, &mint),
+                vault_token_account: get_associated_token_address(&vault, &mint),
+                mint,
+                allowed_mint: self.allowed_mint(&mint),
+                vault_authority: vault,
+                mint_stats: self.mint_stats(&mint),
+                token_program: spl_token::ID,
+                vault_delegate: None,
+                event_authority: self.event_authority,
+                program: self.program_id,
+            },
+            program::instruction::Deposit { amount },
+        )
+    }
+
+    /// Withdraws to `user`'s associated token account of `mint`.
+    pub fn build_withdraw_instruction(&self, user: Pubkey, mint: Pubkey, amount: u64) -> Instruction {
+        let vault = self.vault_address(&user);
+
+        self.instruction(
+            program::accounts::Withdraw {
+                user,
+                vault,
+                user_token_account: get_associated_token_address(&user, &mint),
+                vault_token_account: get_associated_token_address(&vault, &mint),
+                mint,
+                vault_authority_pda: vault,
+                vault_authority: self.vault_authority,
+                mint_stats: self.mint_stats(&mint),
+                token_program: spl_token::ID,
+                vault_delegate: None,
+                event_authority: self.event_authority,
+                program: self.program_id,
+            },
+            program::instruction::Withdraw { amount },
+        )
+    }
+
+    pub fn build_deposit_sol_instruction(&self, user: Pubkey, amount: u64) -> Instruction {
+        let vault = self.vault_address(&user);
+        let native_mint = spl_token::native_mint::ID;
+
+        self.instruction(
+            program::accounts::DepositSol {
+                user,
+                vault,
+                vault_token_account: get_associated_token_address(&vault, &native_mint),
+                native_mint,
+                allowed_mint: self.allowed_mint(&native_mint),
+                mint_stats: self.mint_stats(&native_mint),
+                token_program: spl_token::ID,
+                system_program: solana_sdk::system_program::ID,
+                event_authority: self.event_authority,
+                program: self.program_id,
+            },
+            program::instruction::DepositSol { amount },
+        )
+    }
+
+    pub fn build_withdraw_sol_instruction(&self, user: Pubkey, amount: u64) -> Instruction {
+        let vault = self.vault_address(&user);
+        let native_mint = spl_token::native_mint::ID;
+
+        // Scratch WSOL account the program unwraps through
+        let (unwrap_account, _) = Pubkey::find_program_address(
+            &[b"unwrap", vault.as_ref()],
+            &self.program_id,
+        );
+
+        self.instruction(
+            program::accounts::WithdrawSol {
+                user,
+                vault,
+                vault_token_account: get_associated_token_address(&vault, &native_mint),
+                unwrap_account,
+                native_mint,
+                mint_stats: self.mint_stats(&native_mint),
+                token_program: spl_token::ID,
+                system_program: solana_sdk::system_program::ID,
+                event_authority: self.event_authority,
+                program: self.program_id,
+            },
+            program::instruction::WithdrawSol { amount },
+        )
+    }
+
+    /// Locks collateral in `owner`'s `mint` vault through `caller_program`'s keeper
+    /// `batch_lock`, which signs the vault CPI with its caller PDA. The
+    /// caller must implement the reference position manager's keeper
+    /// instructions and accept `keeper` (the vault admin).
+    pub fn build_keeper_lock_instruction(
+        &self,
+        caller_program: Pubkey,
+        keeper: Pubkey,
         mint: Pubkey,
-        vault_authority: Pubkey,
+        owner: Pubkey,
         amount: u64,
     ) -> Instruction {
-        let mut data = DEPOSIT_DISCRIMINATOR.to_vec();
-        data.extend_from_slice(&amount.to_le_bytes());
+        self.keeper_instruction(
+            caller_program,
+            self.keeper_batch_accounts(caller_program, keeper, mint),
+            position_manager::instruction::BatchLock { owners: vec![owner], amounts: vec![amount] },
+            vec![
+                AccountMeta::new(self.vault_address(&owner), false),
+                AccountMeta::new_readonly(self.vault_token_account(&owner, &mint), false),
+            ],
+        )
+    }
 
-        Instruction {
-            program_id: self.program_id,
-            accounts: vec![
-                solana_sdk::instruction::AccountMeta::new(user, true),
-                solana_sdk::instruction::AccountMeta::new(vault, false),
-                solana_sdk::instruction::AccountMeta::new(user_token_account, false),
-                solana_sdk::instruction::AccountMeta::new(vault_token_account, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(mint, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(vault_authority, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(spl_token::ID, false),
+    /// Unlocks collateral in `owner`'s vault through `caller_program`'s
+    /// keeper `batch_unlock`; see `build_keeper_lock_instruction`.
+    pub fn build_keeper_unlock_instruction(
+        &self,
+        caller_program: Pubkey,
+        keeper: Pubkey,
+        mint: Pubkey,
+        owner: Pubkey,
+        amount: u64,
+    ) -> Instruction {
+        self.keeper_instruction(
+            caller_program,
+            self.keeper_batch_accounts(caller_program, keeper, mint),
+            position_manager::instruction::BatchUnlock { owners: vec![owner], amounts: vec![amount] },
+            vec![
+                AccountMeta::new(self.vault_address(&owner), false),
+                AccountMeta::new_readonly(self.vault_token_account(&owner, &mint), false),
             ],
-            data,
-        }
+        )
     }
 
-    pub fn build_withdraw_instruction(
+    /// Moves available collateral of `mint` from `from_owner`'s vault to
+    /// `to_owner`'s through `caller_program`'s keeper `transfer_collateral`;
+    /// see `build_keeper_lock_instruction`.
+    pub fn build_keeper_transfer_instruction(
         &self,
-        user: Pubkey,
-        vault: Pubkey,
-        user_token_account: Pubkey,
-        vault_token_account: Pubkey,
+        caller_program: Pubkey,
+        keeper: Pubkey,
         mint: Pubkey,
-        vault_authority_pda: Pubkey,
-        vault_authority: Pubkey,
+        from_owner: Pubkey,
+        to_owner: Pubkey,
         amount: u64,
     ) -> Instruction {
-        let mut data = WITHDRAW_DISCRIMINATOR.to_vec();
-        data.extend_from_slice(&amount.to_le_bytes());
+        self.keeper_instruction(
+            caller_program,
+            position_manager::accounts::KeeperTransfer {
+                keeper,
+                from_vault: self.vault_address(&from_owner),
+                to_vault: self.vault_address(&to_owner),
+                from_vault_token_account: self.vault_token_account(&from_owner, &mint),
+                to_vault_token_account: self.vault_token_account(&to_owner, &mint),
+                mint,
+                vault_authority: self.vault_authority,
+                caller_authority: caller_authority_address(&caller_program),
+                vault_event_authority: self.event_authority,
+                collateral_vault_program: self.program_id,
+                position_manager_program: caller_program,
+                token_program: spl_token::ID,
+            },
+            position_manager::instruction::TransferCollateral { amount },
+            vec![],
+        )
+    }
+
+    fn keeper_batch_accounts(
+        &self,
+        caller_program: Pubkey,
+        keeper: Pubkey,
+        mint: Pubkey,
+    ) -> position_manager::accounts::BatchLock {
+        position_manager::accounts::BatchLock {
+            keeper,
+            vault_mint_stats: self.mint_stats(&mint),
+            vault_authority: self.vault_authority,
+            caller_authority: caller_authority_address(&caller_program),
+            vault_event_authority: self.event_authority,
+            collateral_vault_program: self.program_id,
+            position_manager_program: caller_program,
+        }
+    }
+
+    // An instruction of `caller_program` with the vaults it CPIs over appended
+    // as remaining accounts
+    fn keeper_instruction(
+        &self,
+        caller_program: Pubkey,
+        accounts: impl ToAccountMetas,
+        data: impl InstructionData,
+        remaining_accounts: Vec<AccountMeta>,
+    ) -> Instruction {
+        let mut accounts = accounts.to_account_metas(None);
+        accounts.extend(remaining_accounts);
 
         Instruction {
-            program_id: self.program_id,
-            accounts: vec![
-                solana_sdk::instruction::AccountMeta::new(user, true),
-                solana_sdk::instruction::AccountMeta::new(vault, false),
-                solana_sdk::instruction::AccountMeta::new(user_token_account, false),
-                solana_sdk::instruction::AccountMeta::new(vault_token_account, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(mint, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(vault_authority_pda, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(vault_authority, false),
-                solana_sdk::instruction::AccountMeta::new_readonly(spl_token::ID, false),
-            ],
-            data,
+            program_id: caller_program,
+            accounts,
+            data: data.data(),
+        }
+    }
+}
+
+// PDA `caller_program` signs privileged vault CPIs with
+fn caller_authority_address(caller_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CALLER_AUTHORITY_SEED], caller_program).0
+}
+
+// Instruction arguments, if `data` starts with `T`'s discriminator
+fn decode_args<T: Discriminator + AnchorDeserialize>(data: &[u8]) -> Option<T> {
+    let mut args = data.strip_prefix(T::DISCRIMINATOR)?;
+    T::deserialize(&mut args).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `VaultManager::submit_transaction` decodes the instruction with
+    // `decode_instruction` and compares its accounts with the ones
+    // `build_user_instruction` gives for the signing user; these fail when
+    // the two drift apart or the program's arguments change
+    #[test]
+    fn user_instructions_decode_and_rebuild_to_themselves() {
+        let builder = TransactionBuilder::new(program::ID);
+        let user = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let (vault, _) = Pubkey::find_program_address(&[b"vault", user.as_ref()], &program::ID);
+
+        let cases = [
+            (builder.build_initialize_vault_instruction(user, mint), VaultInstruction::InitializeVault),
+            (builder.build_deposit_instruction(user, mint, 7), VaultInstruction::Deposit { amount: 7 }),
+            (builder.build_withdraw_instruction(user, mint, 8), VaultInstruction::Withdraw { amount: 8 }),
+            (builder.build_deposit_sol_instruction(user, 9), VaultInstruction::DepositSol { lamports: 9 }),
+            (builder.build_withdraw_sol_instruction(user, 10), VaultInstruction::WithdrawSol { lamports: 10 }),
+        ];
+
+        for (instruction, expected) in cases {
+            assert_eq!(instruction.program_id, program::ID);
+            assert_eq!(instruction.accounts[1], AccountMeta::new(vault, false), "{:?}", expected);
+            assert_eq!(TransactionBuilder::decode_instruction(&instruction.data), Some(expected));
+            assert_eq!(builder.build_user_instruction(expected, user, mint), instruction);
         }
     }
+
+    // The vault only accepts these from inside the caller program, so they
+    // must target it and name its caller PDA, with the vaults it CPIs over
+    // appended
+    #[test]
+    fn keeper_instructions_go_through_the_caller_program() {
+        let builder = TransactionBuilder::new(program::ID);
+        let (caller, keeper, from_owner, to_owner, mint) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (caller_authority, _) = Pubkey::find_program_address(&[b"vault_caller"], &caller);
+        let from_vault = builder.vault_address(&from_owner);
+
+        for instruction in [
+            builder.build_keeper_lock_instruction(caller, keeper, mint, from_owner, 5),
+            builder.build_keeper_unlock_instruction(caller, keeper, mint, from_owner, 5),
+        ] {
+            assert_eq!(instruction.program_id, caller);
+            assert_eq!(instruction.accounts[0], AccountMeta::new_readonly(keeper, true));
+            assert_eq!(instruction.accounts[1], AccountMeta::new(builder.mint_stats(&mint), false));
+            assert_eq!(instruction.accounts[3].pubkey, caller_authority);
+            assert_eq!(instruction.accounts[instruction.accounts.len() - 2..], [
+                AccountMeta::new(from_vault, false),
+                AccountMeta::new_readonly(builder.vault_token_account(&from_owner, &mint), false),
+            ]);
+        }
+
+        let transfer = builder.build_keeper_transfer_instruction(caller, keeper, mint, from_owner, to_owner, 5);
+        assert_eq!(transfer.program_id, caller);
+        let keys: Vec<Pubkey> = transfer.accounts.iter().map(|meta| meta.pubkey).collect();
+        assert_eq!(keys[..8], [
+            keeper,
+            from_vault,
+            builder.vault_address(&to_owner),
+            builder.vault_token_account(&from_owner, &mint),
+            builder.vault_token_account(&to_owner, &mint),
+            mint,
+            builder.vault_authority(),
+            caller_authority,
+        ]);
+        let args = decode_args::<position_manager::instruction::TransferCollateral>(&transfer.data).unwrap();
+        assert_eq!(args.amount, 5);
+    }
+
+    #[test]
+    fn initialize_vault_authority_creates_the_global_accounts() {
+        let builder = TransactionBuilder::new(program::ID);
+        let admin = Pubkey::new_unique();
+        let authorized = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+
+        let instruction = builder.build_initialize_vault_authority_instruction(admin, authorized.clone());
+        assert_eq!(instruction.accounts, vec![
+            AccountMeta::new(admin, true),
+            AccountMeta::new(builder.vault_authority(), false),
+            AccountMeta::new(builder.protocol_stats(), false),
+            AccountMeta::new_readonly(solana_sdk::system_program::ID, false),
+        ]);
+
+        let args = decode_args::<program::instruction::InitializeVaultAuthority>(&instruction.data).unwrap();
+        assert_eq!(args.authorized_programs, authorized);
+    }
 }
@@ -1,18 +1,74 @@
 use solana_sdk::pubkey::Pubkey;
 use anchor_client::Client;
-use crate::models::VaultInfo;
+use anchor_lang::AccountDeserialize;
+use collateral_vault as program;
+use crate::models::{
+    InsuranceFundInfo, MintTvl, ProtocolStats, SubmittedTransaction, UnsignedTransaction, VaultInfo,
+};
 use crate::error::{Error, Result};
 use crate::database::Database;
-use crate::transaction_builder::TransactionBuilder;
+use crate::compute_budget::{ComputeBudgetConfig, Operation};
+use crate::transaction_builder::{TransactionBuilder, VaultInstruction};
+use base64::Engine;
 use std::str::FromStr;
 use std::sync::Arc;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
+    instruction::Instruction,
     signature::{Keypair, Signer},
     transaction::Transaction,
 };
 use spl_associated_token_account::get_associated_token_address;
 
+// Admin wallet from WALLET_PATH (default ~/.config/solana/id.json); a fresh
+// keypair when the file is missing or malformed
+pub(crate) fn load_payer() -> Arc<Keypair> {
+    let keypair_path = if let Ok(path) = std::env::var("WALLET_PATH") {
+        path
+    } else {
+        format!("{}/.config/solana/id.json", std::env::var("HOME").unwrap_or_default())
+    };
+
+    match std::fs::read_to_string(&keypair_path) {
+        Ok(contents) => {
+            let keypair_bytes: Vec<u8> = serde_json::from_str(&contents)
+                .unwrap_or_else(|_| std::fs::read(&keypair_path).unwrap_or_default());
+            
+            if keypair_bytes.len() == 64 {
+                Arc::new(Keypair::try_from(&keypair_bytes[..]).unwrap_or_else(|_| Keypair::new()))
+            } else {
+                Arc::new(Keypair::new())
+            }
+        }
+        Err(_) => Arc::new(Keypair::new())
+    }
+}
+
+/// Mint of an SPL token account; the account data starts with it.
+pub fn token_account_mint(data: &[u8]) -> Option<Pubkey> {
+    data.get(..32).and_then(|bytes| Pubkey::try_from(bytes).ok())
+}
+
+/// Decodes a `CollateralVault` account stored at `vault`.
+pub fn decode_vault(vault: Pubkey, data: &[u8]) -> Result<VaultInfo> {
+    let account = program::CollateralVault::try_deserialize(&mut &data[..])
+        .map_err(|_| Error::InvalidAccount(format!("{} is not a collateral vault", vault)))?;
+
+    Ok(VaultInfo {
+        owner: account.owner.to_string(),
+        vault: vault.to_string(),
+        token_account: account.token_account.to_string(),
+        total_balance: account.total_balance,
+        locked_balance: account.locked_balance,
+        available_balance: account.available_balance,
+        total_deposited: account.total_deposited,
+        total_withdrawn: account.total_withdrawn,
+        created_at: account.created_at,
+        sequence: account.sequence,
+        sub_account_balance: account.sub_account_balance,
+    })
+}
+
 macro_rules! require {
     ($condition:expr, $error:expr) => {
         if !$condition {
@@ -50,27 +106,7 @@ impl VaultManager {
         
         let rpc_client = Arc::new(RpcClient::new(rpc_url.clone()));
         
-        let payer = {
-            let keypair_path = if let Ok(path) = std::env::var("WALLET_PATH") {
-                path
-            } else {
-                format!("{}/.config/solana/id.json", std::env::var("HOME").unwrap_or_default())
-            };
-
-            match std::fs::read_to_string(&keypair_path) {
-                Ok(contents) => {
-                    let keypair_bytes: Vec<u8> = serde_json::from_str(&contents)
-                        .unwrap_or_else(|_| std::fs::read(&keypair_path).unwrap_or_default());
-                    
-                    if keypair_bytes.len() == 64 {
-                        Arc::new(Keypair::try_from(&keypair_bytes[..]).unwrap_or_else(|_| Keypair::new()))
-                    } else {
-                        Arc::new(Keypair::new())
-                    }
-                }
-                Err(_) => Arc::new(Keypair::new())
-            }
-        };
+        let payer = load_payer();
         
         Self {
             rpc_client: rpc_client.clone(),
@@ -78,7 +114,8 @@ impl VaultManager {
             database,
             mint,
             payer,
-            tx_builder: TransactionBuilder::new(program_id),
+            tx_builder: TransactionBuilder::new(program_id)
+                .with_compute_budget(ComputeBudgetConfig::from_env()),
         }
     }
 
@@ -164,26 +201,10 @@ impl VaultManager {
             }
         }
 
-        let (vault_authority_pda, _) = Pubkey::find_program_address(
-            &[b"vault", user_pubkey.as_ref()],
-            &self.program_id,
-        );
-
-        let (global_vault_authority, _) = Pubkey::find_program_address(
-            &[b"vault_authority"],
-            &self.program_id,
-        );
-
-        let vault_token_account = get_associated_token_address(&vault_authority_pda, &self.mint);
-
-        let instruction = self.tx_builder.build_initialize_vault_instruction(
-            user_pubkey,
-            vault_pda,
-            vault_token_account,
-            self.mint,
-            vault_authority_pda,
-            global_vault_authority,
-        );
+        let instruction = self.tx_builder.build_initialize_vault_instruction(user_pubkey, self.mint);
+        let instructions = self.tx_builder
+            .add_compute_budget(&self.rpc_client, user_pubkey, vec![instruction], Operation::InitializeVault)
+            .await?;
 
         let recent_blockhash = tokio::task::spawn_blocking({
             let rpc_client = self.rpc_client.clone();
@@ -194,7 +215,7 @@ impl VaultManager {
         .map_err(|e| Error::SolanaClient(format!("Failed to get blockhash: {}", e)))?;
 
         let mut transaction = Transaction::new_with_payer(
-            &[instruction],
+            &instructions,
             Some(&user_pubkey),
         );
         transaction.message.recent_blockhash = recent_blockhash;
@@ -239,57 +260,10 @@ impl VaultManager {
             &self.program_id,
         );
 
-        let rpc_client = self.rpc_client.clone();
-        let vault_pda_clone = vault_pda;
-        let account_info = tokio::task::spawn_blocking(move || {
-            rpc_client.get_account(&vault_pda_clone)
-        })
-        .await
-        .map_err(|_| Error::VaultNotFound)?
-        .map_err(|_| Error::VaultNotFound)?;
-
-        if account_info.data.is_empty() {
-            return Err(Error::VaultNotFound);
-        }
-
-        let (vault_authority_pda, _) = Pubkey::find_program_address(
-            &[b"vault", user_pubkey.as_ref()],
-            &self.program_id,
-        );
-
-        let user_token_account = get_associated_token_address(&user_pubkey, &self.mint);
-        let vault_token_account = get_associated_token_address(&vault_authority_pda, &self.mint);
-
-        let rpc_client_ata = self.rpc_client.clone();
-        let user_token_account_clone = user_token_account;
-        let user_ata_exists = tokio::task::spawn_blocking(move || {
-            rpc_client_ata.get_account(&user_token_account_clone)
-        })
-        .await
-        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?;
-
-        let mut instructions = vec![];
-
-        if user_ata_exists.is_err() {
-            let create_ata_ix = spl_associated_token_account::instruction::create_associated_token_account(
-                &self.payer.pubkey(),
-                &user_pubkey,
-                &self.mint,
-                &spl_token::ID,
-            );
-            instructions.push(create_ata_ix);
-        }
-
-        let deposit_instruction = self.tx_builder.build_deposit_instruction(
-            user_pubkey,
-            vault_pda,
-            user_token_account,
-            vault_token_account,
-            self.mint,
-            vault_authority_pda,
-            amount,
-        );
-        instructions.push(deposit_instruction);
+        let instructions = self.deposit_instructions(user_pubkey, vault_pda, amount, self.payer.pubkey()).await?;
+        let instructions = self.tx_builder
+            .add_compute_budget(&self.rpc_client, user_pubkey, instructions, Operation::Deposit)
+            .await?;
 
         let recent_blockhash = tokio::task::spawn_blocking({
             let rpc_client = self.rpc_client.clone();
@@ -349,29 +323,10 @@ impl VaultManager {
             return Err(Error::InsufficientBalance);
         }
 
-        let (vault_authority_pda, _) = Pubkey::find_program_address(
-            &[b"vault", user_pubkey.as_ref()],
-            &self.program_id,
-        );
-
-        let (global_vault_authority, _) = Pubkey::find_program_address(
-            &[b"vault_authority"],
-            &self.program_id,
-        );
-
-        let user_token_account = get_associated_token_address(&user_pubkey, &self.mint);
-        let vault_token_account = get_associated_token_address(&vault_authority_pda, &self.mint);
-
-        let instruction = self.tx_builder.build_withdraw_instruction(
-            user_pubkey,
-            vault_pda,
-            user_token_account,
-            vault_token_account,
-            self.mint,
-            vault_authority_pda,
-            global_vault_authority,
-            amount,
-        );
+        let instruction = self.tx_builder.build_withdraw_instruction(user_pubkey, self.mint, amount);
+        let instructions = self.tx_builder
+            .add_compute_budget(&self.rpc_client, user_pubkey, vec![instruction], Operation::Withdraw)
+            .await?;
 
         let recent_blockhash = tokio::task::spawn_blocking({
             let rpc_client = self.rpc_client.clone();
@@ -382,7 +337,7 @@ impl VaultManager {
         .map_err(|e| Error::SolanaClient(format!("Failed to get blockhash: {}", e)))?;
 
         let mut transaction = Transaction::new_with_payer(
-            &[instruction],
+            &instructions,
             Some(&user_pubkey),
         );
         transaction.message.recent_blockhash = recent_blockhash;
@@ -415,92 +370,640 @@ impl VaultManager {
         Ok(signature.to_string())
     }
 
-    pub async fn get_vault_info(&self, user: &str) -> Result<VaultInfo> {
+    pub async fn deposit_sol(&self, user: &str, lamports: u64) -> Result<String> {
         let user_pubkey = Pubkey::from_str(user)
             .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
 
+        let (vault_pda, instruction) = self.deposit_sol_instruction(user, user_pubkey, lamports).await?;
+
+        let signature = self.sign_and_send(user_pubkey, vec![instruction], "Deposit", Operation::DepositSol).await?;
+
+        self.database.create_transaction(
+            &vault_pda.to_string(),
+            crate::models::TransactionType::Deposit,
+            lamports,
+            Some(&signature),
+        ).await?;
+
+        Ok(signature)
+    }
+
+    pub async fn withdraw_sol(&self, user: &str, lamports: u64) -> Result<String> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        let (vault_pda, instruction) = self.withdraw_sol_instruction(user, user_pubkey, lamports).await?;
+
+        let signature = self.sign_and_send(user_pubkey, vec![instruction], "Withdraw", Operation::WithdrawSol).await?;
+
+        self.database.create_transaction(
+            &vault_pda.to_string(),
+            crate::models::TransactionType::Withdrawal,
+            lamports,
+            Some(&signature),
+        ).await?;
+
+        Ok(signature)
+    }
+
+    /// Unsigned `initialize_vault` transaction, paid for and signed by the user.
+    pub async fn build_initialize_vault(&self, user: &str) -> Result<UnsignedTransaction> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        if self.get_vault_info(user).await.is_ok() {
+            return Err(Error::InvalidAccount("Vault already exists".to_string()));
+        }
+
+        let instruction = self.tx_builder.build_initialize_vault_instruction(user_pubkey, self.mint);
+        self.unsigned_transaction(user_pubkey, vec![instruction], Operation::InitializeVault).await
+    }
+
+    /// Unsigned `deposit` transaction, paid for and signed by the user.
+    pub async fn build_deposit(&self, user: &str, amount: u64) -> Result<UnsignedTransaction> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        require!(amount > 0, Error::InvalidAccount("Amount must be greater than 0".to_string()));
+
         let (vault_pda, _) = Pubkey::find_program_address(
             &[b"vault", user_pubkey.as_ref()],
             &self.program_id,
         );
 
+        let instructions = self.deposit_instructions(user_pubkey, vault_pda, amount, user_pubkey).await?;
+        self.unsigned_transaction(user_pubkey, instructions, Operation::Deposit).await
+    }
+
+    /// Unsigned `withdraw` transaction, paid for and signed by the user.
+    pub async fn build_withdraw(&self, user: &str, amount: u64) -> Result<UnsignedTransaction> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        require!(amount > 0, Error::InvalidAccount("Amount must be greater than 0".to_string()));
+
+        let vault_info = self.get_vault_info(user).await?;
+        if vault_info.available_balance < amount {
+            return Err(Error::InsufficientBalance);
+        }
+
+        let instruction = self.tx_builder.build_withdraw_instruction(user_pubkey, self.mint, amount);
+        self.unsigned_transaction(user_pubkey, vec![instruction], Operation::Withdraw).await
+    }
+
+    /// Unsigned `deposit_sol` transaction, paid for and signed by the user.
+    pub async fn build_deposit_sol(&self, user: &str, lamports: u64) -> Result<UnsignedTransaction> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        let (_, instruction) = self.deposit_sol_instruction(user, user_pubkey, lamports).await?;
+        self.unsigned_transaction(user_pubkey, vec![instruction], Operation::DepositSol).await
+    }
+
+    /// Unsigned `withdraw_sol` transaction, paid for and signed by the user.
+    pub async fn build_withdraw_sol(&self, user: &str, lamports: u64) -> Result<UnsignedTransaction> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        let (_, instruction) = self.withdraw_sol_instruction(user, user_pubkey, lamports).await?;
+        self.unsigned_transaction(user_pubkey, vec![instruction], Operation::WithdrawSol).await
+    }
+
+    /// Sends a wallet-signed transaction produced by one of the `build_*`
+    /// methods and records it in `transactions`. The signatures must verify
+    /// and the transaction may only contain a single vault instruction plus
+    /// token account creation and compute budget instructions.
+    pub async fn submit_transaction(&self, encoded: &str) -> Result<SubmittedTransaction> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid transaction encoding: {}", e)))?;
+        let transaction: Transaction = bincode::deserialize(&bytes)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid transaction: {}", e)))?;
+
+        transaction.verify()
+            .map_err(|e| Error::InvalidAccount(format!("Invalid transaction signatures: {}", e)))?;
+
+        let (vault_pda, instruction) = verify_vault_transaction(&self.tx_builder, self.mint, &transaction)?;
+
+        let rpc_client = self.rpc_client.clone();
+        let signature = tokio::task::spawn_blocking(move || {
+            rpc_client.send_and_confirm_transaction(&transaction)
+                .map_err(|e| Error::TransactionFailed(format!("Failed to send transaction: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::TransactionFailed(format!("Task join error: {}", e)))??;
+
+        if let Some(transaction_type) = instruction.transaction_type() {
+            self.database.create_transaction(
+                &vault_pda.to_string(),
+                transaction_type,
+                instruction.amount(),
+                Some(&signature.to_string()),
+            ).await?;
+        }
+
+        Ok(SubmittedTransaction {
+            signature: signature.to_string(),
+            vault: vault_pda.to_string(),
+            transaction_type: instruction.transaction_type(),
+            amount: instruction.amount(),
+        })
+    }
+
+    async fn unsigned_transaction(
+        &self,
+        fee_payer: Pubkey,
+        instructions: Vec<Instruction>,
+        operation: Operation,
+    ) -> Result<UnsignedTransaction> {
+        let instructions = self.tx_builder
+            .add_compute_budget(&self.rpc_client, fee_payer, instructions, operation)
+            .await?;
+
+        let recent_blockhash = tokio::task::spawn_blocking({
+            let rpc_client = self.rpc_client.clone();
+            move || {
+                rpc_client.get_latest_blockhash()
+                    .map_err(|e| Error::SolanaClient(format!("Failed to get blockhash: {}", e)))
+            }
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let mut transaction = Transaction::new_with_payer(&instructions, Some(&fee_payer));
+        transaction.message.recent_blockhash = recent_blockhash;
+
+        let bytes = bincode::serialize(&transaction)
+            .map_err(|e| Error::SolanaClient(format!("Failed to serialize transaction: {}", e)))?;
+
+        Ok(UnsignedTransaction {
+            transaction: base64::engine::general_purpose::STANDARD.encode(bytes),
+            fee_payer: fee_payer.to_string(),
+            recent_blockhash: recent_blockhash.to_string(),
+        })
+    }
+
+    // Deposit instructions, preceded by creation of the user's token account
+    // (paid by `fee_payer`) when it does not exist yet
+    async fn deposit_instructions(
+        &self,
+        user_pubkey: Pubkey,
+        vault_pda: Pubkey,
+        amount: u64,
+        fee_payer: Pubkey,
+    ) -> Result<Vec<Instruction>> {
         let rpc_client = self.rpc_client.clone();
         let vault_pda_clone = vault_pda;
         let account_info = tokio::task::spawn_blocking(move || {
-            rpc_client.get_account(&vault_pda_clone)
+            rpc_client.get_account(&vault_pda_clone).map_err(|_| Error::VaultNotFound)
         })
         .await
-        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?
-        .map_err(|e| Error::SolanaClient(format!("Failed to fetch vault account: {}", e)))?;
+        .map_err(|_| Error::VaultNotFound)??;
 
         if account_info.data.is_empty() {
-            return Err(Error::InvalidAccount("Vault account does not exist".to_string()));
+            return Err(Error::VaultNotFound);
         }
 
-        let account_data = &account_info.data[8..];
-        
-        if account_data.len() < 105 {
-            return Err(Error::SolanaClient("Invalid account data length".to_string()));
+        let user_token_account = get_associated_token_address(&user_pubkey, &self.mint);
+
+        let rpc_client_ata = self.rpc_client.clone();
+        let user_token_account_clone = user_token_account;
+        let user_ata_exists = tokio::task::spawn_blocking(move || {
+            rpc_client_ata.get_account(&user_token_account_clone).is_ok()
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?;
+
+        let mut instructions = vec![];
+
+        if !user_ata_exists {
+            let create_ata_ix = spl_associated_token_account::instruction::create_associated_token_account(
+                &fee_payer,
+                &user_pubkey,
+                &self.mint,
+                &spl_token::ID,
+            );
+            instructions.push(create_ata_ix);
         }
 
-        let mut offset = 0;
-        
-        let owner = Pubkey::try_from(&account_data[offset..offset+32])
-            .map_err(|_| Error::SolanaClient("Failed to parse owner".to_string()))?;
-        offset += 32;
-        
-        let token_account = Pubkey::try_from(&account_data[offset..offset+32])
-            .map_err(|_| Error::SolanaClient("Failed to parse token_account".to_string()))?;
-        offset += 32;
-        
-        let total_balance = u64::from_le_bytes(
-            account_data[offset..offset+8].try_into()
-                .map_err(|_| Error::SolanaClient("Failed to parse total_balance".to_string()))?
+        instructions.push(self.tx_builder.build_deposit_instruction(user_pubkey, self.mint, amount));
+
+        Ok(instructions)
+    }
+
+    async fn deposit_sol_instruction(
+        &self,
+        user: &str,
+        user_pubkey: Pubkey,
+        lamports: u64,
+    ) -> Result<(Pubkey, Instruction)> {
+        require!(lamports > 0, Error::InvalidAccount("Amount must be greater than 0".to_string()));
+
+        let vault_info = self.get_vault_info(user).await
+            .map_err(|_| Error::VaultNotFound)?;
+
+        let (vault_pda, _) = Pubkey::find_program_address(
+            &[b"vault", user_pubkey.as_ref()],
+            &self.program_id,
         );
-        offset += 8;
-        
-        let locked_balance = u64::from_le_bytes(
-            account_data[offset..offset+8].try_into()
-                .map_err(|_| Error::SolanaClient("Failed to parse locked_balance".to_string()))?
+        self.require_native_vault(&vault_info)?;
+
+        let instruction = self.tx_builder.build_deposit_sol_instruction(user_pubkey, lamports);
+
+        Ok((vault_pda, instruction))
+    }
+
+    async fn withdraw_sol_instruction(
+        &self,
+        user: &str,
+        user_pubkey: Pubkey,
+        lamports: u64,
+    ) -> Result<(Pubkey, Instruction)> {
+        require!(lamports > 0, Error::InvalidAccount("Amount must be greater than 0".to_string()));
+
+        let vault_info = self.get_vault_info(user).await
+            .map_err(|_| Error::VaultNotFound)?;
+        if vault_info.available_balance < lamports {
+            return Err(Error::InsufficientBalance);
+        }
+
+        let (vault_pda, _) = Pubkey::find_program_address(
+            &[b"vault", user_pubkey.as_ref()],
+            &self.program_id,
         );
-        offset += 8;
-        
-        let available_balance = u64::from_le_bytes(
-            account_data[offset..offset+8].try_into()
-                .map_err(|_| Error::SolanaClient("Failed to parse available_balance".to_string()))?
+        self.require_native_vault(&vault_info)?;
+
+        let instruction = self.tx_builder.build_withdraw_sol_instruction(user_pubkey, lamports);
+
+        Ok((vault_pda, instruction))
+    }
+
+    // Errors for vaults whose token account is not the WSOL one
+    fn require_native_vault(&self, vault_info: &VaultInfo) -> Result<()> {
+        let owner = Pubkey::from_str(&vault_info.owner)
+            .map_err(|_| Error::SolanaClient("Failed to parse vault owner".to_string()))?;
+        let vault_token_account = self.tx_builder.vault_token_account(&owner, &spl_token::native_mint::ID);
+        require!(
+            vault_info.token_account == vault_token_account.to_string(),
+            Error::InvalidAccount("Vault does not hold native SOL".to_string())
         );
-        offset += 8;
-        
-        let total_deposited = u64::from_le_bytes(
-            account_data[offset..offset+8].try_into()
-                .map_err(|_| Error::SolanaClient("Failed to parse total_deposited".to_string()))?
+        Ok(())
+    }
+
+    async fn sign_and_send(
+        &self,
+        user_pubkey: Pubkey,
+        instructions: Vec<solana_sdk::instruction::Instruction>,
+        action: &str,
+        operation: Operation,
+    ) -> Result<String> {
+        if user_pubkey != self.payer.pubkey() {
+            return Err(Error::TransactionFailed(format!(
+                "{} requires user's wallet signature. Please sign the transaction with your wallet.",
+                action
+            )));
+        }
+
+        let instructions = self.tx_builder
+            .add_compute_budget(&self.rpc_client, user_pubkey, instructions, operation)
+            .await?;
+
+        let recent_blockhash = tokio::task::spawn_blocking({
+            let rpc_client = self.rpc_client.clone();
+            move || {
+                rpc_client.get_latest_blockhash()
+                    .map_err(|e| Error::SolanaClient(format!("Failed to get blockhash: {}", e)))
+            }
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let mut transaction = Transaction::new_with_payer(
+            &instructions,
+            Some(&user_pubkey),
         );
-        offset += 8;
-        
-        let total_withdrawn = u64::from_le_bytes(
-            account_data[offset..offset+8].try_into()
-                .map_err(|_| Error::SolanaClient("Failed to parse total_withdrawn".to_string()))?
+        transaction.try_sign(&[&*self.payer], recent_blockhash)
+            .map_err(|e| Error::TransactionFailed(format!("Failed to sign transaction: {}", e)))?;
+
+        let rpc_client = self.rpc_client.clone();
+        let signature = tokio::task::spawn_blocking(move || {
+            rpc_client.send_and_confirm_transaction(&transaction)
+                .map_err(|e| Error::TransactionFailed(format!("Failed to send transaction: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::TransactionFailed(format!("Task join error: {}", e)))??;
+
+        Ok(signature.to_string())
+    }
+
+    pub async fn get_vault_info(&self, user: &str) -> Result<VaultInfo> {
+        let user_pubkey = Pubkey::from_str(user)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid user pubkey: {}", e)))?;
+
+        let (vault_pda, _) = Pubkey::find_program_address(
+            &[b"vault", user_pubkey.as_ref()],
+            &self.program_id,
         );
-        offset += 8;
-        
-        let created_at = i64::from_le_bytes(
-            account_data[offset..offset+8].try_into()
-                .map_err(|_| Error::SolanaClient("Failed to parse created_at".to_string()))?
+
+        let rpc_client = self.rpc_client.clone();
+        let vault_pda_clone = vault_pda;
+        let account_info = tokio::task::spawn_blocking(move || {
+            rpc_client.get_account(&vault_pda_clone)
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?
+        .map_err(|e| Error::SolanaClient(format!("Failed to fetch vault account: {}", e)))?;
+
+        if account_info.data.is_empty() {
+            return Err(Error::InvalidAccount("Vault account does not exist".to_string()));
+        }
+
+        decode_vault(vault_pda, &account_info.data)
+    }
+
+    /// Largest deposit the vault and mint caps currently allow, or `None` if
+    /// neither is capped.
+    pub async fn get_deposit_headroom(&self, vault_info: &VaultInfo) -> Result<Option<u64>> {
+        let token_account = Pubkey::from_str(&vault_info.token_account)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid token account: {}", e)))?;
+
+        let rpc_client = self.rpc_client.clone();
+        let token_account_info = tokio::task::spawn_blocking(move || {
+            rpc_client.get_account(&token_account)
+                .map_err(|e| Error::SolanaClient(format!("Failed to fetch vault token account: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let mint = token_account_mint(&token_account_info.data)
+            .ok_or_else(|| Error::SolanaClient("Failed to parse token account mint".to_string()))?;
+
+        let Some(entry) = self.get_mint_stats(&[mint]).await?.into_iter().next() else {
+            return Ok(None);
+        };
+
+        let vault_headroom = (entry.max_vault_balance > 0)
+            .then(|| entry.max_vault_balance.saturating_sub(vault_info.total_balance));
+
+        Ok(match (vault_headroom, entry.tvl_headroom()) {
+            (Some(vault), Some(mint)) => Some(vault.min(mint)),
+            (vault, mint) => vault.or(mint),
+        })
+    }
+
+    /// Totals of every mint in the `ProtocolStats` registry.
+    pub async fn get_protocol_stats(&self) -> Result<ProtocolStats> {
+        let rpc_client = self.rpc_client.clone();
+        let protocol_stats = self.tx_builder.protocol_stats();
+        let account_info = tokio::task::spawn_blocking(move || {
+            rpc_client.get_account(&protocol_stats)
+                .map_err(|e| Error::SolanaClient(format!("Failed to fetch protocol stats: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let registry = program::ProtocolStats::try_deserialize(&mut &account_info.data[..])
+            .map_err(|e| Error::SolanaClient(format!("Failed to decode protocol stats: {}", e)))?;
+
+        let mints = self.get_mint_stats(&registry.mints).await?;
+        Ok(ProtocolStats {
+            vault_count: mints.iter().map(|entry| entry.vault_count).sum(),
+            mints,
+        })
+    }
+
+    /// `MintStats` of each of `mints`; mints without the account are left out.
+    pub async fn get_mint_stats(&self, mints: &[Pubkey]) -> Result<Vec<MintTvl>> {
+        let addresses: Vec<Pubkey> = mints.iter()
+            .map(|mint| self.tx_builder.mint_stats(mint))
+            .collect();
+
+        let rpc_client = self.rpc_client.clone();
+        let accounts = tokio::task::spawn_blocking(move || {
+            rpc_client.get_multiple_accounts(&addresses)
+                .map_err(|e| Error::SolanaClient(format!("Failed to fetch mint stats: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        accounts
+            .into_iter()
+            .flatten()
+            .map(|account| {
+                let stats = program::MintStats::try_deserialize(&mut &account.data[..])
+                    .map_err(|e| Error::SolanaClient(format!("Failed to decode mint stats: {}", e)))?;
+                Ok(MintTvl {
+                    mint: stats.mint.to_string(),
+                    vault_count: stats.vault_count,
+                    tvl: stats.tvl,
+                    total_locked: stats.total_locked,
+                    total_deposited: stats.total_deposited,
+                    total_withdrawn: stats.total_withdrawn,
+                    max_vault_balance: stats.max_vault_balance,
+                    max_tvl: stats.max_tvl,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn get_insurance_fund(&self, mint: &str) -> Result<InsuranceFundInfo> {
+        let mint_pubkey = Pubkey::from_str(mint)
+            .map_err(|e| Error::InvalidAccount(format!("Invalid mint pubkey: {}", e)))?;
+
+        let (insurance_fund, _) = Pubkey::find_program_address(
+            &[b"insurance_fund", mint_pubkey.as_ref()],
+            &self.program_id,
         );
-        offset += 8;
-        
-        let _bump = account_data[offset];
 
-        Ok(VaultInfo {
-            owner: owner.to_string(),
-            vault: vault_pda.to_string(),
-            token_account: token_account.to_string(),
-            total_balance,
-            locked_balance,
-            available_balance,
-            total_deposited,
-            total_withdrawn,
-            created_at,
+        let rpc_client = self.rpc_client.clone();
+        let account_info = tokio::task::spawn_blocking(move || {
+            rpc_client.get_account(&insurance_fund)
+                .map_err(|e| Error::SolanaClient(format!("Failed to fetch insurance fund: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        let fund = program::InsuranceFund::try_deserialize(&mut &account_info.data[..])
+            .map_err(|e| Error::SolanaClient(format!("Failed to decode insurance fund: {}", e)))?;
+
+        Ok(InsuranceFundInfo {
+            mint: fund.mint.to_string(),
+            insurance_fund: insurance_fund.to_string(),
+            token_account: fund.token_account.to_string(),
+            treasury: fund.treasury.to_string(),
+            fee_share_bps: fund.fee_share_bps,
+            balance: fund.balance,
+            total_collected: fund.total_collected,
+            total_covered: fund.total_covered,
         })
     }
 }
+
+// Returns the vault and the single vault instruction of a client-signed
+// transaction. The instruction's user is its one signing account; its
+// accounts must then be exactly those the builder produces for that user and
+// `mint`, so the vault is the user's PDA whatever order the accounts come in.
+// Other programs are rejected, except token account creation and compute
+// budget instructions.
+fn verify_vault_transaction(
+    tx_builder: &TransactionBuilder,
+    mint: Pubkey,
+    transaction: &Transaction,
+) -> Result<(Pubkey, VaultInstruction)> {
+    let message = &transaction.message;
+    let mut found = None;
+
+    for compiled in &message.instructions {
+        let program_id = message.account_keys.get(compiled.program_id_index as usize)
+            .ok_or_else(|| Error::InvalidAccount("Invalid program index".to_string()))?;
+
+        if *program_id == tx_builder.program_id() {
+            let instruction = TransactionBuilder::decode_instruction(&compiled.data)
+                .ok_or_else(|| Error::InvalidAccount("Unexpected vault instruction".to_string()))?;
+            require!(
+                found.is_none(),
+                Error::InvalidAccount("Transaction contains more than one vault instruction".to_string())
+            );
+
+            let indices = compiled.accounts.iter()
+                .map(|&index| index as usize)
+                .filter(|&index| index < message.account_keys.len())
+                .collect::<Vec<_>>();
+            require!(
+                indices.len() == compiled.accounts.len(),
+                Error::InvalidAccount("Invalid vault instruction account".to_string())
+            );
+
+            let mut signers = indices.iter().filter(|&&index| message.is_signer(index));
+            let user = match (signers.next(), signers.next()) {
+                (Some(&index), None) => message.account_keys[index],
+                _ => return Err(Error::Unauthorized),
+            };
+
+            let expected = tx_builder.build_user_instruction(instruction, user, mint);
+            require!(
+                indices.iter()
+                    .map(|&index| message.account_keys[index])
+                    .eq(expected.accounts.iter().map(|meta| meta.pubkey)),
+                Error::InvalidAccount("Vault instruction accounts do not match the signing user".to_string())
+            );
+
+            found = Some((tx_builder.vault_address(&user), instruction));
+        } else if *program_id != spl_associated_token_account::ID
+            && *program_id != solana_sdk::compute_budget::ID
+        {
+            return Err(Error::InvalidAccount(format!("Unexpected program {} in transaction", program_id)));
+        }
+    }
+
+    found.ok_or_else(|| Error::InvalidAccount("Transaction contains no vault instruction".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use solana_sdk::instruction::AccountMeta;
+
+    // A `CollateralVault` laid out by hand: discriminator, owner,
+    // token_account, total/locked/available balance, total deposited and
+    // withdrawn, created_at, sequence, sub_account_balance and bump
+    fn vault_account_data() -> Vec<u8> {
+        [
+            &[19, 189, 95, 155, 100, 9, 159, 145][..],
+            &[1; 32],
+            &[2; 32],
+            &1_000u64.to_le_bytes(),
+            &200u64.to_le_bytes(),
+            &700u64.to_le_bytes(),
+            &1_500u64.to_le_bytes(),
+            &500u64.to_le_bytes(),
+            &1_699_123_456i64.to_le_bytes(),
+            &12u64.to_le_bytes(),
+            &100u64.to_le_bytes(),
+            &[254],
+        ]
+        .concat()
+    }
+
+    // Fails when `VaultInfo` stops mirroring the program's account layout
+    #[test]
+    fn decodes_the_program_vault_account_layout() {
+        let data = vault_account_data();
+        assert_eq!(data.len(), 137);
+        let vault = Pubkey::new_unique();
+
+        let info = decode_vault(vault, &data).unwrap();
+        assert_eq!(info.owner, Pubkey::new_from_array([1; 32]).to_string());
+        assert_eq!(info.vault, vault.to_string());
+        assert_eq!(info.token_account, Pubkey::new_from_array([2; 32]).to_string());
+        assert_eq!(info.total_balance, 1_000);
+        assert_eq!(info.locked_balance, 200);
+        assert_eq!(info.available_balance, 700);
+        assert_eq!(info.total_deposited, 1_500);
+        assert_eq!(info.total_withdrawn, 500);
+        assert_eq!(info.created_at, 1_699_123_456);
+        assert_eq!(info.sequence, 12);
+        assert_eq!(info.sub_account_balance, 100);
+    }
+
+    #[test]
+    fn rejects_accounts_of_other_types() {
+        let mut data = vault_account_data();
+        data[..8].copy_from_slice(program::VaultAuthority::DISCRIMINATOR);
+
+        assert!(matches!(decode_vault(Pubkey::new_unique(), &data), Err(Error::InvalidAccount(_))));
+    }
+
+    fn verify(instructions: &[Instruction], payer: &Pubkey, mint: Pubkey) -> Result<(Pubkey, VaultInstruction)> {
+        let transaction = Transaction::new_with_payer(instructions, Some(payer));
+        verify_vault_transaction(&TransactionBuilder::new(program::ID), mint, &transaction)
+    }
+
+    #[test]
+    fn accepts_a_built_vault_transaction() {
+        let builder = TransactionBuilder::new(program::ID);
+        let (user, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let instructions = [
+            ComputeBudgetInstruction::set_compute_unit_limit(50_000),
+            builder.build_deposit_instruction(user, mint, 7),
+        ];
+
+        let (vault, instruction) = verify(&instructions, &user, mint).unwrap();
+        assert_eq!(vault, builder.vault_address(&user));
+        assert_eq!(instruction, VaultInstruction::Deposit { amount: 7 });
+    }
+
+    #[test]
+    fn rejects_reordered_vault_accounts() {
+        let builder = TransactionBuilder::new(program::ID);
+        let (user, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut instruction = builder.build_withdraw_instruction(user, mint, 7);
+        instruction.accounts.swap(0, 1);
+
+        assert!(matches!(verify(&[instruction], &user, mint), Err(Error::InvalidAccount(_))));
+    }
+
+    #[test]
+    fn rejects_another_users_vault() {
+        let builder = TransactionBuilder::new(program::ID);
+        let (user, victim, mint) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut instruction = builder.build_withdraw_instruction(victim, mint, 7);
+        instruction.accounts[0] = AccountMeta::new(user, true);
+
+        assert!(matches!(verify(&[instruction], &user, mint), Err(Error::InvalidAccount(_))));
+    }
+
+    #[test]
+    fn rejects_instructions_of_other_programs() {
+        let builder = TransactionBuilder::new(program::ID);
+        let (user, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let instructions = [
+            builder.build_deposit_instruction(user, mint, 7),
+            Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![AccountMeta::new(user, true)]),
+        ];
+
+        assert!(matches!(verify(&instructions, &user, mint), Err(Error::InvalidAccount(_))));
+    }
+}
@@ -1,26 +1,183 @@
+use anchor_lang::Discriminator;
 use crate::database::Database;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::models::{MintScanTotals, StreamEvent, VaultInfo, VaultScanSummary};
+use crate::vault_manager::{decode_vault, token_account_mint};
+use crate::websocket::WebSocketManager;
+use solana_account_decoder_client_types::UiAccountEncoding;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::RwLock;
 use tokio::time::{interval, Duration};
 
 pub struct VaultMonitor {
+    rpc_client: Arc<RpcClient>,
     database: Database,
+    program_id: Pubkey,
+    snapshot_interval: Duration,
+    latest: RwLock<Option<VaultScanSummary>>,
+    websocket: Option<WebSocketManager>,
 }
 
 impl VaultMonitor {
-    pub fn new(database: Database) -> Self {
-        Self { database }
+    pub fn new(database: Database, program_id: Pubkey, snapshot_interval: Duration) -> Self {
+        let rpc_url = std::env::var("RPC_URL")
+            .unwrap_or_else(|_| "http://localhost:8899".to_string());
+
+        Self {
+            rpc_client: Arc::new(RpcClient::new(rpc_url)),
+            database,
+            program_id,
+            snapshot_interval,
+            latest: RwLock::new(None),
+            websocket: None,
+        }
+    }
+
+    /// Pushes the totals of every snapshot round to `/ws` subscribers.
+    pub fn with_websocket(mut self, websocket: WebSocketManager) -> Self {
+        self.websocket = Some(websocket);
+        self
     }
 
+    /// Records snapshots of every vault each `snapshot_interval`. A failed
+    /// round is logged and retried on the next tick.
     pub async fn monitor_vaults(&self) -> Result<()> {
-        let mut interval = interval(Duration::from_secs(300));
+        let mut interval = interval(self.snapshot_interval);
 
         loop {
             interval.tick().await;
+
+            if let Err(e) = self.record_snapshots().await {
+                tracing::warn!("Vault snapshot round failed: {}", e);
+            }
+        }
+    }
+
+    /// All `CollateralVault` accounts owned by the program.
+    pub async fn scan_vaults(&self) -> Result<Vec<VaultInfo>> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+                0,
+                collateral_vault::CollateralVault::DISCRIMINATOR.to_vec(),
+            ))]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+
+        let rpc_client = self.rpc_client.clone();
+        let program_id = self.program_id;
+        let accounts = tokio::task::spawn_blocking(move || {
+            rpc_client.get_program_accounts_with_config(&program_id, config)
+                .map_err(|e| Error::SolanaClient(format!("Failed to scan vault accounts: {}", e)))
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))??;
+
+        accounts
+            .into_iter()
+            .map(|(address, account)| decode_vault(address, &account.data))
+            .collect()
+    }
+
+    /// Scans all vaults, upserts the `vaults` table, writes one
+    /// `balance_snapshots` row per vault and caches the resulting totals.
+    pub async fn record_snapshots(&self) -> Result<VaultScanSummary> {
+        let vaults = self.scan_vaults().await?;
+
+        for vault in &vaults {
+            let created_at = chrono::DateTime::from_timestamp(vault.created_at, 0)
+                .unwrap_or_else(chrono::Utc::now);
+            self.database
+                .upsert_vault(&vault.owner, &vault.vault, &vault.token_account, created_at)
+                .await?;
+            self.database
+                .create_balance_snapshot(
+                    &vault.vault,
+                    vault.total_balance,
+                    vault.locked_balance,
+                    vault.available_balance,
+                )
+                .await?;
+        }
+
+        let summary = summarize(&vaults, &self.vault_mints(&vaults).await?);
+        *self.latest.write().await = Some(summary.clone());
+        if let Some(websocket) = &self.websocket {
+            websocket.broadcast(StreamEvent::Tvl(summary.clone()));
+        }
+        Ok(summary)
+    }
+
+    /// Totals from the latest snapshot round, or from a fresh scan when no
+    /// round has completed yet.
+    pub async fn get_tvl(&self) -> Result<VaultScanSummary> {
+        if let Some(summary) = self.latest.read().await.clone() {
+            return Ok(summary);
         }
+
+        let vaults = self.scan_vaults().await?;
+        let summary = summarize(&vaults, &self.vault_mints(&vaults).await?);
+        *self.latest.write().await = Some(summary.clone());
+        Ok(summary)
     }
 
-    pub async fn get_tvl(&self) -> Result<u64> {
-        self.database.get_total_tvl().await
+    // Mint of each vault, read from its token account; `None` when the
+    // token account is missing or unreadable
+    async fn vault_mints(&self, vaults: &[VaultInfo]) -> Result<Vec<Option<Pubkey>>> {
+        let token_accounts = vaults
+            .iter()
+            .map(|vault| {
+                Pubkey::from_str(&vault.token_account)
+                    .map_err(|e| Error::InvalidAccount(format!("Invalid token account: {}", e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let rpc_client = self.rpc_client.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut mints = Vec::with_capacity(token_accounts.len());
+            // getMultipleAccounts takes at most 100 addresses
+            for chunk in token_accounts.chunks(100) {
+                let accounts = rpc_client.get_multiple_accounts(chunk)
+                    .map_err(|e| Error::SolanaClient(format!("Failed to fetch vault token accounts: {}", e)))?;
+                mints.extend(accounts.into_iter().map(|account| {
+                    account.and_then(|account| token_account_mint(&account.data))
+                }));
+            }
+            Ok(mints)
+        })
+        .await
+        .map_err(|e| Error::SolanaClient(format!("Task join error: {}", e)))?
     }
 }
 
+// Per-mint totals; raw amounts of different mints are never added together
+fn summarize(vaults: &[VaultInfo], mints: &[Option<Pubkey>]) -> VaultScanSummary {
+    let mut totals = BTreeMap::new();
+    for (vault, mint) in vaults.iter().zip(mints) {
+        let Some(mint) = mint else {
+            continue;
+        };
+        let entry = totals.entry(mint.to_string()).or_insert((0u64, 0u64, 0u64));
+        entry.0 += 1;
+        entry.1 = entry.1.saturating_add(vault.total_balance);
+        entry.2 = entry.2.saturating_add(vault.locked_balance);
+    }
+
+    VaultScanSummary {
+        total_vaults: vaults.len() as u64,
+        mints: totals
+            .into_iter()
+            .map(|(mint, (vault_count, tvl, locked))| MintScanTotals { mint, vault_count, tvl, locked })
+            .collect(),
+        timestamp: chrono::Utc::now().timestamp(),
+    }
+}
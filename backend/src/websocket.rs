@@ -1,42 +1,199 @@
 use axum::extract::ws::{Message, WebSocket};
 use futures_util::{SinkExt, StreamExt};
-use tokio::sync::broadcast;
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashSet;
+use std::str::FromStr;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{interval, Duration, Instant};
+use crate::models::{StreamEvent, StreamEventType, StreamRequest, StreamSubscription};
 
+// Events retained for slow clients before they start skipping
+const CHANNEL_CAPACITY: usize = 1024;
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+// A client that sends nothing, not even a pong, for this long is dropped
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(90);
+// Cap on vaults plus owners per connection
+const MAX_SUBSCRIPTIONS: usize = 256;
+
+/// Fans typed events out to `/ws` clients. Cheap to clone; every clone
+/// feeds the same channel.
+#[derive(Clone)]
 pub struct WebSocketManager {
-    tx: broadcast::Sender<String>,
+    tx: broadcast::Sender<StreamEvent>,
+}
+
+/// Replies to client messages and channel notices; events themselves are
+/// sent as `StreamEvent`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage<'a> {
+    Subscriptions {
+        vaults: Vec<&'a str>,
+        owners: Vec<&'a str>,
+        event_types: Vec<StreamEventType>,
+    },
+    Lagged { skipped: u64 },
+    Error { error: String },
+}
+
+// What one connection is subscribed to. TVL is not tied to a vault, so it
+// is only delivered when `tvl` is among the event types; vault events need
+// a matching vault or owner, or, when neither is given, any listed type.
+#[derive(Default)]
+struct Subscriptions {
+    vaults: HashSet<String>,
+    owners: HashSet<String>,
+    event_types: HashSet<StreamEventType>,
+}
+
+impl Subscriptions {
+    fn matches(&self, event: &StreamEvent) -> bool {
+        let event_type = event.event_type();
+        let activity = match event {
+            StreamEvent::Tvl(_) => return self.event_types.contains(&event_type),
+            StreamEvent::Deposit(activity)
+            | StreamEvent::Withdrawal(activity)
+            | StreamEvent::Lock(activity)
+            | StreamEvent::Unlock(activity)
+            | StreamEvent::Transfer(activity)
+            | StreamEvent::Fee(activity)
+            | StreamEvent::ShortfallCover(activity) => activity,
+            StreamEvent::Settlement(settlement) => &settlement.activity,
+        };
+
+        if !self.event_types.is_empty() && !self.event_types.contains(&event_type) {
+            return false;
+        }
+        if self.vaults.is_empty() && self.owners.is_empty() {
+            return !self.event_types.is_empty();
+        }
+
+        let mut vaults = std::iter::once(&activity.vault).chain(&activity.counterparty_vault);
+        let mut owners = activity.owner.iter().chain(&activity.counterparty_owner);
+        vaults.any(|vault| self.vaults.contains(vault))
+            || owners.any(|owner| self.owners.contains(owner))
+    }
+
+    fn apply(&mut self, request: StreamRequest) -> std::result::Result<(), String> {
+        match request {
+            StreamRequest::Subscribe(subscription) => {
+                validate(&subscription)?;
+                if self.vaults.len() + self.owners.len()
+                    + subscription.vaults.len() + subscription.owners.len() > MAX_SUBSCRIPTIONS
+                {
+                    return Err(format!("At most {} vaults and owners per connection", MAX_SUBSCRIPTIONS));
+                }
+                self.vaults.extend(subscription.vaults);
+                self.owners.extend(subscription.owners);
+                self.event_types.extend(subscription.event_types);
+            }
+            // An unsubscribe that names nothing clears every subscription
+            StreamRequest::Unsubscribe(subscription) => {
+                if subscription.vaults.is_empty()
+                    && subscription.owners.is_empty()
+                    && subscription.event_types.is_empty()
+                {
+                    *self = Self::default();
+                }
+                for vault in &subscription.vaults {
+                    self.vaults.remove(vault);
+                }
+                for owner in &subscription.owners {
+                    self.owners.remove(owner);
+                }
+                for event_type in &subscription.event_types {
+                    self.event_types.remove(event_type);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn summary(&self) -> ControlMessage<'_> {
+        ControlMessage::Subscriptions {
+            vaults: self.vaults.iter().map(String::as_str).collect(),
+            owners: self.owners.iter().map(String::as_str).collect(),
+            event_types: self.event_types.iter().copied().collect(),
+        }
+    }
+}
+
+fn validate(subscription: &StreamSubscription) -> std::result::Result<(), String> {
+    for key in subscription.vaults.iter().chain(&subscription.owners) {
+        Pubkey::from_str(key).map_err(|e| format!("Invalid pubkey {}: {}", key, e))?;
+    }
+    Ok(())
+}
+
+fn text<T: Serialize>(message: &T) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap_or_default())
 }
 
 impl WebSocketManager {
     pub fn new() -> Self {
-        let (tx, _) = broadcast::channel(100);
+        let (tx, _) = broadcast::channel(CHANNEL_CAPACITY);
         Self { tx }
     }
 
-    pub fn broadcast(&self, event: &str) {
-        let _ = self.tx.send(event.to_string());
+    pub fn broadcast(&self, event: StreamEvent) {
+        // Fails only when no client is connected
+        let _ = self.tx.send(event);
     }
 
-    pub async fn handle_socket(ws: WebSocket, mut rx: broadcast::Receiver<String>) {
+    /// Serves one client until it disconnects or stops answering pings.
+    /// A client that falls more than the channel capacity behind skips to
+    /// the oldest retained event and is sent a `lagged` notice with the
+    /// number of events it missed, so it can resync over REST.
+    pub async fn handle_socket(&self, ws: WebSocket) {
+        let mut rx = self.tx.subscribe();
         let (mut sender, mut receiver) = ws.split();
+        let mut subscriptions = Subscriptions::default();
+        let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+
+        loop {
+            let outgoing = tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) if subscriptions.matches(&event) => text(&event),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => text(&ControlMessage::Lagged { skipped }),
+                    Err(RecvError::Closed) => break,
+                },
+                message = receiver.next() => {
+                    let Some(Ok(message)) = message else {
+                        break;
+                    };
+                    last_seen = Instant::now();
 
-        let mut send_task = tokio::spawn(async move {
-            while let Ok(msg) = rx.recv().await {
-                if sender.send(Message::Text(msg)).await.is_err() {
-                    break;
+                    match message {
+                        Message::Text(body) => {
+                            let result = serde_json::from_str::<StreamRequest>(&body)
+                                .map_err(|e| format!("Invalid message: {}", e))
+                                .and_then(|request| subscriptions.apply(request));
+                            match result {
+                                Ok(()) => text(&subscriptions.summary()),
+                                Err(error) => text(&ControlMessage::Error { error }),
+                            }
+                        }
+                        Message::Close(_) => break,
+                        _ => continue,
+                    }
                 }
-            }
-        });
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > CLIENT_TIMEOUT {
+                        tracing::debug!("Closing unresponsive WebSocket client");
+                        break;
+                    }
+                    Message::Ping(Vec::new())
+                }
+            };
 
-        let mut recv_task = tokio::spawn(async move {
-            while let Some(Ok(Message::Text(_))) = receiver.next().await {
-                // Handle incoming messages
+            if sender.send(outgoing).await.is_err() {
+                break;
             }
-        });
+        }
 
-        tokio::select! {
-            _ = (&mut send_task) => recv_task.abort(),
-            _ = (&mut recv_task) => send_task.abort(),
-        };
+        let _ = sender.close().await;
     }
 }
-
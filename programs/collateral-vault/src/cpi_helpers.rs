@@ -0,0 +1,457 @@
+//! Typed helpers for programs that CPI into the collateral vault.
+//!
+//! Integrators hand the helpers the accounts they received and the business
+//! parameters (vault owners, mint, amount). Every vault PDA and vault token
+//! account is derived here and looked up by address, so callers never build
+//! the `LockCollateral`/`UnlockCollateral`/`TransferCollateral` account sets
+//! themselves.
+//!
+//! ```ignore
+//! let accounts = ctx.remaining_accounts;
+//! let vault_cpi = VaultCpi::new(
+//!     ctx.accounts.collateral_vault_program.to_account_info(),
+//!     ctx.accounts.this_program.to_account_info(),
+//!     accounts,
+//! )?;
+//! let balances = cpi_helpers::lock(&vault_cpi, &trader, &mint, 1_000_000)?;
+//! ```
+
+use anchor_lang::prelude::*;
+use anchor_spl::associated_token::get_associated_token_address;
+
+use crate::cpi::accounts::{
+    BatchLockCollateral, BatchUnlockCollateral, CollectFee, CoverShortfall, LockCollateral,
+    LockSubAccountCollateral, SettleBatch, TransferCollateral, UnlockCollateral,
+    UnlockSubAccountCollateral,
+};
+use crate::{
+    ErrorCode, InsuranceFund, SubAccountBalances, TransferBalances, VaultBalances,
+    CALLER_AUTHORITY_SEED,
+};
+
+/// Address of the `CollateralVault` PDA owned by `owner`.
+pub fn vault_address(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"vault", owner.as_ref()], &crate::ID).0
+}
+
+/// Address of sub-account `index` of `owner`'s vault.
+pub fn sub_account_address(owner: &Pubkey, index: u8) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"sub_account", vault_address(owner).as_ref(), &[index]],
+        &crate::ID,
+    )
+    .0
+}
+
+/// Address of the global `VaultAuthority` PDA.
+pub fn vault_authority_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"vault_authority"], &crate::ID).0
+}
+
+/// Address of the global `ProtocolStats` PDA.
+pub fn protocol_stats_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"protocol_stats"], &crate::ID).0
+}
+
+/// Address of the `MintStats` PDA of `mint`.
+pub fn mint_stats_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"mint_stats", mint.as_ref()], &crate::ID).0
+}
+
+/// Address of the insurance fund for `mint`.
+pub fn insurance_fund_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"insurance_fund", mint.as_ref()], &crate::ID).0
+}
+
+/// Address of the PDA the vault program signs its `emit_cpi!` events with.
+pub fn event_authority_address() -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority"], &crate::ID).0
+}
+
+/// Address of the PDA `caller_program` signs privileged vault calls with.
+pub fn caller_authority_address(caller_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[CALLER_AUTHORITY_SEED], caller_program).0
+}
+
+/// Address of the vault's associated token account for `mint`.
+pub fn vault_token_account_address(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&vault_address(owner), mint)
+}
+
+/// Accounts shared by every vault CPI.
+///
+/// `accounts` must contain every vault, token account, mint and program the
+/// call touches, plus the vault program's event authority, the mint's stats
+/// and the caller's [`caller_authority_address`] PDA; the helpers pick out
+/// the ones they need by address. The helpers sign for the caller PDA, so
+/// they only work from inside `caller_program`.
+pub struct VaultCpi<'a, 'info> {
+    vault_program: AccountInfo<'info>,
+    caller_program: AccountInfo<'info>,
+    accounts: &'a [AccountInfo<'info>],
+}
+
+impl<'a, 'info> VaultCpi<'a, 'info> {
+    pub fn new(
+        vault_program: AccountInfo<'info>,
+        caller_program: AccountInfo<'info>,
+        accounts: &'a [AccountInfo<'info>],
+    ) -> Result<Self> {
+        require_keys_eq!(
+            *vault_program.key,
+            crate::ID,
+            anchor_lang::error::ErrorCode::InvalidProgramId
+        );
+
+        Ok(Self {
+            vault_program,
+            caller_program,
+            accounts,
+        })
+    }
+
+    fn account(&self, address: &Pubkey) -> Result<AccountInfo<'info>> {
+        self.accounts
+            .iter()
+            .find(|account| account.key == address)
+            .cloned()
+            .ok_or_else(|| error!(ErrorCode::MissingCpiAccount))
+    }
+
+    // The caller's `CALLER_AUTHORITY_SEED` PDA and its bump, for signing
+    fn caller_authority(&self) -> Result<(AccountInfo<'info>, u8)> {
+        let (address, bump) =
+            Pubkey::find_program_address(&[CALLER_AUTHORITY_SEED], self.caller_program.key);
+        Ok((self.account(&address)?, bump))
+    }
+
+    // Vaults of a batch call, in entry order, plus the amounts to pass along.
+    fn batch(
+        &self,
+        mint: &Pubkey,
+        entries: &[(Pubkey, u64)],
+    ) -> Result<(Vec<AccountInfo<'info>>, Vec<u64>)> {
+        let mut accounts = entries
+            .iter()
+            .map(|(owner, _)| self.account(&vault_address(owner)))
+            .collect::<Result<Vec<_>>>()?;
+        for (owner, _) in entries {
+            accounts.push(self.account(&vault_token_account_address(owner, mint))?);
+        }
+        let amounts = entries.iter().map(|(_, amount)| *amount).collect();
+
+        Ok((accounts, amounts))
+    }
+}
+
+/// Locks `amount` in `vault_owner`'s vault and returns the post-lock balances.
+pub fn lock(
+    ctx: &VaultCpi,
+    vault_owner: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<VaultBalances> {
+    let (caller_authority, bump) = ctx.caller_authority()?;
+    let balances = crate::cpi::lock_collateral(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            LockCollateral {
+                vault: ctx.account(&vault_address(vault_owner))?,
+                vault_token_account: ctx.account(&vault_token_account_address(vault_owner, mint))?,
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )?;
+
+    Ok(balances.get())
+}
+
+/// Unlocks `amount` in `vault_owner`'s vault and returns the post-unlock
+/// balances.
+pub fn unlock(
+    ctx: &VaultCpi,
+    vault_owner: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<VaultBalances> {
+    let (caller_authority, bump) = ctx.caller_authority()?;
+    let balances = crate::cpi::unlock_collateral(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            UnlockCollateral {
+                vault: ctx.account(&vault_address(vault_owner))?,
+                vault_token_account: ctx.account(&vault_token_account_address(vault_owner, mint))?,
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )?;
+
+    Ok(balances.get())
+}
+
+/// Moves `amount` of available `mint` collateral from `from_owner`'s vault to
+/// `to_owner`'s vault and returns the balances of both vaults.
+pub fn transfer(
+    ctx: &VaultCpi,
+    from_owner: &Pubkey,
+    to_owner: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<TransferBalances> {
+    let from_vault = ctx.account(&vault_address(from_owner))?;
+    let to_vault = ctx.account(&vault_address(to_owner))?;
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    let balances = crate::cpi::transfer_collateral(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            TransferCollateral {
+                from_vault: from_vault.clone(),
+                to_vault: to_vault.clone(),
+                from_vault_token_account: ctx
+                    .account(&vault_token_account_address(from_owner, mint))?,
+                to_vault_token_account: ctx.account(&vault_token_account_address(to_owner, mint))?,
+                mint: ctx.account(mint)?,
+                // Vault PDAs sign for their own token accounts.
+                to_vault_authority: to_vault,
+                from_vault_authority: from_vault,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                token_program: ctx.account(&anchor_spl::token::ID)?,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )?;
+
+    Ok(balances.get())
+}
+
+/// Locks `amount` in sub-account `index` of `vault_owner`'s vault and returns
+/// the sub-account's post-lock balances.
+pub fn lock_sub_account(
+    ctx: &VaultCpi,
+    vault_owner: &Pubkey,
+    mint: &Pubkey,
+    index: u8,
+    amount: u64,
+) -> Result<SubAccountBalances> {
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    let balances = crate::cpi::lock_sub_account_collateral(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            LockSubAccountCollateral {
+                vault: ctx.account(&vault_address(vault_owner))?,
+                vault_token_account: ctx.account(&vault_token_account_address(vault_owner, mint))?,
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                sub_account: ctx.account(&sub_account_address(vault_owner, index))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )?;
+
+    Ok(balances.get())
+}
+
+/// Unlocks `amount` in sub-account `index` of `vault_owner`'s vault and
+/// returns the sub-account's post-unlock balances.
+pub fn unlock_sub_account(
+    ctx: &VaultCpi,
+    vault_owner: &Pubkey,
+    mint: &Pubkey,
+    index: u8,
+    amount: u64,
+) -> Result<SubAccountBalances> {
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    let balances = crate::cpi::unlock_sub_account_collateral(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            UnlockSubAccountCollateral {
+                vault: ctx.account(&vault_address(vault_owner))?,
+                vault_token_account: ctx.account(&vault_token_account_address(vault_owner, mint))?,
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                sub_account: ctx.account(&sub_account_address(vault_owner, index))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )?;
+
+    Ok(balances.get())
+}
+
+/// Locks `mint` collateral in several vaults in one call. `entries` pairs each
+/// vault owner with the amount to lock; the whole batch fails if any entry
+/// does.
+pub fn batch_lock(ctx: &VaultCpi, mint: &Pubkey, entries: &[(Pubkey, u64)]) -> Result<()> {
+    let (accounts, amounts) = ctx.batch(mint, entries)?;
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    crate::cpi::batch_lock(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            BatchLockCollateral {
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        )
+        .with_remaining_accounts(accounts),
+        amounts,
+    )
+}
+
+/// Unlocks collateral in several vaults in one call; see [`batch_lock`].
+pub fn batch_unlock(ctx: &VaultCpi, mint: &Pubkey, entries: &[(Pubkey, u64)]) -> Result<()> {
+    let (accounts, amounts) = ctx.batch(mint, entries)?;
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    crate::cpi::batch_unlock(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            BatchUnlockCollateral {
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        )
+        .with_remaining_accounts(accounts),
+        amounts,
+    )
+}
+
+/// Applies signed `mint` balance changes to several vaults in one call.
+/// `entries` pairs each vault owner with its delta; the deltas must net to
+/// zero.
+pub fn settle_batch(ctx: &VaultCpi, mint: &Pubkey, entries: &[(Pubkey, i64)]) -> Result<()> {
+    let mut accounts = entries
+        .iter()
+        .map(|(owner, _)| ctx.account(&vault_address(owner)))
+        .collect::<Result<Vec<_>>>()?;
+    for (owner, _) in entries {
+        accounts.push(ctx.account(&vault_token_account_address(owner, mint))?);
+    }
+    let deltas = entries.iter().map(|(_, delta)| *delta).collect();
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    crate::cpi::settle_batch(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            SettleBatch {
+                mint: ctx.account(mint)?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                token_program: ctx.account(&anchor_spl::token::ID)?,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        )
+        .with_remaining_accounts(accounts),
+        deltas,
+    )
+}
+
+/// Charges a protocol fee of `amount` against `vault_owner`'s vault. The
+/// fund's treasury is read from the insurance fund account.
+pub fn collect_fee(ctx: &VaultCpi, vault_owner: &Pubkey, mint: &Pubkey, amount: u64) -> Result<()> {
+    let insurance_fund = ctx.account(&insurance_fund_address(mint))?;
+    let fund = InsuranceFund::try_deserialize(&mut &insurance_fund.try_borrow_data()?[..])?;
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    crate::cpi::collect_fee(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            CollectFee {
+                vault: ctx.account(&vault_address(vault_owner))?,
+                vault_token_account: ctx.account(&vault_token_account_address(vault_owner, mint))?,
+                mint: ctx.account(mint)?,
+                insurance_fund,
+                insurance_fund_token_account: ctx.account(&fund.token_account)?,
+                treasury: ctx.account(&fund.treasury)?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                token_program: ctx.account(&anchor_spl::token::ID)?,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )
+}
+
+/// Pays `amount` from the `mint` insurance fund into `vault_owner`'s vault.
+pub fn cover_shortfall(
+    ctx: &VaultCpi,
+    vault_owner: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let fund_address = insurance_fund_address(mint);
+    let (caller_authority, bump) = ctx.caller_authority()?;
+
+    crate::cpi::cover_shortfall(
+        CpiContext::new_with_signer(
+            ctx.vault_program.clone(),
+            CoverShortfall {
+                vault: ctx.account(&vault_address(vault_owner))?,
+                vault_token_account: ctx.account(&vault_token_account_address(vault_owner, mint))?,
+                mint: ctx.account(mint)?,
+                insurance_fund: ctx.account(&fund_address)?,
+                insurance_fund_token_account: ctx
+                    .account(&get_associated_token_address(&fund_address, mint))?,
+                vault_authority: ctx.account(&vault_authority_address())?,
+                mint_stats: ctx.account(&mint_stats_address(mint))?,
+                caller_program: ctx.caller_program.clone(),
+                caller_authority,
+                token_program: ctx.account(&anchor_spl::token::ID)?,
+                event_authority: ctx.account(&event_authority_address())?,
+                program: ctx.vault_program.clone(),
+            },
+            &[&[CALLER_AUTHORITY_SEED, &[bump]]],
+        ),
+        amount,
+    )
+}
@@ -1,7 +1,14 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::system_program;
+use anchor_spl::token::{self, CloseAccount, Mint, SyncNative, Token, TokenAccount, Transfer};
 use anchor_spl::associated_token::AssociatedToken;
 
+#[cfg(feature = "cpi")]
+pub mod cpi_helpers;
+pub mod oracle;
+
+use oracle::PriceData;
+
 declare_id!("8vjbjPhoD2rav71J8mgbVxcYdbbqST78y2bzMPRqoGr9");
 
 #[program]
@@ -20,19 +27,29 @@ pub mod collateral_vault {
         vault.total_deposited = 0;
         vault.total_withdrawn = 0;
         vault.created_at = clock.unix_timestamp;
+        vault.sequence = 0;
+        vault.sub_account_balance = 0;
         vault.bump = ctx.bumps.vault;
 
-        emit!(VaultInitialized {
+        ctx.accounts.mint_stats.record_vault()?;
+
+        emit_cpi!(VaultInitialized {
             user: ctx.accounts.user.key(),
             vault: vault.key(),
+            sequence: vault.sequence,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<VaultBalances> {
         require!(amount > 0, ErrorCode::InvalidAmount);
+        ctx.accounts.vault.authorize(
+            ctx.accounts.user.key(),
+            ctx.accounts.vault_delegate.as_deref(),
+            DELEGATE_DEPOSIT,
+        )?;
 
         // Transfer USDT from user to vault using CPI
         token::transfer(
@@ -49,35 +66,36 @@ pub mod collateral_vault {
 
         // Update vault state
         let vault = &mut ctx.accounts.vault;
-        vault.total_balance = vault
-            .total_balance
-            .checked_add(amount)
-            .ok_or(ErrorCode::Overflow)?;
-        vault.available_balance = vault
-            .available_balance
-            .checked_add(amount)
-            .ok_or(ErrorCode::Overflow)?;
-        vault.total_deposited = vault
-            .total_deposited
-            .checked_add(amount)
-            .ok_or(ErrorCode::Overflow)?;
+        vault.record_deposit(amount)?;
+        ctx.accounts.mint_stats.record_deposit(amount)?;
+        ctx.accounts
+            .mint_stats
+            .enforce_deposit_caps(vault.total_balance)?;
 
         let clock = Clock::get()?;
-        emit!(DepositEvent {
-            user: ctx.accounts.user.key(),
+        emit_cpi!(DepositEvent {
+            user: vault.owner,
             vault: vault.key(),
             amount,
             new_balance: vault.total_balance,
+            sequence: vault.sequence,
             timestamp: clock.unix_timestamp,
         });
 
-        Ok(())
+        Ok(VaultBalances::from(&**vault))
     }
 
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         require!(amount > 0, ErrorCode::InvalidAmount);
 
+        let user = ctx.accounts.user.key();
         let vault = &mut ctx.accounts.vault;
+        vault.authorize(user, ctx.accounts.vault_delegate.as_deref(), DELEGATE_WITHDRAW)?;
+        // Delegates may only pay out to the owner's own token accounts
+        require!(
+            user == vault.owner || ctx.accounts.user_token_account.owner == vault.owner,
+            ErrorCode::InvalidWithdrawDestination
+        );
         require!(
             vault.available_balance >= amount,
             ErrorCode::InsufficientAvailableBalance
@@ -105,32 +123,123 @@ pub mod collateral_vault {
         )?;
 
         // Update vault state
-        vault.total_balance = vault
-            .total_balance
-            .checked_sub(amount)
-            .ok_or(ErrorCode::Underflow)?;
-        vault.available_balance = vault
-            .available_balance
-            .checked_sub(amount)
-            .ok_or(ErrorCode::Underflow)?;
-        vault.total_withdrawn = vault
-            .total_withdrawn
-            .checked_add(amount)
-            .ok_or(ErrorCode::Overflow)?;
+        vault.record_withdrawal(amount)?;
+        ctx.accounts.mint_stats.record_withdrawal(amount)?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(WithdrawEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            amount,
+            new_balance: vault.total_balance,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn deposit_sol(ctx: Context<DepositSol>, amount: u64) -> Result<VaultBalances> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Move lamports into the vault's WSOL account, then sync so the token
+        // amount reflects them
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: ctx.accounts.vault_token_account.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        token::sync_native(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            SyncNative {
+                account: ctx.accounts.vault_token_account.to_account_info(),
+            },
+        ))?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.record_deposit(amount)?;
+        ctx.accounts.mint_stats.record_deposit(amount)?;
+        ctx.accounts
+            .mint_stats
+            .enforce_deposit_caps(vault.total_balance)?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(DepositEvent {
+            user: ctx.accounts.user.key(),
+            vault: vault.key(),
+            amount,
+            new_balance: vault.total_balance,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(VaultBalances::from(&**vault))
+    }
+
+    pub fn withdraw_sol(ctx: Context<WithdrawSol>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            vault.available_balance >= amount,
+            ErrorCode::InsufficientAvailableBalance
+        );
+
+        let seeds = &[
+            b"vault",
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        // A WSOL account only releases lamports when closed, so move the
+        // amount into a scratch WSOL account and close that to the user
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault_token_account.to_account_info(),
+                    to: ctx.accounts.unwrap_account.to_account_info(),
+                    authority: vault.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+
+        token::close_account(CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            CloseAccount {
+                account: ctx.accounts.unwrap_account.to_account_info(),
+                destination: ctx.accounts.user.to_account_info(),
+                authority: vault.to_account_info(),
+            },
+            signer,
+        ))?;
+
+        vault.record_withdrawal(amount)?;
+        ctx.accounts.mint_stats.record_withdrawal(amount)?;
 
         let clock = Clock::get()?;
-        emit!(WithdrawEvent {
+        emit_cpi!(WithdrawEvent {
             user: ctx.accounts.user.key(),
             vault: vault.key(),
             amount,
             new_balance: vault.total_balance,
+            sequence: vault.sequence,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
-    pub fn lock_collateral(ctx: Context<LockCollateral>, amount: u64) -> Result<()> {
+    pub fn lock_collateral(ctx: Context<LockCollateral>, amount: u64) -> Result<VaultBalances> {
         require!(amount > 0, ErrorCode::InvalidAmount);
 
         let vault = &mut ctx.accounts.vault;
@@ -146,28 +255,26 @@ pub mod collateral_vault {
             ErrorCode::UnauthorizedProgram
         );
 
-        vault.locked_balance = vault
-            .locked_balance
-            .checked_add(amount)
-            .ok_or(ErrorCode::Overflow)?;
-        vault.available_balance = vault
-            .available_balance
-            .checked_sub(amount)
-            .ok_or(ErrorCode::Underflow)?;
+        vault.record_lock(amount)?;
+        ctx.accounts.mint_stats.record_lock(amount)?;
 
         let clock = Clock::get()?;
-        emit!(LockEvent {
+        emit_cpi!(LockEvent {
             user: vault.owner,
             vault: vault.key(),
             amount,
             locked_balance: vault.locked_balance,
+            sequence: vault.sequence,
             timestamp: clock.unix_timestamp,
         });
 
-        Ok(())
+        Ok(VaultBalances::from(&**vault))
     }
 
-    pub fn unlock_collateral(ctx: Context<UnlockCollateral>, amount: u64) -> Result<()> {
+    pub fn unlock_collateral(
+        ctx: Context<UnlockCollateral>,
+        amount: u64,
+    ) -> Result<VaultBalances> {
         require!(amount > 0, ErrorCode::InvalidAmount);
 
         let vault = &mut ctx.accounts.vault;
@@ -183,31 +290,26 @@ pub mod collateral_vault {
             ErrorCode::UnauthorizedProgram
         );
 
-        vault.locked_balance = vault
-            .locked_balance
-            .checked_sub(amount)
-            .ok_or(ErrorCode::Underflow)?;
-        vault.available_balance = vault
-            .available_balance
-            .checked_add(amount)
-            .ok_or(ErrorCode::Overflow)?;
+        vault.record_unlock(amount)?;
+        ctx.accounts.mint_stats.record_unlock(amount)?;
 
         let clock = Clock::get()?;
-        emit!(UnlockEvent {
+        emit_cpi!(UnlockEvent {
             user: vault.owner,
             vault: vault.key(),
             amount,
             locked_balance: vault.locked_balance,
+            sequence: vault.sequence,
             timestamp: clock.unix_timestamp,
         });
 
-        Ok(())
+        Ok(VaultBalances::from(&**vault))
     }
 
     pub fn transfer_collateral(
         ctx: Context<TransferCollateral>,
         amount: u64,
-    ) -> Result<()> {
+    ) -> Result<TransferBalances> {
         require!(amount > 0, ErrorCode::InvalidAmount);
 
         let from_vault = &mut ctx.accounts.from_vault;
@@ -266,157 +368,1779 @@ pub mod collateral_vault {
             .checked_add(amount)
             .ok_or(ErrorCode::Overflow)?;
 
+        from_vault.advance_sequence()?;
+        to_vault.advance_sequence()?;
+
         let clock = Clock::get()?;
-        emit!(TransferEvent {
+        emit_cpi!(TransferEvent {
             from_user: from_vault.owner,
             to_user: to_vault.owner,
             from_vault: from_vault.key(),
             to_vault: to_vault.key(),
             amount,
+            from_sequence: from_vault.sequence,
+            to_sequence: to_vault.sequence,
             timestamp: clock.unix_timestamp,
         });
 
-        Ok(())
+        Ok(TransferBalances {
+            from_vault: VaultBalances::from(&**from_vault),
+            to_vault: VaultBalances::from(&**to_vault),
+        })
     }
 
-    pub fn initialize_vault_authority(
-        ctx: Context<InitializeVaultAuthority>,
-        authorized_programs: Vec<Pubkey>,
+    /// Locks collateral in many vaults of one mint at once.
+    /// `remaining_accounts` holds the vaults (writable) in the same order as
+    /// `amounts`, followed by their token accounts. Entries are checked
+    /// and applied one vault at a time; a failing entry aborts the
+    /// transaction, which rolls back the vaults already written, so the batch
+    /// still succeeds or fails as a whole. The caller program must sign with
+    /// its `CALLER_AUTHORITY_SEED` PDA.
+    pub fn batch_lock<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchLockCollateral<'info>>,
+        amounts: Vec<u64>,
     ) -> Result<()> {
-        let vault_authority = &mut ctx.accounts.vault_authority;
-        vault_authority.authorized_programs = authorized_programs;
-        vault_authority.bump = ctx.bumps.vault_authority;
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
+
+        require!(
+            ctx.remaining_accounts.len() == amounts.len() * 2,
+            ErrorCode::InvalidRemainingAccounts
+        );
+        let (vault_infos, token_infos) = ctx.remaining_accounts.split_at(amounts.len());
+        let mut vaults = load_batch_vaults(vault_infos, amounts.len())?;
+        check_batch_token_accounts(&vaults, token_infos, &ctx.accounts.mint_stats.mint)?;
+
+        let mut entries = Vec::with_capacity(vaults.len());
+        let mut total_amount: u64 = 0;
+
+        for (vault, amount) in vaults.iter_mut().zip(amounts) {
+            require!(amount > 0, ErrorCode::InvalidAmount);
+            require!(
+                vault.available_balance >= amount,
+                ErrorCode::InsufficientAvailableBalance
+            );
+
+            vault.record_lock(amount)?;
+            total_amount = total_amount.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+            entries.push(BatchEntry {
+                vault: vault.key(),
+                amount,
+                locked_balance: vault.locked_balance,
+                sequence: vault.sequence,
+            });
+            vault.exit(&crate::ID)?;
+        }
+        ctx.accounts.mint_stats.record_lock(total_amount)?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(BatchLockEvent {
+            caller_program: ctx.accounts.caller_program.key(),
+            total_amount,
+            entries,
+            timestamp: clock.unix_timestamp,
+        });
 
         Ok(())
     }
-}
 
-#[account]
-pub struct CollateralVault {
-    pub owner: Pubkey,
-    pub token_account: Pubkey,
-    pub total_balance: u64,
-    pub locked_balance: u64,
-    pub available_balance: u64,
-    pub total_deposited: u64,
-    pub total_withdrawn: u64,
-    pub created_at: i64,
-    pub bump: u8,
-}
+    /// Unlocks collateral in many vaults at once; see `batch_lock`.
+    pub fn batch_unlock<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchUnlockCollateral<'info>>,
+        amounts: Vec<u64>,
+    ) -> Result<()> {
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
 
-#[account]
-pub struct VaultAuthority {
-    pub authorized_programs: Vec<Pubkey>,
-    pub bump: u8,
-}
+        require!(
+            ctx.remaining_accounts.len() == amounts.len() * 2,
+            ErrorCode::InvalidRemainingAccounts
+        );
+        let (vault_infos, token_infos) = ctx.remaining_accounts.split_at(amounts.len());
+        let mut vaults = load_batch_vaults(vault_infos, amounts.len())?;
+        check_batch_token_accounts(&vaults, token_infos, &ctx.accounts.mint_stats.mint)?;
+
+        let mut entries = Vec::with_capacity(vaults.len());
+        let mut total_amount: u64 = 0;
+
+        for (vault, amount) in vaults.iter_mut().zip(amounts) {
+            require!(amount > 0, ErrorCode::InvalidAmount);
+            require!(
+                vault.locked_balance >= amount,
+                ErrorCode::InsufficientLockedBalance
+            );
+
+            vault.record_unlock(amount)?;
+            total_amount = total_amount.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+            entries.push(BatchEntry {
+                vault: vault.key(),
+                amount,
+                locked_balance: vault.locked_balance,
+                sequence: vault.sequence,
+            });
+            vault.exit(&crate::ID)?;
+        }
+        ctx.accounts.mint_stats.record_unlock(total_amount)?;
 
-#[derive(Accounts)]
-pub struct InitializeVault<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+        let clock = Clock::get()?;
+        emit_cpi!(BatchUnlockEvent {
+            caller_program: ctx.accounts.caller_program.key(),
+            total_amount,
+            entries,
+            timestamp: clock.unix_timestamp,
+        });
 
-    #[account(
-        init,
-        payer = user,
-        space = 8 + CollateralVault::LEN,
-        seeds = [b"vault", user.key().as_ref()],
-        bump
-    )]
-    pub vault: Account<'info, CollateralVault>,
+        Ok(())
+    }
 
-    #[account(
-        init,
-        payer = user,
-        associated_token::mint = mint,
-        associated_token::authority = vault_authority_pda
-    )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+    /// Settles many vault-to-vault obligations at once. `deltas[i]` is the
+    /// signed change to vault `i`'s available balance and the deltas must sum
+    /// to zero. `remaining_accounts` holds the N vaults followed by their N
+    /// token accounts, all of `mint`. Payers are matched greedily against
+    /// receivers, so at most N - 1 token transfers are made. The caller
+    /// program must sign with its `CALLER_AUTHORITY_SEED` PDA.
+    pub fn settle_batch<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleBatch<'info>>,
+        deltas: Vec<i64>,
+    ) -> Result<()> {
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
 
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+        require!(
+            ctx.remaining_accounts.len() == deltas.len() * 2,
+            ErrorCode::InvalidRemainingAccounts
+        );
+        let (vault_infos, token_infos) = ctx.remaining_accounts.split_at(deltas.len());
+        let mut vaults = load_batch_vaults(vault_infos, deltas.len())?;
+
+        for info in token_infos {
+            require!(info.is_writable, anchor_lang::error::ErrorCode::ConstraintMut);
+        }
+        check_batch_token_accounts(&vaults, token_infos, &ctx.accounts.mint.key())?;
+
+        let net = deltas
+            .iter()
+            .try_fold(0i128, |sum, delta| {
+                require!(*delta != 0, ErrorCode::InvalidAmount);
+                Ok(sum + i128::from(*delta))
+            })?;
+        require!(net == 0, ErrorCode::SettlementNotBalanced);
+
+        let mut entries = Vec::with_capacity(vaults.len());
+        for (vault, delta) in vaults.iter_mut().zip(&deltas) {
+            let amount = delta.unsigned_abs();
+            if *delta < 0 {
+                require!(
+                    vault.available_balance >= amount,
+                    ErrorCode::InsufficientAvailableBalance
+                );
+                vault.total_balance = vault
+                    .total_balance
+                    .checked_sub(amount)
+                    .ok_or(ErrorCode::Underflow)?;
+                vault.available_balance = vault
+                    .available_balance
+                    .checked_sub(amount)
+                    .ok_or(ErrorCode::Underflow)?;
+            } else {
+                vault.total_balance = vault
+                    .total_balance
+                    .checked_add(amount)
+                    .ok_or(ErrorCode::Overflow)?;
+                vault.available_balance = vault
+                    .available_balance
+                    .checked_add(amount)
+                    .ok_or(ErrorCode::Overflow)?;
+            }
+            vault.advance_sequence()?;
+
+            entries.push(SettlementEntry {
+                vault: vault.key(),
+                delta: *delta,
+                total_balance: vault.total_balance,
+                sequence: vault.sequence,
+            });
+        }
+
+        let transfers = plan_settlement_transfers(&deltas);
+        for &(from, to, amount) in &transfers {
+            let payer = &vaults[from];
+            let seeds = &[b"vault", payer.owner.as_ref(), &[payer.bump]];
+            let signer = &[&seeds[..]];
+
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: token_infos[from].clone(),
+                        to: token_infos[to].clone(),
+                        authority: vault_infos[from].clone(),
+                    },
+                    signer,
+                ),
+                amount,
+            )?;
+        }
+
+        for vault in &vaults {
+            vault.exit(&crate::ID)?;
+        }
 
-    #[account(
-        seeds = [b"vault", user.key().as_ref()],
-        bump
-    )]
-    /// CHECK: PDA authority for vault token account
-    pub vault_authority_pda: AccountInfo<'info>,
+        let clock = Clock::get()?;
+        emit_cpi!(SettlementEvent {
+            caller_program: ctx.accounts.caller_program.key(),
+            mint: ctx.accounts.mint.key(),
+            entries,
+            transfer_count: transfers.len() as u32,
+            timestamp: clock.unix_timestamp,
+        });
 
-    #[account(
-        seeds = [b"vault_authority"],
-        bump
-    )]
-    pub vault_authority: Account<'info, VaultAuthority>,
+        Ok(())
+    }
 
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
-}
+    /// Lets `delegate` act on the owner's vault with the given permission
+    /// bits (`DELEGATE_*`) until `expires_at` (0 = no expiry).
+    pub fn add_delegate(
+        ctx: Context<AddDelegate>,
+        delegate: Pubkey,
+        permissions: u8,
+        expires_at: i64,
+    ) -> Result<()> {
+        VaultDelegate::validate(permissions)?;
 
-#[derive(Accounts)]
-pub struct Deposit<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+        let vault_delegate = &mut ctx.accounts.vault_delegate;
+        vault_delegate.vault = ctx.accounts.vault.key();
+        vault_delegate.delegate = delegate;
+        vault_delegate.permissions = permissions;
+        vault_delegate.expires_at = expires_at;
+        vault_delegate.bump = ctx.bumps.vault_delegate;
 
-    #[account(
-        mut,
-        seeds = [b"vault", user.key().as_ref()],
-        bump = vault.bump,
-    )]
-    pub vault: Account<'info, CollateralVault>,
+        Ok(())
+    }
 
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+    pub fn update_delegate(
+        ctx: Context<UpdateDelegate>,
+        permissions: u8,
+        expires_at: i64,
+    ) -> Result<()> {
+        VaultDelegate::validate(permissions)?;
 
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = vault_authority
-    )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+        let vault_delegate = &mut ctx.accounts.vault_delegate;
+        vault_delegate.permissions = permissions;
+        vault_delegate.expires_at = expires_at;
 
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+        Ok(())
+    }
 
-    #[account(
-        seeds = [b"vault", user.key().as_ref()],
-        bump = vault.bump
-    )]
-    /// CHECK: PDA authority for vault token account
-    pub vault_authority: AccountInfo<'info>,
+    /// Removes a delegate and returns its rent to the owner.
+    pub fn revoke_delegate(_ctx: Context<RevokeDelegate>) -> Result<()> {
+        Ok(())
+    }
 
-    pub token_program: Program<'info, Token>,
-}
+    pub fn create_sub_account(ctx: Context<CreateSubAccount>, index: u8) -> Result<()> {
+        let sub_account = &mut ctx.accounts.sub_account;
+        sub_account.vault = ctx.accounts.vault.key();
+        sub_account.index = index;
+        sub_account.available_balance = 0;
+        sub_account.locked_balance = 0;
+        sub_account.bump = ctx.bumps.sub_account;
 
-#[derive(Accounts)]
-pub struct Withdraw<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [b"vault", user.key().as_ref()],
+    /// Moves available collateral from the vault's main book into a
+    /// sub-account.
+    pub fn allocate_to_sub_account(
+        ctx: Context<AllocateToSubAccount>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.authorize(
+            ctx.accounts.user.key(),
+            ctx.accounts.vault_delegate.as_deref(),
+            DELEGATE_TRANSFER,
+        )?;
+        let sub_account = &mut ctx.accounts.sub_account;
+        require!(
+            vault.available_balance >= amount,
+            ErrorCode::InsufficientAvailableBalance
+        );
+
+        vault.available_balance = vault
+            .available_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        vault.sub_account_balance = vault
+            .sub_account_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        sub_account.credit_available(amount)?;
+        vault.advance_sequence()?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(SubAccountTransferEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            from_index: None,
+            to_index: Some(sub_account.index),
+            amount,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Moves available collateral from a sub-account back to the vault's
+    /// main book.
+    pub fn release_from_sub_account(
+        ctx: Context<ReleaseFromSubAccount>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        let vault = &mut ctx.accounts.vault;
+        vault.authorize(
+            ctx.accounts.user.key(),
+            ctx.accounts.vault_delegate.as_deref(),
+            DELEGATE_TRANSFER,
+        )?;
+        let sub_account = &mut ctx.accounts.sub_account;
+        sub_account.debit_available(amount)?;
+
+        vault.sub_account_balance = vault
+            .sub_account_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        vault.available_balance = vault
+            .available_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vault.advance_sequence()?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(SubAccountTransferEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            from_index: Some(sub_account.index),
+            to_index: None,
+            amount,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn transfer_between_sub_accounts(
+        ctx: Context<TransferBetweenSubAccounts>,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        ctx.accounts.vault.authorize(
+            ctx.accounts.user.key(),
+            ctx.accounts.vault_delegate.as_deref(),
+            DELEGATE_TRANSFER,
+        )?;
+
+        let from_sub_account = &mut ctx.accounts.from_sub_account;
+        let to_sub_account = &mut ctx.accounts.to_sub_account;
+        require!(
+            from_sub_account.index != to_sub_account.index,
+            ErrorCode::SameSubAccount
+        );
+
+        from_sub_account.debit_available(amount)?;
+        to_sub_account.credit_available(amount)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.advance_sequence()?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(SubAccountTransferEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            from_index: Some(from_sub_account.index),
+            to_index: Some(to_sub_account.index),
+            amount,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn lock_sub_account_collateral(
+        ctx: Context<LockSubAccountCollateral>,
+        amount: u64,
+    ) -> Result<SubAccountBalances> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
+
+        let sub_account = &mut ctx.accounts.sub_account;
+        sub_account.debit_available(amount)?;
+        sub_account.locked_balance = sub_account
+            .locked_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        ctx.accounts.mint_stats.record_lock(amount)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.advance_sequence()?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(SubAccountLockEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            index: sub_account.index,
+            amount,
+            locked_balance: sub_account.locked_balance,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(SubAccountBalances::new(sub_account, vault))
+    }
+
+    pub fn unlock_sub_account_collateral(
+        ctx: Context<UnlockSubAccountCollateral>,
+        amount: u64,
+    ) -> Result<SubAccountBalances> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
+
+        let sub_account = &mut ctx.accounts.sub_account;
+        require!(
+            sub_account.locked_balance >= amount,
+            ErrorCode::InsufficientLockedBalance
+        );
+        sub_account.locked_balance = sub_account
+            .locked_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        sub_account.credit_available(amount)?;
+        ctx.accounts.mint_stats.record_unlock(amount)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.advance_sequence()?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(SubAccountUnlockEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            index: sub_account.index,
+            amount,
+            locked_balance: sub_account.locked_balance,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(SubAccountBalances::new(sub_account, vault))
+    }
+
+    /// Adds `mint` to the allowlist, enabled, and creates its `MintStats`.
+    /// Vaults can only be created for, and deposits only made in, enabled
+    /// mints.
+    pub fn add_allowed_mint(ctx: Context<AddAllowedMint>) -> Result<()> {
+        let allowed_mint = &mut ctx.accounts.allowed_mint;
+        allowed_mint.mint = ctx.accounts.mint.key();
+        allowed_mint.enabled = true;
+        allowed_mint.bump = ctx.bumps.allowed_mint;
+
+        let mint_stats = &mut ctx.accounts.mint_stats;
+        mint_stats.mint = ctx.accounts.mint.key();
+        mint_stats.vault_count = 0;
+        mint_stats.total_deposited = 0;
+        mint_stats.total_withdrawn = 0;
+        mint_stats.tvl = 0;
+        mint_stats.total_locked = 0;
+        mint_stats.max_vault_balance = 0;
+        mint_stats.max_tvl = 0;
+        mint_stats.bump = ctx.bumps.mint_stats;
+
+        ctx.accounts
+            .protocol_stats
+            .register_mint(ctx.accounts.mint.key())?;
+
+        Ok(())
+    }
+
+    /// Enables or disables an allowlisted mint. A disabled mint is
+    /// withdraw-only: existing vaults can still withdraw, but no new vaults or
+    /// deposits are accepted.
+    pub fn set_mint_enabled(ctx: Context<SetMintEnabled>, enabled: bool) -> Result<()> {
+        ctx.accounts.allowed_mint.enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Sets the deposit caps of `mint`. A cap of 0 means unlimited.
+    pub fn set_deposit_caps(
+        ctx: Context<SetDepositCaps>,
+        _mint: Pubkey,
+        max_vault_balance: u64,
+        max_tvl: u64,
+    ) -> Result<()> {
+        let mint_stats = &mut ctx.accounts.mint_stats;
+        mint_stats.max_vault_balance = max_vault_balance;
+        mint_stats.max_tvl = max_tvl;
+
+        Ok(())
+    }
+
+    pub fn add_collateral_config(
+        ctx: Context<AddCollateralConfig>,
+        oracle: Pubkey,
+        haircut_bps: u16,
+        max_weight_bps: u16,
+        max_price_age: u32,
+    ) -> Result<()> {
+        CollateralConfig::validate(haircut_bps, max_weight_bps)?;
+
+        let config = &mut ctx.accounts.collateral_config;
+        config.mint = ctx.accounts.mint.key();
+        config.oracle = oracle;
+        config.decimals = ctx.accounts.mint.decimals;
+        config.haircut_bps = haircut_bps;
+        config.max_weight_bps = max_weight_bps;
+        config.max_price_age = max_price_age;
+        config.bump = ctx.bumps.collateral_config;
+
+        Ok(())
+    }
+
+    pub fn update_collateral_config(
+        ctx: Context<UpdateCollateralConfig>,
+        oracle: Pubkey,
+        haircut_bps: u16,
+        max_weight_bps: u16,
+        max_price_age: u32,
+    ) -> Result<()> {
+        CollateralConfig::validate(haircut_bps, max_weight_bps)?;
+
+        let config = &mut ctx.accounts.collateral_config;
+        config.oracle = oracle;
+        config.haircut_bps = haircut_bps;
+        config.max_weight_bps = max_weight_bps;
+        config.max_price_age = max_price_age;
+
+        Ok(())
+    }
+
+    /// Read-only valuation of a set of vaults in USD. `remaining_accounts`
+    /// holds one `(vault, vault_token_account, collateral_config, price)`
+    /// group per vault.
+    ///
+    /// Each vault's total balance is priced, reduced by its mint's haircut,
+    /// and then capped at `max_weight_bps` of the summed haircut value, so no
+    /// single mint can dominate the result.
+    pub fn compute_collateral_value(
+        ctx: Context<ComputeCollateralValue>,
+    ) -> Result<CollateralValuation> {
+        let accounts = ctx.remaining_accounts;
+        // `usize::is_multiple_of` is newer than the SBF toolchain's rustc
+        #[allow(clippy::manual_is_multiple_of)]
+        let whole_groups = accounts.len() % 4 == 0;
+        require!(
+            !accounts.is_empty() && whole_groups,
+            ErrorCode::InvalidRemainingAccounts
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let mut seen_vaults = Vec::with_capacity(accounts.len() / 4);
+        let mut entries: Vec<(Pubkey, u64, u16)> = Vec::with_capacity(accounts.len() / 4);
+        let mut gross_value: u64 = 0;
+        let mut haircut_value: u64 = 0;
+
+        for group in accounts.chunks(4) {
+            let vault: CollateralVault = load_account(&group[0])?;
+            let token_account: TokenAccount = load_account(&group[1])?;
+            let config: CollateralConfig = load_account(&group[2])?;
+            let price_account = &group[3];
+
+            require!(
+                !seen_vaults.contains(group[0].key),
+                ErrorCode::DuplicateCollateralAccount
+            );
+            seen_vaults.push(*group[0].key);
+
+            require_keys_eq!(
+                *group[1].key,
+                vault.token_account,
+                ErrorCode::CollateralConfigMismatch
+            );
+            require_keys_eq!(
+                config.mint,
+                token_account.mint,
+                ErrorCode::CollateralConfigMismatch
+            );
+            require_keys_eq!(price_account.key(), config.oracle, ErrorCode::OracleMismatch);
+
+            let price = PriceData::parse(&price_account.try_borrow_data()?)?;
+            if config.max_price_age > 0 {
+                require!(
+                    now.saturating_sub(price.publish_time) <= config.max_price_age as i64,
+                    ErrorCode::StalePrice
+                );
+            }
+
+            let value = price.value_of(vault.total_balance, config.decimals)?;
+            let after_haircut = apply_bps(value, BPS_DENOMINATOR - config.haircut_bps)?;
+
+            gross_value = gross_value.checked_add(value).ok_or(ErrorCode::Overflow)?;
+            haircut_value = haircut_value
+                .checked_add(after_haircut)
+                .ok_or(ErrorCode::Overflow)?;
+            // The weight cap applies to the mint, so vaults of the same mint
+            // are summed before it is taken
+            match entries.iter_mut().find(|(mint, _, _)| *mint == config.mint) {
+                Some((_, value, _)) => {
+                    *value = value.checked_add(after_haircut).ok_or(ErrorCode::Overflow)?;
+                }
+                None => entries.push((config.mint, after_haircut, config.max_weight_bps)),
+            }
+        }
+
+        let mut weighted_value: u64 = 0;
+        for (_, value, max_weight_bps) in entries {
+            let cap = apply_bps(haircut_value, max_weight_bps)?;
+            weighted_value = weighted_value
+                .checked_add(value.min(cap))
+                .ok_or(ErrorCode::Overflow)?;
+        }
+
+        Ok(CollateralValuation {
+            gross_value,
+            haircut_value,
+            weighted_value,
+        })
+    }
+
+    /// Creates the insurance fund for `mint`. `fee_share_bps` of every fee
+    /// taken with `collect_fee` goes to the fund; the rest goes to `treasury`.
+    pub fn initialize_insurance_fund(
+        ctx: Context<InitializeInsuranceFund>,
+        fee_share_bps: u16,
+    ) -> Result<()> {
+        require!(fee_share_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeShare);
+
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.mint = ctx.accounts.mint.key();
+        fund.token_account = ctx.accounts.insurance_fund_token_account.key();
+        fund.treasury = ctx.accounts.treasury.key();
+        fund.fee_share_bps = fee_share_bps;
+        fund.balance = 0;
+        fund.total_collected = 0;
+        fund.total_covered = 0;
+        fund.bump = ctx.bumps.insurance_fund;
+
+        Ok(())
+    }
+
+    pub fn set_insurance_fee_share(
+        ctx: Context<SetInsuranceFeeShare>,
+        fee_share_bps: u16,
+    ) -> Result<()> {
+        require!(fee_share_bps <= BPS_DENOMINATOR, ErrorCode::InvalidFeeShare);
+        ctx.accounts.insurance_fund.fee_share_bps = fee_share_bps;
+        Ok(())
+    }
+
+    /// Charges a protocol fee against a vault's available balance (called via
+    /// CPI). The insurance fund receives `fee_share_bps` of it and the
+    /// treasury the remainder. The caller program must sign with its
+    /// `CALLER_AUTHORITY_SEED` PDA.
+    pub fn collect_fee(ctx: Context<CollectFee>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
+
+        let vault = &mut ctx.accounts.vault;
+        require!(
+            vault.available_balance >= amount,
+            ErrorCode::InsufficientAvailableBalance
+        );
+
+        let fund = &mut ctx.accounts.insurance_fund;
+        let insurance_share = apply_bps(amount, fund.fee_share_bps)?;
+        let treasury_share = amount
+            .checked_sub(insurance_share)
+            .ok_or(ErrorCode::Underflow)?;
+
+        let seeds = &[
+            b"vault",
+            vault.owner.as_ref(),
+            &[vault.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        for (destination, share) in [
+            (ctx.accounts.insurance_fund_token_account.to_account_info(), insurance_share),
+            (ctx.accounts.treasury.to_account_info(), treasury_share),
+        ] {
+            if share == 0 {
+                continue;
+            }
+            token::transfer(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.vault_token_account.to_account_info(),
+                        to: destination,
+                        authority: vault.to_account_info(),
+                    },
+                    signer,
+                ),
+                share,
+            )?;
+        }
+
+        vault.total_balance = vault
+            .total_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        vault.available_balance = vault
+            .available_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        vault.advance_sequence()?;
+
+        fund.balance = fund
+            .balance
+            .checked_add(insurance_share)
+            .ok_or(ErrorCode::Overflow)?;
+        fund.total_collected = fund
+            .total_collected
+            .checked_add(insurance_share)
+            .ok_or(ErrorCode::Overflow)?;
+
+        ctx.accounts.mint_stats.record_withdrawal(amount)?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(FeeCollectedEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            amount,
+            insurance_share,
+            fund_balance: fund.balance,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Pays `amount` from the insurance fund into a vault to absorb bad debt
+    /// left by a liquidation (called via CPI). The caller program must sign
+    /// with its `CALLER_AUTHORITY_SEED` PDA.
+    pub fn cover_shortfall(ctx: Context<CoverShortfall>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidAmount);
+
+        // Verify caller is authorized program
+        let vault_authority = &ctx.accounts.vault_authority;
+        require!(
+            vault_authority.authorized_programs.contains(&ctx.accounts.caller_program.key()),
+            ErrorCode::UnauthorizedProgram
+        );
+
+        let fund = &mut ctx.accounts.insurance_fund;
+        require!(fund.balance >= amount, ErrorCode::InsufficientInsuranceFund);
+
+        let mint = ctx.accounts.mint.key();
+        let seeds = &[
+            b"insurance_fund",
+            mint.as_ref(),
+            &[fund.bump],
+        ];
+        let signer = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.insurance_fund_token_account.to_account_info(),
+                    to: ctx.accounts.vault_token_account.to_account_info(),
+                    authority: fund.to_account_info(),
+                },
+                signer,
+            ),
+            amount,
+        )?;
+
+        fund.balance = fund
+            .balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        fund.total_covered = fund
+            .total_covered
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let vault = &mut ctx.accounts.vault;
+        vault.total_balance = vault
+            .total_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vault.available_balance = vault
+            .available_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        vault.advance_sequence()?;
+
+        // Coverage restores collateral that was already counted, so deposit
+        // caps are not enforced here.
+        ctx.accounts.mint_stats.record_deposit(amount)?;
+
+        let clock = Clock::get()?;
+        emit_cpi!(ShortfallCoveredEvent {
+            user: vault.owner,
+            vault: vault.key(),
+            amount,
+            fund_balance: fund.balance,
+            sequence: vault.sequence,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_vault_authority(
+        ctx: Context<InitializeVaultAuthority>,
+        authorized_programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        let vault_authority = &mut ctx.accounts.vault_authority;
+        vault_authority.admin = ctx.accounts.admin.key();
+        vault_authority.authorized_programs = authorized_programs;
+        vault_authority.bump = ctx.bumps.vault_authority;
+
+        let protocol_stats = &mut ctx.accounts.protocol_stats;
+        protocol_stats.mints = Vec::new();
+        protocol_stats.bump = ctx.bumps.protocol_stats;
+
+        Ok(())
+    }
+}
+
+#[account]
+pub struct CollateralVault {
+    pub owner: Pubkey,
+    pub token_account: Pubkey,
+    pub total_balance: u64,
+    pub locked_balance: u64,
+    pub available_balance: u64,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub created_at: i64,
+    pub sequence: u64,
+    /// Collateral moved out of the main book into sub-accounts; always equals
+    /// `total_balance - available_balance - locked_balance`.
+    pub sub_account_balance: u64,
+    pub bump: u8,
+}
+
+/// Numbered partition of a vault's collateral for isolated margin. Shares the
+/// vault's token account; only the balances are tracked separately.
+#[account]
+pub struct SubAccount {
+    pub vault: Pubkey,
+    pub index: u8,
+    pub available_balance: u64,
+    pub locked_balance: u64,
+    pub bump: u8,
+}
+
+/// Post-instruction vault balances handed back to callers as return data, so
+/// CPI callers don't have to reload the vault account.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VaultBalances {
+    pub total_balance: u64,
+    pub locked_balance: u64,
+    pub available_balance: u64,
+    pub sequence: u64,
+}
+
+impl From<&CollateralVault> for VaultBalances {
+    fn from(vault: &CollateralVault) -> Self {
+        Self {
+            total_balance: vault.total_balance,
+            locked_balance: vault.locked_balance,
+            available_balance: vault.available_balance,
+            sequence: vault.sequence,
+        }
+    }
+}
+
+/// Return data of `transfer_collateral`: balances of both vaults involved.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransferBalances {
+    pub from_vault: VaultBalances,
+    pub to_vault: VaultBalances,
+}
+
+/// Return data of the sub-account lock/unlock CPIs. `sequence` is the parent
+/// vault's.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SubAccountBalances {
+    pub index: u8,
+    pub available_balance: u64,
+    pub locked_balance: u64,
+    pub sequence: u64,
+}
+
+impl SubAccountBalances {
+    fn new(sub_account: &SubAccount, vault: &CollateralVault) -> Self {
+        Self {
+            index: sub_account.index,
+            available_balance: sub_account.available_balance,
+            locked_balance: sub_account.locked_balance,
+            sequence: vault.sequence,
+        }
+    }
+}
+
+/// Return data of `compute_collateral_value`. All values are USD with
+/// `oracle::VALUE_DECIMALS` decimals.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollateralValuation {
+    /// Market value before haircuts
+    pub gross_value: u64,
+    /// Value after per-mint haircuts
+    pub haircut_value: u64,
+    /// Haircut value with each mint capped at its max weight
+    pub weighted_value: u64,
+}
+
+/// Per-mint valuation parameters, managed by the vault authority admin.
+#[account]
+pub struct CollateralConfig {
+    pub mint: Pubkey,
+    pub oracle: Pubkey,
+    pub decimals: u8,
+    pub haircut_bps: u16,
+    pub max_weight_bps: u16,
+    /// Maximum age of the oracle price in seconds; 0 disables the check
+    pub max_price_age: u32,
+    pub bump: u8,
+}
+
+/// Owner-granted permission for another key to act on a vault. Checked by
+/// `deposit`, `withdraw` and the sub-account moves when the signer is not
+/// the owner.
+#[account]
+pub struct VaultDelegate {
+    pub vault: Pubkey,
+    pub delegate: Pubkey,
+    /// Bitmask of `DELEGATE_*` permissions
+    pub permissions: u8,
+    /// Unix timestamp after which the delegate is ignored; 0 = no expiry
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+/// Program-owned reserve per mint that absorbs liquidation shortfalls. Its
+/// tokens sit in the fund PDA's associated token account and are not part of
+/// any vault's balance or the protocol TVL.
+#[account]
+pub struct InsuranceFund {
+    pub mint: Pubkey,
+    pub token_account: Pubkey,
+    /// Token account receiving the part of each fee not kept by the fund
+    pub treasury: Pubkey,
+    pub fee_share_bps: u16,
+    pub balance: u64,
+    pub total_collected: u64,
+    pub total_covered: u64,
+    pub bump: u8,
+}
+
+/// Registry of the mints with a `MintStats` account, so every shard can be
+/// found from one address. Only `add_allowed_mint` writes it; the totals live
+/// in the per-mint accounts so that vault operations on different mints never
+/// write the same account.
+#[account]
+pub struct ProtocolStats {
+    pub mints: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+/// Totals of one mint plus its admin-set deposit caps, in the mint's raw
+/// units; a cap of 0 means unlimited. Written by vault creation, deposits,
+/// withdrawals, fees and shortfall cover of that mint, and by locks and
+/// unlocks, main-book and sub-account, for `total_locked`. Transfers,
+/// settlements and moves between sub-accounts only draw on available
+/// balances, so they leave `total_locked` alone.
+#[account]
+pub struct MintStats {
+    pub mint: Pubkey,
+    pub vault_count: u64,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub tvl: u64,
+    /// Sum of the vaults' `locked_balance` and their sub-accounts'
+    pub total_locked: u64,
+    pub max_vault_balance: u64,
+    pub max_tvl: u64,
+    pub bump: u8,
+}
+
+/// Allowlist entry for a collateral mint.
+#[account]
+pub struct AllowedMint {
+    pub mint: Pubkey,
+    pub enabled: bool,
+    pub bump: u8,
+}
+
+#[account]
+pub struct VaultAuthority {
+    pub admin: Pubkey,
+    pub authorized_programs: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitializeVault<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + CollateralVault::LEN,
+        seeds = [b"vault", user.key().as_ref()],
+        bump
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority_pda
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(
+        seeds = [b"allowed_mint", mint.key().as_ref()],
+        bump = allowed_mint.bump,
+        constraint = allowed_mint.enabled @ ErrorCode::MintNotAllowed
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump
+    )]
+    /// CHECK: PDA authority for vault token account
+    pub vault_authority_pda: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    /// Vault owner or a delegate with `DELEGATE_DEPOSIT`
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = vault.token_account @ ErrorCode::VaultTokenAccountMismatch,
+        constraint = vault_token_account.mint == mint.key() @ ErrorCode::VaultTokenAccountMismatch,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(
+        seeds = [b"allowed_mint", mint.key().as_ref()],
+        bump = allowed_mint.bump,
+        constraint = allowed_mint.enabled @ ErrorCode::MintNotAllowed
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+
+    #[account(
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    /// CHECK: PDA authority for vault token account
+    pub vault_authority: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// Required when `user` is not the vault owner
+    #[account(
+        seeds = [b"delegate", vault.key().as_ref(), user.key().as_ref()],
+        bump = vault_delegate.bump
+    )]
+    pub vault_delegate: Option<Account<'info, VaultDelegate>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    /// Vault owner or a delegate with `DELEGATE_WITHDRAW`
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        address = vault.token_account @ ErrorCode::VaultTokenAccountMismatch,
+        constraint = vault_token_account.mint == mint.key() @ ErrorCode::VaultTokenAccountMismatch,
+        associated_token::mint = mint,
+        associated_token::authority = vault_authority_pda
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump
+    )]
+    /// CHECK: PDA authority for vault token account
+    pub vault_authority_pda: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    pub token_program: Program<'info, Token>,
+
+    /// Required when `user` is not the vault owner
+    #[account(
+        seeds = [b"delegate", vault.key().as_ref(), user.key().as_ref()],
+        bump = vault_delegate.bump
+    )]
+    pub vault_delegate: Option<Account<'info, VaultDelegate>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct DepositSol<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        address = vault.token_account @ ErrorCode::NotNativeVault,
+        associated_token::mint = native_mint,
+        associated_token::authority = vault
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(address = token::spl_token::native_mint::ID @ ErrorCode::NotNativeVault)]
+    pub native_mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"allowed_mint", native_mint.key().as_ref()],
+        bump = allowed_mint.bump,
+        constraint = allowed_mint.enabled @ ErrorCode::MintNotAllowed
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", native_mint.key().as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", user.key().as_ref()],
+        bump = vault.bump,
+        constraint = vault.owner == user.key() @ ErrorCode::UnauthorizedOwner
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        address = vault.token_account @ ErrorCode::NotNativeVault,
+        associated_token::mint = native_mint,
+        associated_token::authority = vault
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    /// Scratch WSOL account, created and closed within the instruction
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"unwrap", vault.key().as_ref()],
+        bump,
+        token::mint = native_mint,
+        token::authority = vault
+    )]
+    pub unwrap_account: Account<'info, TokenAccount>,
+
+    #[account(address = token::spl_token::native_mint::ID @ ErrorCode::NotNativeVault)]
+    pub native_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", native_mint.key().as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct LockCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    /// Identifies the vault's mint for `mint_stats`
+    #[account(address = vault.token_account)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", vault_token_account.mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UnlockCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    /// Identifies the vault's mint for `mint_stats`
+    #[account(address = vault.token_account)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", vault_token_account.mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct TransferCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", from_vault.owner.as_ref()],
+        bump = from_vault.bump,
+    )]
+    pub from_vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", to_vault.owner.as_ref()],
+        bump = to_vault.bump,
+    )]
+    pub to_vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = from_vault_authority
+    )]
+    pub from_vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = to_vault_authority
+    )]
+    pub to_vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(
+        seeds = [b"vault", to_vault.owner.as_ref()],
+        bump = to_vault.bump
+    )]
+    /// CHECK: PDA authority for to_vault
+    pub to_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"vault", from_vault.owner.as_ref()],
+        bump = from_vault.bump
+    )]
+    /// CHECK: PDA authority for from_vault
+    pub from_vault_authority: AccountInfo<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct BatchLockCollateral<'info> {
+    /// Stats of the mint every vault in the batch holds
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint_stats.mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct BatchUnlockCollateral<'info> {
+    /// Stats of the mint every vault in the batch holds
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint_stats.mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SettleBatch<'info> {
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct AddDelegate<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump = vault.bump,
+        constraint = vault.owner == user.key() @ ErrorCode::UnauthorizedOwner
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + VaultDelegate::LEN,
+        seeds = [b"delegate", vault.key().as_ref(), delegate.as_ref()],
+        bump
+    )]
+    pub vault_delegate: Account<'info, VaultDelegate>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDelegate<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump = vault.bump,
+        constraint = vault.owner == user.key() @ ErrorCode::UnauthorizedOwner
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        seeds = [b"delegate", vault.key().as_ref(), vault_delegate.delegate.as_ref()],
+        bump = vault_delegate.bump,
+        has_one = vault
+    )]
+    pub vault_delegate: Account<'info, VaultDelegate>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegate<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump = vault.bump,
+        constraint = vault.owner == user.key() @ ErrorCode::UnauthorizedOwner
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"delegate", vault.key().as_ref(), vault_delegate.delegate.as_ref()],
+        bump = vault_delegate.bump,
+        has_one = vault
+    )]
+    pub vault_delegate: Account<'info, VaultDelegate>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u8)]
+pub struct CreateSubAccount<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault", user.key().as_ref()],
+        bump = vault.bump,
+        constraint = vault.owner == user.key() @ ErrorCode::UnauthorizedOwner
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + SubAccount::LEN,
+        seeds = [b"sub_account", vault.key().as_ref(), &[index]],
+        bump
+    )]
+    pub sub_account: Account<'info, SubAccount>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AllocateToSubAccount<'info> {
+    /// Vault owner or a delegate with `DELEGATE_TRANSFER`
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
         bump = vault.bump,
-        constraint = vault.owner == user.key() @ ErrorCode::UnauthorizedOwner
     )]
     pub vault: Account<'info, CollateralVault>,
 
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"sub_account", vault.key().as_ref(), &[sub_account.index]],
+        bump = sub_account.bump,
+        has_one = vault
+    )]
+    pub sub_account: Account<'info, SubAccount>,
+
+    /// Required when `user` is not the vault owner
+    #[account(
+        seeds = [b"delegate", vault.key().as_ref(), user.key().as_ref()],
+        bump = vault_delegate.bump
+    )]
+    pub vault_delegate: Option<Account<'info, VaultDelegate>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ReleaseFromSubAccount<'info> {
+    /// Vault owner or a delegate with `DELEGATE_TRANSFER`
+    pub user: Signer<'info>,
 
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = vault_authority_pda
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        seeds = [b"sub_account", vault.key().as_ref(), &[sub_account.index]],
+        bump = sub_account.bump,
+        has_one = vault
+    )]
+    pub sub_account: Account<'info, SubAccount>,
+
+    /// Required when `user` is not the vault owner
+    #[account(
+        seeds = [b"delegate", vault.key().as_ref(), user.key().as_ref()],
+        bump = vault_delegate.bump
+    )]
+    pub vault_delegate: Option<Account<'info, VaultDelegate>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct TransferBetweenSubAccounts<'info> {
+    /// Vault owner or a delegate with `DELEGATE_TRANSFER`
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        seeds = [b"sub_account", vault.key().as_ref(), &[from_sub_account.index]],
+        bump = from_sub_account.bump,
+        has_one = vault
+    )]
+    pub from_sub_account: Account<'info, SubAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"sub_account", vault.key().as_ref(), &[to_sub_account.index]],
+        bump = to_sub_account.bump,
+        has_one = vault
+    )]
+    pub to_sub_account: Account<'info, SubAccount>,
+
+    /// Required when `user` is not the vault owner
+    #[account(
+        seeds = [b"delegate", vault.key().as_ref(), user.key().as_ref()],
+        bump = vault_delegate.bump
     )]
+    pub vault_delegate: Option<Account<'info, VaultDelegate>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct LockSubAccountCollateral<'info> {
+    #[account(
+        mut,
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    /// Identifies the vault's mint for `mint_stats`
+    #[account(address = vault.token_account)]
     pub vault_token_account: Account<'info, TokenAccount>,
 
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+    #[account(
+        mut,
+        seeds = [b"mint_stats", vault_token_account.mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
 
     #[account(
-        seeds = [b"vault", user.key().as_ref()],
-        bump = vault.bump
+        mut,
+        seeds = [b"sub_account", vault.key().as_ref(), &[sub_account.index]],
+        bump = sub_account.bump,
+        has_one = vault
     )]
-    /// CHECK: PDA authority for vault token account
-    pub vault_authority_pda: AccountInfo<'info>,
+    pub sub_account: Account<'info, SubAccount>,
 
     #[account(
         seeds = [b"vault_authority"],
@@ -424,11 +2148,22 @@ pub struct Withdraw<'info> {
     )]
     pub vault_authority: Account<'info, VaultAuthority>,
 
-    pub token_program: Program<'info, Token>,
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
 }
 
+#[event_cpi]
 #[derive(Accounts)]
-pub struct LockCollateral<'info> {
+pub struct UnlockSubAccountCollateral<'info> {
     #[account(
         mut,
         seeds = [b"vault", vault.owner.as_ref()],
@@ -436,6 +2171,25 @@ pub struct LockCollateral<'info> {
     )]
     pub vault: Account<'info, CollateralVault>,
 
+    /// Identifies the vault's mint for `mint_stats`
+    #[account(address = vault.token_account)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", vault_token_account.mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    #[account(
+        mut,
+        seeds = [b"sub_account", vault.key().as_ref(), &[sub_account.index]],
+        bump = sub_account.bump,
+        has_one = vault
+    )]
+    pub sub_account: Account<'info, SubAccount>,
+
     #[account(
         seeds = [b"vault_authority"],
         bump = vault_authority.bump
@@ -444,10 +2198,213 @@ pub struct LockCollateral<'info> {
 
     /// CHECK: Verified by checking authorized_programs
     pub caller_program: AccountInfo<'info>,
+
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct UnlockCollateral<'info> {
+pub struct AddAllowedMint<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AllowedMint::LEN,
+        seeds = [b"allowed_mint", mint.key().as_ref()],
+        bump
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + MintStats::LEN,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
+    #[account(
+        mut,
+        seeds = [b"protocol_stats"],
+        bump = protocol_stats.bump
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+
+    pub mint: Account<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMintEnabled<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        mut,
+        seeds = [b"allowed_mint", allowed_mint.mint.as_ref()],
+        bump = allowed_mint.bump
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+}
+
+#[derive(Accounts)]
+#[instruction(mint: Pubkey)]
+pub struct SetDepositCaps<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint.as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+}
+
+#[derive(Accounts)]
+pub struct AddCollateralConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + CollateralConfig::LEN,
+        seeds = [b"collateral_config", mint.key().as_ref()],
+        bump
+    )]
+    pub collateral_config: Account<'info, CollateralConfig>,
+
+    pub mint: Account<'info, Mint>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateCollateralConfig<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        mut,
+        seeds = [b"collateral_config", collateral_config.mint.as_ref()],
+        bump = collateral_config.bump
+    )]
+    pub collateral_config: Account<'info, CollateralConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeCollateralValue<'info> {
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeInsuranceFund<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + InsuranceFund::LEN,
+        seeds = [b"insurance_fund", mint.key().as_ref()],
+        bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = mint,
+        associated_token::authority = insurance_fund
+    )]
+    pub insurance_fund_token_account: Account<'info, TokenAccount>,
+
+    #[account(token::mint = mint)]
+    pub treasury: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetInsuranceFeeShare<'info> {
+    pub admin: Signer<'info>,
+
+    #[account(
+        seeds = [b"vault_authority"],
+        bump = vault_authority.bump,
+        has_one = admin @ ErrorCode::UnauthorizedAdmin
+    )]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance_fund", insurance_fund.mint.as_ref()],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CollectFee<'info> {
     #[account(
         mut,
         seeds = [b"vault", vault.owner.as_ref()],
@@ -455,61 +2412,91 @@ pub struct UnlockCollateral<'info> {
     )]
     pub vault: Account<'info, CollateralVault>,
 
+    #[account(
+        mut,
+        address = vault.token_account,
+        token::mint = mint
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance_fund", mint.key().as_ref()],
+        bump = insurance_fund.bump,
+        has_one = treasury
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        address = insurance_fund.token_account
+    )]
+    pub insurance_fund_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub treasury: Account<'info, TokenAccount>,
+
     #[account(
         seeds = [b"vault_authority"],
         bump = vault_authority.bump
     )]
     pub vault_authority: Account<'info, VaultAuthority>,
 
-    /// CHECK: Verified by checking authorized_programs
-    pub caller_program: AccountInfo<'info>,
-}
-
-#[derive(Accounts)]
-pub struct TransferCollateral<'info> {
     #[account(
         mut,
-        seeds = [b"vault", from_vault.owner.as_ref()],
-        bump = from_vault.bump,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump = mint_stats.bump
     )]
-    pub from_vault: Account<'info, CollateralVault>,
+    pub mint_stats: Account<'info, MintStats>,
+
+    /// CHECK: Verified by checking authorized_programs
+    pub caller_program: AccountInfo<'info>,
 
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
     #[account(
-        mut,
-        seeds = [b"vault", to_vault.owner.as_ref()],
-        bump = to_vault.bump,
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
     )]
-    pub to_vault: Account<'info, CollateralVault>,
+    pub caller_authority: Signer<'info>,
 
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CoverShortfall<'info> {
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = from_vault_authority
+        seeds = [b"vault", vault.owner.as_ref()],
+        bump = vault.bump,
     )]
-    pub from_vault_token_account: Account<'info, TokenAccount>,
+    pub vault: Account<'info, CollateralVault>,
 
     #[account(
         mut,
-        associated_token::mint = mint,
-        associated_token::authority = to_vault_authority
+        address = vault.token_account,
+        token::mint = mint
     )]
-    pub to_vault_token_account: Account<'info, TokenAccount>,
+    pub vault_token_account: Account<'info, TokenAccount>,
 
-    pub mint: Account<'info, anchor_spl::token::Mint>,
+    pub mint: Account<'info, Mint>,
 
     #[account(
-        seeds = [b"vault", to_vault.owner.as_ref()],
-        bump = to_vault.bump
+        mut,
+        seeds = [b"insurance_fund", mint.key().as_ref()],
+        bump = insurance_fund.bump
     )]
-    /// CHECK: PDA authority for to_vault
-    pub to_vault_authority: AccountInfo<'info>,
+    pub insurance_fund: Account<'info, InsuranceFund>,
 
     #[account(
-        seeds = [b"vault", from_vault.owner.as_ref()],
-        bump = from_vault.bump
+        mut,
+        address = insurance_fund.token_account
     )]
-    /// CHECK: PDA authority for from_vault
-    pub from_vault_authority: AccountInfo<'info>,
+    pub insurance_fund_token_account: Account<'info, TokenAccount>,
 
     #[account(
         seeds = [b"vault_authority"],
@@ -517,9 +2504,25 @@ pub struct TransferCollateral<'info> {
     )]
     pub vault_authority: Account<'info, VaultAuthority>,
 
+    #[account(
+        mut,
+        seeds = [b"mint_stats", mint.key().as_ref()],
+        bump = mint_stats.bump
+    )]
+    pub mint_stats: Account<'info, MintStats>,
+
     /// CHECK: Verified by checking authorized_programs
     pub caller_program: AccountInfo<'info>,
 
+    /// Only `caller_program` can sign for this PDA, so the signature proves
+    /// the instruction is its CPI rather than a transaction naming it
+    #[account(
+        seeds = [CALLER_AUTHORITY_SEED],
+        bump,
+        seeds::program = caller_program.key()
+    )]
+    pub caller_authority: Signer<'info>,
+
     pub token_program: Program<'info, Token>,
 }
 
@@ -537,21 +2540,366 @@ pub struct InitializeVaultAuthority<'info> {
     )]
     pub vault_authority: Account<'info, VaultAuthority>,
 
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + ProtocolStats::LEN,
+        seeds = [b"protocol_stats"],
+        bump
+    )]
+    pub protocol_stats: Account<'info, ProtocolStats>,
+
     pub system_program: Program<'info, System>,
 }
 
 impl CollateralVault {
-    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+
+    /// Credits a deposit to the total and available balances.
+    pub fn record_deposit(&mut self, amount: u64) -> Result<()> {
+        self.total_balance = self
+            .total_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.available_balance = self
+            .available_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.total_deposited = self
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.advance_sequence()
+    }
+
+    /// Debits a withdrawal from the total and available balances.
+    pub fn record_withdrawal(&mut self, amount: u64) -> Result<()> {
+        self.total_balance = self
+            .total_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        self.available_balance = self
+            .available_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        self.total_withdrawn = self
+            .total_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.advance_sequence()
+    }
+
+    /// Checks that `signer` may perform an action needing `permission`: the
+    /// owner always may; anyone else needs an unexpired delegate record
+    /// (whose PDA seeds already tie it to this vault and signer).
+    pub fn authorize(
+        &self,
+        signer: Pubkey,
+        delegate: Option<&VaultDelegate>,
+        permission: u8,
+    ) -> Result<()> {
+        if signer == self.owner {
+            return Ok(());
+        }
+
+        let delegate = delegate.ok_or(ErrorCode::UnauthorizedOwner)?;
+        require!(
+            delegate.permissions & permission == permission,
+            ErrorCode::UnauthorizedDelegate
+        );
+        require!(
+            delegate.expires_at == 0 || Clock::get()?.unix_timestamp < delegate.expires_at,
+            ErrorCode::DelegateExpired
+        );
+        Ok(())
+    }
+
+    /// Moves `amount` from the available to the locked balance.
+    pub fn record_lock(&mut self, amount: u64) -> Result<()> {
+        self.locked_balance = self
+            .locked_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.available_balance = self
+            .available_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        self.advance_sequence()
+    }
+
+    /// Moves `amount` from the locked back to the available balance.
+    pub fn record_unlock(&mut self, amount: u64) -> Result<()> {
+        self.locked_balance = self
+            .locked_balance
+            .checked_sub(amount)
+            .ok_or(ErrorCode::Underflow)?;
+        self.available_balance = self
+            .available_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.advance_sequence()
+    }
+
+    /// Bumps the per-vault sequence number; called once by every instruction
+    /// that mutates the vault.
+    pub fn advance_sequence(&mut self) -> Result<()> {
+        self.sequence = self.sequence.checked_add(1).ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+}
+
+impl SubAccount {
+    pub const LEN: usize = 32 + 1 + 8 + 8 + 1;
+
+    pub fn credit_available(&mut self, amount: u64) -> Result<()> {
+        self.available_balance = self
+            .available_balance
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+
+    pub fn debit_available(&mut self, amount: u64) -> Result<()> {
+        require!(
+            self.available_balance >= amount,
+            ErrorCode::InsufficientAvailableBalance
+        );
+        self.available_balance -= amount;
+        Ok(())
+    }
+}
+
+impl ProtocolStats {
+    pub const MAX_MINTS: usize = 16;
+    pub const LEN: usize = 4 + (Self::MAX_MINTS * 32) + 1;
+
+    pub fn register_mint(&mut self, mint: Pubkey) -> Result<()> {
+        require!(self.mints.len() < Self::MAX_MINTS, ErrorCode::TooManyMints);
+        self.mints.push(mint);
+        Ok(())
+    }
+}
+
+impl MintStats {
+    pub const LEN: usize = 32 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1;
+
+    pub fn record_vault(&mut self) -> Result<()> {
+        self.vault_count = self.vault_count.checked_add(1).ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+
+    pub fn record_deposit(&mut self, amount: u64) -> Result<()> {
+        self.total_deposited = self
+            .total_deposited
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.tvl = self.tvl.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+
+    pub fn record_withdrawal(&mut self, amount: u64) -> Result<()> {
+        self.total_withdrawn = self
+            .total_withdrawn
+            .checked_add(amount)
+            .ok_or(ErrorCode::Overflow)?;
+        self.tvl = self.tvl.checked_sub(amount).ok_or(ErrorCode::Underflow)?;
+        Ok(())
+    }
+
+    pub fn record_lock(&mut self, amount: u64) -> Result<()> {
+        self.total_locked = self.total_locked.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+        Ok(())
+    }
+
+    pub fn record_unlock(&mut self, amount: u64) -> Result<()> {
+        self.total_locked = self.total_locked.checked_sub(amount).ok_or(ErrorCode::Underflow)?;
+        Ok(())
+    }
+
+    /// Fails if the deposit just recorded took the vault or the mint over
+    /// its cap.
+    pub fn enforce_deposit_caps(&self, vault_balance: u64) -> Result<()> {
+        require!(
+            self.max_vault_balance == 0 || vault_balance <= self.max_vault_balance,
+            ErrorCode::DepositCapExceeded
+        );
+        require!(
+            self.max_tvl == 0 || self.tvl <= self.max_tvl,
+            ErrorCode::DepositCapExceeded
+        );
+        Ok(())
+    }
+}
+
+impl AllowedMint {
+    pub const LEN: usize = 32 + 1 + 1;
+}
+
+impl CollateralConfig {
+    pub const LEN: usize = 32 + 32 + 1 + 2 + 2 + 4 + 1;
+
+    fn validate(haircut_bps: u16, max_weight_bps: u16) -> Result<()> {
+        require!(
+            haircut_bps <= BPS_DENOMINATOR && max_weight_bps > 0 && max_weight_bps <= BPS_DENOMINATOR,
+            ErrorCode::InvalidCollateralConfig
+        );
+        Ok(())
+    }
+}
+
+/// Delegate may deposit into the vault from its own token accounts.
+pub const DELEGATE_DEPOSIT: u8 = 1 << 0;
+/// Delegate may withdraw, but only to token accounts owned by the vault owner.
+pub const DELEGATE_WITHDRAW: u8 = 1 << 1;
+/// Delegate may move collateral between the main book and sub-accounts.
+pub const DELEGATE_TRANSFER: u8 = 1 << 2;
+pub const DELEGATE_ALL: u8 = DELEGATE_DEPOSIT | DELEGATE_WITHDRAW | DELEGATE_TRANSFER;
+
+impl VaultDelegate {
+    pub const LEN: usize = 32 + 32 + 1 + 8 + 1;
+
+    fn validate(permissions: u8) -> Result<()> {
+        require!(
+            permissions != 0 && permissions & !DELEGATE_ALL == 0,
+            ErrorCode::InvalidDelegatePermissions
+        );
+        Ok(())
+    }
+}
+
+impl InsuranceFund {
+    pub const LEN: usize = 32 + 32 + 32 + 2 + 8 + 8 + 8 + 1;
 }
 
 impl VaultAuthority {
-    pub const LEN: usize = 4 + (32 * 10) + 1; // Vec<Pubkey> with max 10 programs + bump
+    pub const LEN: usize = 32 + 4 + (32 * 10) + 1; // admin + Vec<Pubkey> with max 10 programs + bump
+}
+
+pub const BPS_DENOMINATOR: u16 = 10_000;
+
+/// Seed of the PDA an authorized program signs with when it CPIs into an
+/// instruction that locks, frees or moves collateral on its behalf
+/// (every instruction that takes a `caller_program`). The PDA is derived
+/// under the caller's program id, not this one.
+pub const CALLER_AUTHORITY_SEED: &[u8] = b"vault_caller";
+
+/// Checks that the token accounts of a batch instruction are the vaults' own
+/// token accounts, in the same order, and hold `mint`.
+fn check_batch_token_accounts<'info>(
+    vaults: &[Account<'info, CollateralVault>],
+    token_infos: &'info [AccountInfo<'info>],
+    mint: &Pubkey,
+) -> Result<()> {
+    for (vault, info) in vaults.iter().zip(token_infos) {
+        require_keys_eq!(
+            vault.token_account,
+            *info.key,
+            anchor_lang::error::ErrorCode::ConstraintTokenOwner
+        );
+        let token_account = Account::<TokenAccount>::try_from(info)?;
+        require_keys_eq!(
+            token_account.mint,
+            *mint,
+            anchor_lang::error::ErrorCode::ConstraintTokenMint
+        );
+    }
+    Ok(())
+}
+
+/// Loads the vaults of a batch instruction from `remaining_accounts`,
+/// checking that there is one per entry, that each is a writable vault PDA,
+/// and that none repeats (a repeated vault would have its first update
+/// overwritten on exit).
+fn load_batch_vaults<'info>(
+    accounts: &'info [AccountInfo<'info>],
+    expected: usize,
+) -> Result<Vec<Account<'info, CollateralVault>>> {
+    require!(
+        expected > 0 && accounts.len() == expected,
+        ErrorCode::InvalidRemainingAccounts
+    );
+
+    let mut vaults: Vec<Account<'info, CollateralVault>> = Vec::with_capacity(expected);
+    for info in accounts {
+        require!(info.is_writable, anchor_lang::error::ErrorCode::ConstraintMut);
+        require!(
+            vaults.iter().all(|vault| vault.key() != *info.key),
+            ErrorCode::DuplicateVault
+        );
+
+        let vault = Account::<CollateralVault>::try_from(info)?;
+        let expected_address = Pubkey::create_program_address(
+            &[b"vault", vault.owner.as_ref(), &[vault.bump]],
+            &crate::ID,
+        )
+        .map_err(|_| anchor_lang::error::ErrorCode::ConstraintSeeds)?;
+        require_keys_eq!(
+            expected_address,
+            *info.key,
+            anchor_lang::error::ErrorCode::ConstraintSeeds
+        );
+
+        vaults.push(vault);
+    }
+
+    Ok(vaults)
+}
+
+/// Pairs payers (negative deltas) with receivers (positive deltas) in order,
+/// returning `(from, to, amount)` transfers. Every transfer settles at least
+/// one side in full, so a balanced batch of N entries needs at most N - 1.
+fn plan_settlement_transfers(deltas: &[i64]) -> Vec<(usize, usize, u64)> {
+    let mut payers: Vec<(usize, u64)> = Vec::new();
+    let mut receivers: Vec<(usize, u64)> = Vec::new();
+    for (index, delta) in deltas.iter().enumerate() {
+        if *delta < 0 {
+            payers.push((index, delta.unsigned_abs()));
+        } else if *delta > 0 {
+            receivers.push((index, delta.unsigned_abs()));
+        }
+    }
+
+    let mut transfers = Vec::with_capacity(deltas.len().saturating_sub(1));
+    let (mut p, mut r) = (0, 0);
+    while p < payers.len() && r < receivers.len() {
+        let amount = payers[p].1.min(receivers[r].1);
+        transfers.push((payers[p].0, receivers[r].0, amount));
+        payers[p].1 -= amount;
+        receivers[r].1 -= amount;
+        if payers[p].1 == 0 {
+            p += 1;
+        }
+        if receivers[r].1 == 0 {
+            r += 1;
+        }
+    }
+
+    transfers
 }
 
+/// Deserializes a remaining account after checking its owner, which `Account`
+/// would otherwise do for declared accounts.
+fn load_account<T: AccountDeserialize + Owner>(info: &AccountInfo) -> Result<T> {
+    if info.owner != &T::owner() {
+        return Err(anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram.into());
+    }
+    T::try_deserialize(&mut &info.try_borrow_data()?[..])
+}
+
+fn apply_bps(value: u64, bps: u16) -> Result<u64> {
+    let scaled = (value as u128) * (bps as u128) / (BPS_DENOMINATOR as u128);
+    u64::try_from(scaled).map_err(|_| error!(ErrorCode::Overflow))
+}
+
+// Every event carries the vault's `sequence` after the instruction. Sequences
+// advance by exactly one per mutating instruction, so an indexer that sees a
+// jump for a vault knows it missed an event.
+
 #[event]
 pub struct VaultInitialized {
     pub user: Pubkey,
     pub vault: Pubkey,
+    pub sequence: u64,
     pub timestamp: i64,
 }
 
@@ -561,6 +2909,7 @@ pub struct DepositEvent {
     pub vault: Pubkey,
     pub amount: u64,
     pub new_balance: u64,
+    pub sequence: u64,
     pub timestamp: i64,
 }
 
@@ -570,6 +2919,7 @@ pub struct WithdrawEvent {
     pub vault: Pubkey,
     pub amount: u64,
     pub new_balance: u64,
+    pub sequence: u64,
     pub timestamp: i64,
 }
 
@@ -579,6 +2929,7 @@ pub struct LockEvent {
     pub vault: Pubkey,
     pub amount: u64,
     pub locked_balance: u64,
+    pub sequence: u64,
     pub timestamp: i64,
 }
 
@@ -588,6 +2939,7 @@ pub struct UnlockEvent {
     pub vault: Pubkey,
     pub amount: u64,
     pub locked_balance: u64,
+    pub sequence: u64,
     pub timestamp: i64,
 }
 
@@ -598,6 +2950,109 @@ pub struct TransferEvent {
     pub from_vault: Pubkey,
     pub to_vault: Pubkey,
     pub amount: u64,
+    pub from_sequence: u64,
+    pub to_sequence: u64,
+    pub timestamp: i64,
+}
+
+/// Per-vault result of a batch lock or unlock.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BatchEntry {
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub locked_balance: u64,
+    pub sequence: u64,
+}
+
+/// Single summary event for a `batch_lock`; carries every vault's new
+/// sequence so indexers can still detect gaps per vault.
+#[event]
+pub struct BatchLockEvent {
+    pub caller_program: Pubkey,
+    pub total_amount: u64,
+    pub entries: Vec<BatchEntry>,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct BatchUnlockEvent {
+    pub caller_program: Pubkey,
+    pub total_amount: u64,
+    pub entries: Vec<BatchEntry>,
+    pub timestamp: i64,
+}
+
+/// Per-vault result of a `settle_batch`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SettlementEntry {
+    pub vault: Pubkey,
+    pub delta: i64,
+    pub total_balance: u64,
+    pub sequence: u64,
+}
+
+#[event]
+pub struct SettlementEvent {
+    pub caller_program: Pubkey,
+    pub mint: Pubkey,
+    pub entries: Vec<SettlementEntry>,
+    pub transfer_count: u32,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeeCollectedEvent {
+    pub user: Pubkey,
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub insurance_share: u64,
+    pub fund_balance: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ShortfallCoveredEvent {
+    pub user: Pubkey,
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub fund_balance: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+/// Owner moves between the main book and sub-accounts. `None` on either side
+/// means the vault's main book.
+#[event]
+pub struct SubAccountTransferEvent {
+    pub user: Pubkey,
+    pub vault: Pubkey,
+    pub from_index: Option<u8>,
+    pub to_index: Option<u8>,
+    pub amount: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SubAccountLockEvent {
+    pub user: Pubkey,
+    pub vault: Pubkey,
+    pub index: u8,
+    pub amount: u64,
+    pub locked_balance: u64,
+    pub sequence: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SubAccountUnlockEvent {
+    pub user: Pubkey,
+    pub vault: Pubkey,
+    pub index: u8,
+    pub amount: u64,
+    pub locked_balance: u64,
+    pub sequence: u64,
     pub timestamp: i64,
 }
 
@@ -617,4 +3072,50 @@ pub enum ErrorCode {
     Overflow,
     #[msg("Integer underflow")]
     Underflow,
+    #[msg("Account required for the vault CPI was not supplied")]
+    MissingCpiAccount,
+    #[msg("Vault does not hold native SOL")]
+    NotNativeVault,
+    #[msg("Source and destination sub-accounts are the same")]
+    SameSubAccount,
+    #[msg("Signer is not the vault authority admin")]
+    UnauthorizedAdmin,
+    #[msg("Haircut must be at most 10000 bps and max weight between 1 and 10000 bps")]
+    InvalidCollateralConfig,
+    #[msg("Remaining accounts do not match the expected layout")]
+    InvalidRemainingAccounts,
+    #[msg("Vault passed more than once")]
+    DuplicateCollateralAccount,
+    #[msg("Vault, token account and collateral config do not match")]
+    CollateralConfigMismatch,
+    #[msg("Price account does not match the collateral config oracle")]
+    OracleMismatch,
+    #[msg("Price account data is invalid")]
+    InvalidPriceAccount,
+    #[msg("Oracle price is stale")]
+    StalePrice,
+    #[msg("Protocol stats track at most 16 mints")]
+    TooManyMints,
+    #[msg("Deposit exceeds the vault or mint deposit cap")]
+    DepositCapExceeded,
+    #[msg("Mint is not allowlisted or is disabled")]
+    MintNotAllowed,
+    #[msg("Vault passed more than once in a batch")]
+    DuplicateVault,
+    #[msg("Settlement deltas do not net to zero")]
+    SettlementNotBalanced,
+    #[msg("Fee share must be at most 10000 bps")]
+    InvalidFeeShare,
+    #[msg("Insurance fund balance is too low")]
+    InsufficientInsuranceFund,
+    #[msg("Delegate permissions must be a non-empty set of known bits")]
+    InvalidDelegatePermissions,
+    #[msg("Delegate lacks the permission for this action")]
+    UnauthorizedDelegate,
+    #[msg("Delegate has expired")]
+    DelegateExpired,
+    #[msg("Delegates may only withdraw to the owner's token accounts")]
+    InvalidWithdrawDestination,
+    #[msg("Token account or mint is not the vault's own")]
+    VaultTokenAccountMismatch,
 }
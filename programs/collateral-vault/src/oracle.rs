@@ -0,0 +1,70 @@
+//! Price account format read by `compute_collateral_value`.
+//!
+//! The layout is intentionally minimal so tests can load a fixture account
+//! instead of running a live oracle. In production `CollateralConfig::oracle`
+//! points at an adapter account kept in this format.
+//!
+//! | Offset | Size | Field          |
+//! |--------|------|----------------|
+//! | 0      | 4    | magic `b"CVPX"`|
+//! | 4      | 8    | `price: i64`   |
+//! | 12     | 4    | `expo: i32`    |
+//! | 16     | 8    | `publish_time` |
+
+use anchor_lang::prelude::*;
+
+use crate::ErrorCode;
+
+pub const PRICE_ACCOUNT_MAGIC: [u8; 4] = *b"CVPX";
+pub const PRICE_ACCOUNT_LEN: usize = 4 + 8 + 4 + 8;
+
+/// Decimals of the USD values returned by `compute_collateral_value`.
+pub const VALUE_DECIMALS: u32 = 6;
+
+/// USD price of one whole token: `price * 10^expo`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceData {
+    pub price: i64,
+    pub expo: i32,
+    pub publish_time: i64,
+}
+
+impl PriceData {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        require!(
+            data.len() >= PRICE_ACCOUNT_LEN && data[..4] == PRICE_ACCOUNT_MAGIC,
+            ErrorCode::InvalidPriceAccount
+        );
+
+        let price = i64::from_le_bytes(data[4..12].try_into().unwrap());
+        let expo = i32::from_le_bytes(data[12..16].try_into().unwrap());
+        let publish_time = i64::from_le_bytes(data[16..24].try_into().unwrap());
+        require!(price > 0, ErrorCode::InvalidPriceAccount);
+
+        Ok(Self {
+            price,
+            expo,
+            publish_time,
+        })
+    }
+
+    /// USD value (with `VALUE_DECIMALS` decimals) of `amount` base units of a
+    /// mint with `decimals` decimals.
+    pub fn value_of(&self, amount: u64, decimals: u8) -> Result<u64> {
+        let scale = VALUE_DECIMALS as i32 + self.expo - decimals as i32;
+        let raw = (amount as u128)
+            .checked_mul(self.price as u128)
+            .ok_or(ErrorCode::Overflow)?;
+
+        let factor = 10u128
+            .checked_pow(scale.unsigned_abs())
+            .ok_or(ErrorCode::Overflow)?;
+        let value = if scale >= 0 {
+            raw.checked_mul(factor).ok_or(ErrorCode::Overflow)?
+        } else {
+            raw / factor
+        };
+
+        u64::try_from(value).map_err(|_| error!(ErrorCode::Overflow))
+    }
+}
@@ -0,0 +1,702 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token::{Token, TokenAccount};
+use collateral_vault::cpi_helpers::{self, VaultCpi};
+use collateral_vault::program::CollateralVault as CollateralVaultProgram;
+use collateral_vault::{
+    CollateralVault, InsuranceFund, MintStats, SubAccount, VaultAuthority, CALLER_AUTHORITY_SEED,
+};
+
+declare_id!("C55UxctAFxQt81Jycd76ibRQ2n6r7HRBmJeSC46eVoLk");
+
+/// Mock position manager used to exercise the vault's CPI surface.
+///
+/// Positions carry no price or PnL logic: opening one locks collateral in the
+/// trader's vault, closing it unlocks the collateral again, and liquidating it
+/// unlocks the collateral and moves it to the liquidator's vault. Liquidation,
+/// sub-account and batch locks, transfers, settlement, fees and shortfall
+/// cover are keeper actions, restricted to the vault admin.
+#[program]
+pub mod position_manager {
+    use super::*;
+
+    pub fn open_position(
+        ctx: Context<OpenPosition>,
+        position_id: u64,
+        collateral: u64,
+    ) -> Result<()> {
+        require!(collateral > 0, ErrorCode::InvalidCollateral);
+
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+        let balances = cpi_helpers::lock(
+            &vault_cpi,
+            &ctx.accounts.owner.key(),
+            &ctx.accounts.vault_token_account.mint,
+            collateral,
+        )?;
+        msg!(
+            "Locked {} (vault locked {}, available {})",
+            collateral,
+            balances.locked_balance,
+            balances.available_balance
+        );
+
+        let position = &mut ctx.accounts.position;
+        position.owner = ctx.accounts.owner.key();
+        position.vault = ctx.accounts.vault.key();
+        position.position_id = position_id;
+        position.collateral = collateral;
+        position.opened_at = Clock::get()?.unix_timestamp;
+        position.bump = ctx.bumps.position;
+
+        Ok(())
+    }
+
+    pub fn close_position(ctx: Context<ClosePosition>) -> Result<()> {
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+        cpi_helpers::unlock(
+            &vault_cpi,
+            &ctx.accounts.owner.key(),
+            &ctx.accounts.vault_token_account.mint,
+            ctx.accounts.position.collateral,
+        )?;
+
+        Ok(())
+    }
+
+    /// Liquidates a position into the liquidator's vault. The mock has no
+    /// health check, so liquidation is a keeper action like settlement.
+    pub fn liquidate_position(ctx: Context<LiquidatePosition>) -> Result<()> {
+        let owner = ctx.accounts.position.owner;
+        let collateral = ctx.accounts.position.collateral;
+
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.liquidator_vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.liquidator_vault_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        // Collateral has to be released before transfer_collateral can move it,
+        // since transfers only draw from the available balance.
+        cpi_helpers::unlock(&vault_cpi, &owner, &ctx.accounts.mint.key(), collateral)?;
+        cpi_helpers::transfer(
+            &vault_cpi,
+            &owner,
+            &ctx.accounts.liquidator.key(),
+            &ctx.accounts.mint.key(),
+            collateral,
+        )?;
+
+        Ok(())
+    }
+
+    /// Locks collateral in one sub-account through
+    /// `lock_sub_account_collateral`.
+    pub fn lock_sub_account(ctx: Context<SubAccountLock>, amount: u64) -> Result<()> {
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.sub_account.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        cpi_helpers::lock_sub_account(
+            &vault_cpi,
+            &ctx.accounts.vault.owner,
+            &ctx.accounts.vault_token_account.mint,
+            ctx.accounts.sub_account.index,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Unlocks collateral in one sub-account through
+    /// `unlock_sub_account_collateral`.
+    pub fn unlock_sub_account(ctx: Context<SubAccountLock>, amount: u64) -> Result<()> {
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.sub_account.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        cpi_helpers::unlock_sub_account(
+            &vault_cpi,
+            &ctx.accounts.vault.owner,
+            &ctx.accounts.vault_token_account.mint,
+            ctx.accounts.sub_account.index,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Locks collateral in the vaults of `owners` through `batch_lock`.
+    /// `remaining_accounts` holds those vaults, writable and in order,
+    /// followed by their token accounts of the `vault_mint_stats` mint.
+    pub fn batch_lock<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchLock<'info>>,
+        owners: Vec<Pubkey>,
+        amounts: Vec<u64>,
+    ) -> Result<()> {
+        require!(owners.len() == amounts.len(), ErrorCode::BatchLengthMismatch);
+
+        let mut accounts = vec![
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        accounts.extend(ctx.remaining_accounts.iter().cloned());
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        let entries: Vec<(Pubkey, u64)> = owners.into_iter().zip(amounts).collect();
+        cpi_helpers::batch_lock(&vault_cpi, &ctx.accounts.vault_mint_stats.mint, &entries)
+    }
+
+    /// Unlocks collateral in the vaults of `owners` through `batch_unlock`;
+    /// see `batch_lock`.
+    pub fn batch_unlock<'info>(
+        ctx: Context<'_, '_, 'info, 'info, BatchLock<'info>>,
+        owners: Vec<Pubkey>,
+        amounts: Vec<u64>,
+    ) -> Result<()> {
+        require!(owners.len() == amounts.len(), ErrorCode::BatchLengthMismatch);
+
+        let mut accounts = vec![
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        accounts.extend(ctx.remaining_accounts.iter().cloned());
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        let entries: Vec<(Pubkey, u64)> = owners.into_iter().zip(amounts).collect();
+        cpi_helpers::batch_unlock(&vault_cpi, &ctx.accounts.vault_mint_stats.mint, &entries)
+    }
+
+    /// Moves available collateral between two vaults through
+    /// `transfer_collateral`.
+    pub fn transfer_collateral(ctx: Context<KeeperTransfer>, amount: u64) -> Result<()> {
+        let accounts = [
+            ctx.accounts.from_vault.to_account_info(),
+            ctx.accounts.to_vault.to_account_info(),
+            ctx.accounts.from_vault_token_account.to_account_info(),
+            ctx.accounts.to_vault_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        cpi_helpers::transfer(
+            &vault_cpi,
+            &ctx.accounts.from_vault.owner,
+            &ctx.accounts.to_vault.owner,
+            &ctx.accounts.mint.key(),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Settles PnL between vaults through `settle_batch`. `remaining_accounts`
+    /// holds the vaults of `owners` and their `mint` token accounts, writable.
+    pub fn settle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Settle<'info>>,
+        owners: Vec<Pubkey>,
+        deltas: Vec<i64>,
+    ) -> Result<()> {
+        require!(owners.len() == deltas.len(), ErrorCode::SettlementLengthMismatch);
+
+        let mut accounts = vec![
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        accounts.extend(ctx.remaining_accounts.iter().cloned());
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        let entries: Vec<(Pubkey, i64)> = owners.into_iter().zip(deltas).collect();
+        cpi_helpers::settle_batch(&vault_cpi, &ctx.accounts.mint.key(), &entries)
+    }
+
+    /// Charges a protocol fee against a trader's vault through `collect_fee`.
+    pub fn charge_fee(ctx: Context<ChargeFee>, amount: u64) -> Result<()> {
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.insurance_fund.to_account_info(),
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts.treasury.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        cpi_helpers::collect_fee(
+            &vault_cpi,
+            &ctx.accounts.vault.owner,
+            &ctx.accounts.mint.key(),
+            amount,
+        )
+    }
+
+    /// Pays a liquidation shortfall into a vault through `cover_shortfall`.
+    pub fn cover_shortfall(ctx: Context<CoverShortfall>, amount: u64) -> Result<()> {
+        let accounts = [
+            ctx.accounts.vault.to_account_info(),
+            ctx.accounts.vault_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.insurance_fund.to_account_info(),
+            ctx.accounts.insurance_fund_token_account.to_account_info(),
+            ctx.accounts.vault_authority.to_account_info(),
+            ctx.accounts.vault_mint_stats.to_account_info(),
+            ctx.accounts.caller_authority.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.vault_event_authority.to_account_info(),
+        ];
+        let vault_cpi = VaultCpi::new(
+            ctx.accounts.collateral_vault_program.to_account_info(),
+            ctx.accounts.position_manager_program.to_account_info(),
+            &accounts,
+        )?;
+
+        cpi_helpers::cover_shortfall(
+            &vault_cpi,
+            &ctx.accounts.vault.owner,
+            &ctx.accounts.mint.key(),
+            amount,
+        )
+    }
+}
+
+#[account]
+pub struct Position {
+    pub owner: Pubkey,
+    pub vault: Pubkey,
+    pub position_id: u64,
+    pub collateral: u64,
+    pub opened_at: i64,
+    pub bump: u8,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct OpenPosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Position::LEN,
+        seeds = [b"position", owner.key().as_ref(), &position_id.to_le_bytes()],
+        bump
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(
+        mut,
+        constraint = vault.owner == owner.key() @ ErrorCode::VaultOwnerMismatch
+    )]
+    pub vault: Account<'info, CollateralVault>,
+
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"position", owner.key().as_ref(), &position.position_id.to_le_bytes()],
+        bump = position.bump,
+        has_one = owner,
+        has_one = vault
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(mut)]
+    pub vault: Account<'info, CollateralVault>,
+
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidatePosition<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(
+        mut,
+        close = liquidator,
+        seeds = [b"position", position.owner.as_ref(), &position.position_id.to_le_bytes()],
+        bump = position.bump,
+        has_one = vault
+    )]
+    pub position: Account<'info, Position>,
+
+    #[account(mut)]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(
+        mut,
+        constraint = liquidator_vault.owner == liquidator.key() @ ErrorCode::VaultOwnerMismatch
+    )]
+    pub liquidator_vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub liquidator_vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct SubAccountLock<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: Account<'info, CollateralVault>,
+
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    #[account(mut)]
+    pub sub_account: Account<'info, SubAccount>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+}
+
+#[derive(Accounts)]
+pub struct BatchLock<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+}
+
+#[derive(Accounts)]
+pub struct KeeperTransfer<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub from_vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub to_vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub from_vault_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub to_vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    pub keeper: Signer<'info>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ChargeFee<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(mut)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(mut)]
+    pub insurance_fund_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub treasury: Account<'info, TokenAccount>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CoverShortfall<'info> {
+    pub keeper: Signer<'info>,
+
+    #[account(mut)]
+    pub vault: Account<'info, CollateralVault>,
+
+    #[account(mut)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, anchor_spl::token::Mint>,
+
+    #[account(mut)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(mut)]
+    pub insurance_fund_token_account: Account<'info, TokenAccount>,
+
+    #[account(constraint = vault_authority.admin == keeper.key() @ ErrorCode::UnauthorizedKeeper)]
+    pub vault_authority: Account<'info, VaultAuthority>,
+
+    #[account(mut)]
+    pub vault_mint_stats: Account<'info, MintStats>,
+
+    /// CHECK: This program's caller PDA, signed for in the vault CPI
+    #[account(seeds = [CALLER_AUTHORITY_SEED], bump)]
+    pub caller_authority: UncheckedAccount<'info>,
+
+    /// CHECK: Vault program's event authority; the vault checks its seeds
+    pub vault_event_authority: UncheckedAccount<'info>,
+
+    pub collateral_vault_program: Program<'info, CollateralVaultProgram>,
+
+    pub position_manager_program: Program<'info, program::PositionManager>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+impl Position {
+    pub const LEN: usize = 32 + 32 + 8 + 8 + 8 + 1;
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Collateral must be greater than zero")]
+    InvalidCollateral,
+    #[msg("Vault is not owned by the expected signer")]
+    VaultOwnerMismatch,
+    #[msg("Only the vault admin may act as keeper")]
+    UnauthorizedKeeper,
+    #[msg("Each settled owner needs exactly one delta")]
+    SettlementLengthMismatch,
+    #[msg("Each batched owner needs exactly one amount")]
+    BatchLengthMismatch,
+}
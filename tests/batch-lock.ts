@@ -0,0 +1,269 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import { PositionManager } from "../target/types/position_manager";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("batch-lock", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  // batch_lock and batch_unlock only accept CPIs signed by an authorized
+  // program, so the batches go through the position manager
+  const positionManager = anchor.workspace.PositionManager as Program<PositionManager>;
+  const admin = provider.wallet;
+  const users = [Keypair.generate(), Keypair.generate()];
+
+  let mint: PublicKey;
+  let vaultAuthority: PublicKey;
+  let vaultEventAuthority: PublicKey;
+  let callerAuthority: PublicKey;
+  let mintStats: PublicKey;
+  const vaults: PublicKey[] = [];
+  const tokenAccounts: PublicKey[] = [];
+
+  // The vaults, writable, followed by their token accounts
+  const asRemaining = (vaultKeys: PublicKey[], tokenKeys: PublicKey[]) => [
+    ...vaultKeys.map((pubkey) => ({ pubkey, isWritable: true, isSigner: false })),
+    ...tokenKeys.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false })),
+  ];
+
+  const totalLocked = async () =>
+    (await program.account.mintStats.fetch(mintStats)).totalLocked.toNumber();
+
+  const batch = (
+    method: "batchLock" | "batchUnlock",
+    amounts: number[],
+    vaultKeys: PublicKey[] = vaults,
+    owners: PublicKey[] = users.map((user) => user.publicKey),
+    tokenKeys: PublicKey[] = tokenAccounts
+  ) =>
+    positionManager.methods[method](
+      owners,
+      amounts.map((amount) => new anchor.BN(amount))
+    )
+      .accounts({
+        keeper: admin.publicKey,
+        vaultMintStats: mintStats,
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: program.programId,
+        positionManagerProgram: positionManager.programId,
+      })
+      .remainingAccounts(asRemaining(vaultKeys, tokenKeys))
+      .rpc();
+
+  before(async () => {
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+    [vaultEventAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("__event_authority")],
+      program.programId
+    );
+    [callerAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_caller")],
+      positionManager.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId, positionManager.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    [mintStats] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_stats"), mint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    for (const user of users) {
+      const sig = await provider.connection.requestAirdrop(
+        user.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(sig);
+
+      const [vault] = PublicKey.findProgramAddressSync(
+        [Buffer.from("vault"), user.publicKey.toBuffer()],
+        program.programId
+      );
+      const vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+      await program.methods
+        .initializeVault()
+        .accounts({
+          user: user.publicKey,
+          vault,
+          vaultTokenAccount,
+          mint,
+          vaultAuthorityPda: vault,
+          vaultAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const userTokenAccount = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin.payer,
+        mint,
+        user.publicKey
+      );
+      await mintTo(
+        provider.connection,
+        admin.payer,
+        mint,
+        userTokenAccount.address,
+        admin.publicKey,
+        1_000_000
+      );
+
+      await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts({
+          user: user.publicKey,
+          vault,
+          userTokenAccount: userTokenAccount.address,
+          vaultTokenAccount,
+          mint,
+          vaultAuthority: vault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+      vaults.push(vault);
+      tokenAccounts.push(vaultTokenAccount);
+    }
+  });
+
+  it("Locks collateral in several vaults at once", async () => {
+    await batch("batchLock", [300_000, 500_000]);
+
+    const [first, second] = await Promise.all(
+      vaults.map((vault) => program.account.collateralVault.fetch(vault))
+    );
+    assert.equal(first.lockedBalance.toNumber(), 300_000);
+    assert.equal(first.availableBalance.toNumber(), 700_000);
+    assert.equal(second.lockedBalance.toNumber(), 500_000);
+    assert.equal(second.availableBalance.toNumber(), 500_000);
+    assert.equal(await totalLocked(), 800_000);
+  });
+
+  it("Rejects batches not signed by the caller program", async () => {
+    // Naming an authorized program is not enough; only it can sign for its
+    // caller PDA
+    const impostor = Keypair.generate();
+    try {
+      await program.methods
+        .batchUnlock([new anchor.BN(300_000), new anchor.BN(500_000)])
+        .accounts({
+          mintStats,
+          vaultAuthority,
+          callerProgram: positionManager.programId,
+          callerAuthority: impostor.publicKey,
+        })
+        .remainingAccounts(asRemaining(vaults, tokenAccounts))
+        .signers([impostor])
+        .rpc();
+      assert.fail("batch_unlock should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+
+    const first = await program.account.collateralVault.fetch(vaults[0]);
+    assert.equal(first.lockedBalance.toNumber(), 300_000);
+  });
+
+  it("Reverts the whole batch when one entry fails", async () => {
+    try {
+      await batch("batchLock", [100_000, 600_000]);
+      assert.fail("batch_lock should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientAvailableBalance");
+    }
+
+    const first = await program.account.collateralVault.fetch(vaults[0]);
+    assert.equal(first.lockedBalance.toNumber(), 300_000);
+    assert.equal(await totalLocked(), 800_000);
+  });
+
+  it("Rejects a vault passed twice", async () => {
+    const owner = users[0].publicKey;
+    try {
+      await batch(
+        "batchLock",
+        [1, 1],
+        [vaults[0], vaults[0]],
+        [owner, owner],
+        [tokenAccounts[0], tokenAccounts[0]]
+      );
+      assert.fail("batch_lock should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "DuplicateVault");
+    }
+  });
+
+  it("Rejects a mismatched number of amounts", async () => {
+    try {
+      await batch("batchUnlock", [1]);
+      assert.fail("batch_unlock should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "BatchLengthMismatch");
+    }
+  });
+
+  it("Unlocks collateral in several vaults at once", async () => {
+    await batch("batchUnlock", [300_000, 500_000]);
+
+    for (const vault of vaults) {
+      const account = await program.account.collateralVault.fetch(vault);
+      assert.equal(account.lockedBalance.toNumber(), 0);
+      assert.equal(account.availableBalance.toNumber(), 1_000_000);
+    }
+    assert.equal(await totalLocked(), 0);
+  });
+});
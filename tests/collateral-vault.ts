@@ -83,7 +83,10 @@ describe("collateral-vault", () => {
   });
 
   it("Initializes vault authority", async () => {
-    const authorizedPrograms = [program.programId]; // For testing
+    const authorizedPrograms = [
+      program.programId, // For testing
+      anchor.workspace.PositionManager.programId,
+    ];
 
     // Check if vault authority already exists
     try {
@@ -106,6 +109,28 @@ describe("collateral-vault", () => {
     console.log("Vault authority initialized:", tx);
   });
 
+  it("Allowlists the collateral mint", async () => {
+    const [allowedMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from("allowed_mint"), mintPubkey.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority: vaultAuthority,
+        allowedMint: allowedMint,
+        mint: mintPubkey,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const entry = await program.account.allowedMint.fetch(allowedMint);
+    const chai = require("chai");
+    chai.assert.isTrue(entry.enabled, "Mint should be enabled");
+  });
+
   it("Initializes user vault", async () => {
     const [vaultPda, vaultBump] = PublicKey.findProgramAddressSync(
       [Buffer.from("vault"), user.publicKey.toBuffer()],
@@ -151,6 +176,7 @@ describe("collateral-vault", () => {
       chai.assert.equal(vaultAccount.totalBalance.toNumber(), 0, "Initial balance should be 0");
       chai.assert.equal(vaultAccount.lockedBalance.toNumber(), 0, "Initial locked balance should be 0");
       chai.assert.equal(vaultAccount.availableBalance.toNumber(), 0, "Initial available balance should be 0");
+      chai.assert.equal(vaultAccount.sequence.toNumber(), 0, "Initial sequence should be 0");
     } catch (err) {
       console.error("Error initializing vault:", err);
       throw err;
@@ -223,7 +249,7 @@ describe("collateral-vault", () => {
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .signers([user])
-        .rpc();
+        .rpc({ commitment: "confirmed" });
 
       console.log("Deposit transaction:", tx);
 
@@ -235,6 +261,29 @@ describe("collateral-vault", () => {
       chai.assert.equal(vaultAccount.totalBalance.toNumber(), 1000000, "Total balance should be 1000000");
       chai.assert.equal(vaultAccount.availableBalance.toNumber(), 1000000, "Available balance should be 1000000");
       chai.assert.equal(vaultAccount.totalDeposited.toNumber(), 1000000, "Total deposited should be 1000000");
+      chai.assert.equal(vaultAccount.sequence.toNumber(), 1, "Deposit should advance the sequence");
+
+      // Deposit hands the post-deposit balances back as return data
+      const txInfo = await provider.connection.getTransaction(tx, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      const returnData = Buffer.from(txInfo.meta.returnData.data[0], "base64");
+      const balances = program.coder.types.decode("vaultBalances", returnData);
+      chai.assert.equal(balances.totalBalance.toNumber(), 1000000, "Returned total should match vault");
+      chai.assert.equal(balances.availableBalance.toNumber(), 1000000, "Returned available should match vault");
+      chai.assert.equal(balances.sequence.toNumber(), 1, "Returned sequence should match vault");
+
+      // Events are emitted through a self-CPI: skip the 8-byte event-CPI tag
+      // and decode the rest of each inner instruction's data
+      const events = txInfo.meta.innerInstructions
+        .flatMap((inner) => inner.instructions)
+        .map((ix) => anchor.utils.bytes.bs58.decode(ix.data))
+        .map((data) => program.coder.events.decode(anchor.utils.bytes.base64.encode(Buffer.from(data.slice(8)))))
+        .filter((event) => event !== null);
+      const depositEvent = events.find((e) => e.name === "depositEvent");
+      chai.assert.isDefined(depositEvent, "Deposit should emit DepositEvent as an inner instruction");
+      chai.assert.equal(depositEvent.data.sequence.toNumber(), 1, "DepositEvent should carry the vault sequence");
     } catch (err) {
       console.error("Error depositing:", err);
       throw err;
@@ -291,9 +340,25 @@ describe("collateral-vault", () => {
       chai.assert.equal(vaultAccount.totalBalance.toNumber(), 500000, "Total balance should be 500000 after withdraw");
       chai.assert.equal(vaultAccount.availableBalance.toNumber(), 500000, "Available balance should be 500000");
       chai.assert.equal(vaultAccount.totalWithdrawn.toNumber(), 500000, "Total withdrawn should be 500000");
+      chai.assert.equal(vaultAccount.sequence.toNumber(), 2, "Withdraw should advance the sequence");
     } catch (err) {
       console.error("Error withdrawing:", err);
       throw err;
     }
   });
+
+  it("Tracks per-mint stats", async () => {
+    const [mintStatsPda] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_stats"), mintPubkey.toBuffer()],
+      program.programId
+    );
+
+    const mintStats = await program.account.mintStats.fetch(mintStatsPda);
+    const chai = require("chai");
+    chai.assert.isTrue(mintStats.mint.equals(mintPubkey), "Stats should belong to the mint");
+    chai.assert.equal(mintStats.vaultCount.toNumber(), 1, "One vault should be counted");
+    chai.assert.equal(mintStats.totalDeposited.toNumber(), 1000000, "Total deposited should be 1000000");
+    chai.assert.equal(mintStats.totalWithdrawn.toNumber(), 500000, "Total withdrawn should be 500000");
+    chai.assert.equal(mintStats.tvl.toNumber(), 500000, "Mint TVL should be 500000");
+  });
 });
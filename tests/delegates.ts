@@ -0,0 +1,276 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+const DELEGATE_DEPOSIT = 1 << 0;
+const DELEGATE_WITHDRAW = 1 << 1;
+const DELEGATE_TRANSFER = 1 << 2;
+
+describe("delegates", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  const admin = provider.wallet;
+  const owner = Keypair.generate();
+  const bot = Keypair.generate();
+
+  let mint: PublicKey;
+  let vault: PublicKey;
+  let vaultTokenAccount: PublicKey;
+  let vaultAuthority: PublicKey;
+  let vaultDelegate: PublicKey;
+  let subAccount: PublicKey;
+  let ownerTokenAccount: PublicKey;
+  let botTokenAccount: PublicKey;
+
+  const withdrawAs = (signer: Keypair, destination: PublicKey, amount: number) =>
+    program.methods
+      .withdraw(new anchor.BN(amount))
+      .accounts({
+        user: signer.publicKey,
+        vault,
+        userTokenAccount: destination,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        vaultDelegate,
+      })
+      .signers([signer])
+      .rpc();
+
+  const updateDelegate = (permissions: number, expiresAt: number) =>
+    program.methods
+      .updateDelegate(permissions, new anchor.BN(expiresAt))
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultDelegate,
+      })
+      .signers([owner])
+      .rpc();
+
+  before(async () => {
+    for (const keypair of [owner, bot]) {
+      const sig = await provider.connection.requestAirdrop(
+        keypair.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(sig);
+    }
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), owner.publicKey.toBuffer()],
+      program.programId
+    );
+    [vaultDelegate] = PublicKey.findProgramAddressSync(
+      [Buffer.from("delegate"), vault.toBuffer(), bot.publicKey.toBuffer()],
+      program.programId
+    );
+    [subAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("sub_account"), vault.toBuffer(), Buffer.from([0])],
+      program.programId
+    );
+    vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+    await program.methods
+      .createSubAccount(0)
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        subAccount,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+    ownerTokenAccount = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin.payer,
+        mint,
+        owner.publicKey
+      )
+    ).address;
+    botTokenAccount = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin.payer,
+        mint,
+        bot.publicKey
+      )
+    ).address;
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      botTokenAccount,
+      admin.publicKey,
+      1_000_000
+    );
+
+    await program.methods
+      .addDelegate(bot.publicKey, DELEGATE_DEPOSIT | DELEGATE_TRANSFER, new anchor.BN(0))
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultDelegate,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+  });
+
+  it("Lets a delegate deposit on the owner's behalf", async () => {
+    await program.methods
+      .deposit(new anchor.BN(500_000))
+      .accounts({
+        user: bot.publicKey,
+        vault,
+        userTokenAccount: botTokenAccount,
+        vaultTokenAccount,
+        mint,
+        vaultAuthority: vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        vaultDelegate,
+      })
+      .signers([bot])
+      .rpc();
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 500_000);
+  });
+
+  it("Lets a delegate move collateral into a sub-account", async () => {
+    await program.methods
+      .allocateToSubAccount(new anchor.BN(200_000))
+      .accounts({
+        user: bot.publicKey,
+        vault,
+        subAccount,
+        vaultDelegate,
+      })
+      .signers([bot])
+      .rpc();
+
+    const sub = await program.account.subAccount.fetch(subAccount);
+    assert.equal(sub.availableBalance.toNumber(), 200_000);
+  });
+
+  it("Rejects a withdrawal without the withdraw permission", async () => {
+    try {
+      await withdrawAs(bot, ownerTokenAccount, 100_000);
+      assert.fail("withdraw should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedDelegate");
+    }
+  });
+
+  it("Only lets a delegate withdraw to the owner's accounts", async () => {
+    await updateDelegate(DELEGATE_DEPOSIT | DELEGATE_WITHDRAW | DELEGATE_TRANSFER, 0);
+
+    try {
+      await withdrawAs(bot, botTokenAccount, 100_000);
+      assert.fail("withdraw should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidWithdrawDestination");
+    }
+
+    await withdrawAs(bot, ownerTokenAccount, 100_000);
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 400_000);
+  });
+
+  it("Ignores an expired delegate", async () => {
+    await updateDelegate(DELEGATE_WITHDRAW, 1);
+
+    try {
+      await withdrawAs(bot, ownerTokenAccount, 100_000);
+      assert.fail("withdraw should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "DelegateExpired");
+    }
+  });
+
+  it("Revokes a delegate", async () => {
+    await program.methods
+      .revokeDelegate()
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultDelegate,
+      })
+      .signers([owner])
+      .rpc();
+
+    assert.isNull(await provider.connection.getAccountInfo(vaultDelegate));
+  });
+});
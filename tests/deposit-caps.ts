@@ -0,0 +1,246 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("deposit-caps", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  const admin = provider.wallet;
+  const alice = Keypair.generate();
+  const bob = Keypair.generate();
+
+  let mint: PublicKey;
+  let vaultAuthority: PublicKey;
+  let mintStats: PublicKey;
+
+  const allowMint = (mint: PublicKey) =>
+    program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  const setupVault = async (owner: Keypair) => {
+    const [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), owner.publicKey.toBuffer()],
+      program.programId
+    );
+    const vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+    const ownerTokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      mint,
+      owner.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      ownerTokenAccount.address,
+      admin.publicKey,
+      10_000_000
+    );
+
+    return (amount: number) =>
+      program.methods
+        .deposit(new anchor.BN(amount))
+        .accounts({
+          user: owner.publicKey,
+          vault,
+          userTokenAccount: ownerTokenAccount.address,
+          vaultTokenAccount,
+          mint,
+          vaultAuthority: vault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner])
+        .rpc();
+  };
+
+  const expectCapExceeded = async (deposit: Promise<string>) => {
+    try {
+      await deposit;
+      assert.fail("deposit should have exceeded the cap");
+    } catch (err) {
+      assert.include(err.toString(), "DepositCapExceeded");
+    }
+  };
+
+  let aliceDeposit: (amount: number) => Promise<string>;
+  let bobDeposit: (amount: number) => Promise<string>;
+
+  before(async () => {
+    for (const wallet of [alice, bob]) {
+      const sig = await provider.connection.requestAirdrop(
+        wallet.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(sig);
+    }
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    [mintStats] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_stats"), mint.toBuffer()],
+      program.programId
+    );
+    await allowMint(mint);
+    aliceDeposit = await setupVault(alice);
+    bobDeposit = await setupVault(bob);
+  });
+
+  it("Rejects caps set by non-admins", async () => {
+    try {
+      await program.methods
+        .setDepositCaps(mint, new anchor.BN(1), new anchor.BN(1))
+        .accounts({ admin: alice.publicKey, vaultAuthority, mintStats })
+        .signers([alice])
+        .rpc();
+      assert.fail("set_deposit_caps should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedAdmin");
+    }
+  });
+
+  it("Sets per-vault and per-mint caps", async () => {
+    await program.methods
+      .setDepositCaps(mint, new anchor.BN(600_000), new anchor.BN(1_000_000))
+      .accounts({ admin: admin.publicKey, vaultAuthority, mintStats })
+      .rpc();
+
+    const entry = await program.account.mintStats.fetch(mintStats);
+    assert.equal(entry.maxVaultBalance.toNumber(), 600_000);
+    assert.equal(entry.maxTvl.toNumber(), 1_000_000);
+  });
+
+  it("Enforces the per-vault cap", async () => {
+    await aliceDeposit(500_000);
+    await expectCapExceeded(aliceDeposit(200_000));
+  });
+
+  it("Enforces the per-mint cap", async () => {
+    await bobDeposit(500_000);
+    await expectCapExceeded(bobDeposit(1));
+
+    const entry = await program.account.mintStats.fetch(mintStats);
+    assert.equal(entry.tvl.toNumber(), 1_000_000);
+  });
+
+  it("Rejects deposits of another mint into a capped vault", async () => {
+    // An uncapped mint must not be usable to top the vault up past its cap
+    const otherMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    await allowMint(otherMint);
+
+    const [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), alice.publicKey.toBuffer()],
+      program.programId
+    );
+    const otherVaultTokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      otherMint,
+      vault,
+      true
+    );
+    const aliceOtherAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      otherMint,
+      alice.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      otherMint,
+      aliceOtherAccount.address,
+      admin.publicKey,
+      1_000_000
+    );
+
+    try {
+      await program.methods
+        .deposit(new anchor.BN(200_000))
+        .accounts({
+          user: alice.publicKey,
+          vault,
+          userTokenAccount: aliceOtherAccount.address,
+          vaultTokenAccount: otherVaultTokenAccount.address,
+          mint: otherMint,
+          vaultAuthority: vault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([alice])
+        .rpc();
+      assert.fail("deposit of another mint should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "VaultTokenAccountMismatch");
+    }
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 500_000);
+  });
+
+  it("Lifts caps when set to zero", async () => {
+    await program.methods
+      .setDepositCaps(mint, new anchor.BN(0), new anchor.BN(0))
+      .accounts({ admin: admin.publicKey, vaultAuthority, mintStats })
+      .rpc();
+
+    await aliceDeposit(2_000_000);
+  });
+});
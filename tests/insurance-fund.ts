@@ -0,0 +1,341 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import { PositionManager } from "../target/types/position_manager";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAccount,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("insurance-fund", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  // collect_fee and cover_shortfall only accept CPIs signed by an authorized
+  // program, so they are called through the position manager
+  const positionManager = anchor.workspace.PositionManager as Program<PositionManager>;
+  const admin = provider.wallet;
+  const user = Keypair.generate();
+
+  let mint: PublicKey;
+  let vault: PublicKey;
+  let vaultTokenAccount: PublicKey;
+  let vaultAuthority: PublicKey;
+  let insuranceFund: PublicKey;
+  let insuranceFundTokenAccount: PublicKey;
+  let treasury: PublicKey;
+  let mintStats: PublicKey;
+  let callerAuthority: PublicKey;
+  let vaultEventAuthority: PublicKey;
+
+  const coverShortfall = (amount: number) =>
+    positionManager.methods
+      .coverShortfall(new anchor.BN(amount))
+      .accounts({
+        keeper: admin.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        insuranceFund,
+        insuranceFundTokenAccount,
+        vaultAuthority,
+        vaultMintStats: mintStats,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: program.programId,
+        positionManagerProgram: positionManager.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+  before(async () => {
+    const sig = await provider.connection.requestAirdrop(
+      user.publicKey,
+      2 * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+    [vaultEventAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("__event_authority")],
+      program.programId
+    );
+    [callerAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_caller")],
+      positionManager.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId, positionManager.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    [mintStats] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_stats"), mint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: user.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const userTokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      mint,
+      user.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      userTokenAccount.address,
+      admin.publicKey,
+      1_000_000
+    );
+
+    await program.methods
+      .deposit(new anchor.BN(1_000_000))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        userTokenAccount: userTokenAccount.address,
+        vaultTokenAccount,
+        mint,
+        vaultAuthority: vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    [insuranceFund] = PublicKey.findProgramAddressSync(
+      [Buffer.from("insurance_fund"), mint.toBuffer()],
+      program.programId
+    );
+    insuranceFundTokenAccount = await getAssociatedTokenAddress(
+      mint,
+      insuranceFund,
+      true
+    );
+    treasury = (
+      await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin.payer,
+        mint,
+        admin.publicKey
+      )
+    ).address;
+  });
+
+  it("Rejects a fee share above 100%", async () => {
+    try {
+      await program.methods
+        .initializeInsuranceFund(10_001)
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          insuranceFund,
+          insuranceFundTokenAccount,
+          treasury,
+          mint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+      assert.fail("initialize_insurance_fund should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InvalidFeeShare");
+    }
+  });
+
+  it("Initializes the insurance fund", async () => {
+    await program.methods
+      .initializeInsuranceFund(2_000)
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        insuranceFund,
+        insuranceFundTokenAccount,
+        treasury,
+        mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.equal(fund.feeShareBps, 2_000);
+    assert.equal(fund.balance.toNumber(), 0);
+    assert.ok(fund.treasury.equals(treasury));
+  });
+
+  it("Rejects fees not signed by the caller program", async () => {
+    // Naming an authorized program is not enough; only it can sign for its
+    // caller PDA
+    const impostor = Keypair.generate();
+    try {
+      await program.methods
+        .collectFee(new anchor.BN(100_000))
+        .accounts({
+          vault,
+          vaultTokenAccount,
+          mint,
+          insuranceFund,
+          insuranceFundTokenAccount,
+          treasury,
+          vaultAuthority,
+          callerProgram: positionManager.programId,
+          callerAuthority: impostor.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([impostor])
+        .rpc();
+      assert.fail("collect_fee should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+  });
+
+  it("Splits a collected fee between the fund and the treasury", async () => {
+    await positionManager.methods
+      .chargeFee(new anchor.BN(100_000))
+      .accounts({
+        keeper: admin.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        insuranceFund,
+        insuranceFundTokenAccount,
+        treasury,
+        vaultAuthority,
+        vaultMintStats: mintStats,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: program.programId,
+        positionManagerProgram: positionManager.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    const fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.equal(fund.balance.toNumber(), 20_000);
+    assert.equal(fund.totalCollected.toNumber(), 20_000);
+
+    const treasuryAccount = await getAccount(provider.connection, treasury);
+    assert.equal(Number(treasuryAccount.amount), 80_000);
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 900_000);
+    assert.equal(vaultAccount.availableBalance.toNumber(), 900_000);
+  });
+
+  it("Rejects shortfall cover not signed by the caller program", async () => {
+    const impostor = Keypair.generate();
+    try {
+      await program.methods
+        .coverShortfall(new anchor.BN(15_000))
+        .accounts({
+          vault,
+          vaultTokenAccount,
+          mint,
+          insuranceFund,
+          insuranceFundTokenAccount,
+          vaultAuthority,
+          callerProgram: positionManager.programId,
+          callerAuthority: impostor.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([impostor])
+        .rpc();
+      assert.fail("cover_shortfall should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+
+    const fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.equal(fund.balance.toNumber(), 20_000);
+  });
+
+  it("Covers a shortfall from the fund", async () => {
+    await coverShortfall(15_000);
+
+    const fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.equal(fund.balance.toNumber(), 5_000);
+    assert.equal(fund.totalCovered.toNumber(), 15_000);
+
+    const fundTokens = await getAccount(provider.connection, insuranceFundTokenAccount);
+    assert.equal(Number(fundTokens.amount), 5_000);
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 915_000);
+  });
+
+  it("Rejects covering more than the fund holds", async () => {
+    try {
+      await coverShortfall(5_001);
+      assert.fail("cover_shortfall should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientInsuranceFund");
+    }
+  });
+});
@@ -0,0 +1,201 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("mint-allowlist", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  const admin = provider.wallet;
+  const user = Keypair.generate();
+
+  let mint: PublicKey;
+  let allowedMint: PublicKey;
+  let vaultAuthority: PublicKey;
+  let vault: PublicKey;
+  let vaultTokenAccount: PublicKey;
+  let userTokenAccount: PublicKey;
+
+  const initializeVault = () =>
+    program.methods
+      .initializeVault()
+      .accounts({
+        user: user.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        allowedMint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+  const deposit = (amount: number) =>
+    program.methods
+      .deposit(new anchor.BN(amount))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        userTokenAccount,
+        vaultTokenAccount,
+        mint,
+        allowedMint,
+        vaultAuthority: vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+  const setEnabled = (enabled: boolean) =>
+    program.methods
+      .setMintEnabled(enabled)
+      .accounts({ admin: admin.publicKey, vaultAuthority, allowedMint })
+      .rpc();
+
+  before(async () => {
+    const sig = await provider.connection.requestAirdrop(
+      user.publicKey,
+      2 * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    [allowedMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from("allowed_mint"), mint.toBuffer()],
+      program.programId
+    );
+    [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    const tokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      mint,
+      user.publicKey
+    );
+    userTokenAccount = tokenAccount.address;
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      userTokenAccount,
+      admin.publicKey,
+      1_000_000
+    );
+  });
+
+  it("Rejects vaults for mints that are not allowlisted", async () => {
+    try {
+      await initializeVault();
+      assert.fail("initialize_vault should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "AccountNotInitialized");
+    }
+  });
+
+  it("Accepts vaults and deposits once the mint is allowlisted", async () => {
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint,
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    await initializeVault();
+    await deposit(600_000);
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 600_000);
+  });
+
+  it("Rejects allowlist changes from non-admins", async () => {
+    try {
+      await program.methods
+        .setMintEnabled(false)
+        .accounts({ admin: user.publicKey, vaultAuthority, allowedMint })
+        .signers([user])
+        .rpc();
+      assert.fail("set_mint_enabled should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedAdmin");
+    }
+  });
+
+  it("Puts a disabled mint into withdraw-only mode", async () => {
+    await setEnabled(false);
+
+    try {
+      await deposit(100_000);
+      assert.fail("deposit should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "MintNotAllowed");
+    }
+
+    await program.methods
+      .withdraw(new anchor.BN(200_000))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        userTokenAccount,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 400_000);
+  });
+
+  it("Accepts deposits again after re-enabling", async () => {
+    await setEnabled(true);
+    await deposit(100_000);
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 500_000);
+  });
+});
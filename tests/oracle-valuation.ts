@@ -0,0 +1,272 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+// Price accounts preloaded from tests/fixtures (see Anchor.toml)
+const USD1_PRICE = new PublicKey("AKvRtHPXyiTTh2WPKbcDxesGGCaa3rJFUSVJSARmPfEW");
+const USD2_PRICE = new PublicKey("sit4PNSACZXnbn6NERjsfdc7wNh24Hv4vy3o6UWzFLQ");
+
+describe("oracle-valuation", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  const admin = provider.wallet;
+  const stableHolder = Keypair.generate();
+  const volatileHolder = Keypair.generate();
+  const secondVolatileHolder = Keypair.generate();
+
+  let vaultAuthority: PublicKey;
+  let stableMint: PublicKey;
+  let volatileMint: PublicKey;
+  let stable: { vault: PublicKey; vaultTokenAccount: PublicKey };
+  let volatile: { vault: PublicKey; vaultTokenAccount: PublicKey };
+  let secondVolatile: { vault: PublicKey; vaultTokenAccount: PublicKey };
+
+  const findConfig = (mint: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("collateral_config"), mint.toBuffer()],
+      program.programId
+    )[0];
+
+  const allowMint = (mint: PublicKey) =>
+    program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  const fundVault = async (owner: Keypair, mint: PublicKey, amount: number) => {
+    const [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), owner.publicKey.toBuffer()],
+      program.programId
+    );
+    const vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+    const ownerTokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      mint,
+      owner.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      ownerTokenAccount.address,
+      admin.publicKey,
+      amount
+    );
+
+    await program.methods
+      .deposit(new anchor.BN(amount))
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        userTokenAccount: ownerTokenAccount.address,
+        vaultTokenAccount,
+        mint,
+        vaultAuthority: vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([owner])
+      .rpc();
+
+    return { vault, vaultTokenAccount };
+  };
+
+  const addConfig = (
+    mint: PublicKey,
+    oracle: PublicKey,
+    haircutBps: number,
+    maxWeightBps: number
+  ) =>
+    program.methods
+      .addCollateralConfig(oracle, haircutBps, maxWeightBps, 0)
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        collateralConfig: findConfig(mint),
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  const group = (
+    { vault, vaultTokenAccount }: { vault: PublicKey; vaultTokenAccount: PublicKey },
+    mint: PublicKey,
+    oracle: PublicKey
+  ) =>
+    [vault, vaultTokenAccount, findConfig(mint), oracle].map((pubkey) => ({
+      pubkey,
+      isWritable: false,
+      isSigner: false,
+    }));
+
+  before(async () => {
+    for (const wallet of [stableHolder, volatileHolder, secondVolatileHolder]) {
+      const sig = await provider.connection.requestAirdrop(
+        wallet.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(sig);
+    }
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    stableMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    volatileMint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 9);
+    await allowMint(stableMint);
+    await allowMint(volatileMint);
+
+    // 1 token at $1 and 3 tokens at $2
+    stable = await fundVault(stableHolder, stableMint, 1_000_000);
+    volatile = await fundVault(volatileHolder, volatileMint, 3_000_000_000);
+    secondVolatile = await fundVault(secondVolatileHolder, volatileMint, 3_000_000_000);
+  });
+
+  it("Registers collateral configs", async () => {
+    await addConfig(stableMint, USD1_PRICE, 1_000, 10_000);
+    await addConfig(volatileMint, USD2_PRICE, 2_000, 5_000);
+
+    const config = await program.account.collateralConfig.fetch(findConfig(volatileMint));
+    assert.equal(config.oracle.toString(), USD2_PRICE.toString());
+    assert.equal(config.decimals, 9);
+    assert.equal(config.haircutBps, 2_000);
+    assert.equal(config.maxWeightBps, 5_000);
+  });
+
+  it("Rejects configs from non-admins", async () => {
+    const mint = await createMint(provider.connection, admin.payer, admin.publicKey, null, 6);
+    try {
+      await program.methods
+        .addCollateralConfig(USD1_PRICE, 0, 10_000, 0)
+        .accounts({
+          admin: stableHolder.publicKey,
+          vaultAuthority,
+          collateralConfig: findConfig(mint),
+          mint,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([stableHolder])
+        .rpc();
+      assert.fail("add_collateral_config should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedAdmin");
+    }
+  });
+
+  it("Returns the haircut-weighted collateral value", async () => {
+    const valuation = await program.methods
+      .computeCollateralValue()
+      .accounts({ vaultAuthority })
+      .remainingAccounts([
+        ...group(stable, stableMint, USD1_PRICE),
+        ...group(volatile, volatileMint, USD2_PRICE),
+      ])
+      .view();
+
+    // $1 + $6 before haircuts, $0.90 + $4.80 after
+    assert.equal(valuation.grossValue.toNumber(), 7_000_000);
+    assert.equal(valuation.haircutValue.toNumber(), 5_700_000);
+    // The volatile mint is capped at 50% of $5.70
+    assert.equal(valuation.weightedValue.toNumber(), 900_000 + 2_850_000);
+  });
+
+  it("Caps a mint's weight across all of its vaults", async () => {
+    const valuation = await program.methods
+      .computeCollateralValue()
+      .accounts({ vaultAuthority })
+      .remainingAccounts([
+        ...group(stable, stableMint, USD1_PRICE),
+        ...group(volatile, volatileMint, USD2_PRICE),
+        ...group(secondVolatile, volatileMint, USD2_PRICE),
+      ])
+      .view();
+
+    // $0.90 + $4.80 + $4.80 after haircuts
+    assert.equal(valuation.haircutValue.toNumber(), 10_500_000);
+    // Each volatile vault alone is under 50% of $10.50, together they are not
+    assert.equal(valuation.weightedValue.toNumber(), 900_000 + 5_250_000);
+  });
+
+  it("Rejects a price account that is not the configured oracle", async () => {
+    try {
+      await program.methods
+        .computeCollateralValue()
+        .accounts({ vaultAuthority })
+        .remainingAccounts(group(stable, stableMint, USD2_PRICE))
+        .rpc();
+      assert.fail("compute_collateral_value should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "OracleMismatch");
+    }
+  });
+
+  it("Rejects the same vault twice", async () => {
+    try {
+      await program.methods
+        .computeCollateralValue()
+        .accounts({ vaultAuthority })
+        .remainingAccounts([
+          ...group(stable, stableMint, USD1_PRICE),
+          ...group(stable, stableMint, USD1_PRICE),
+        ])
+        .rpc();
+      assert.fail("compute_collateral_value should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "DuplicateCollateralAccount");
+    }
+  });
+});
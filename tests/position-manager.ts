@@ -0,0 +1,425 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import { PositionManager } from "../target/types/position_manager";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("position-manager", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const vaultProgram = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  const program = anchor.workspace.PositionManager as Program<PositionManager>;
+  const admin = provider.wallet;
+  const trader = Keypair.generate();
+  const liquidator = Keypair.generate();
+
+  const depositAmount = new anchor.BN(1_000_000);
+  const collateral = new anchor.BN(400_000);
+
+  let mint: PublicKey;
+  let vaultAuthority: PublicKey;
+  let vaultEventAuthority: PublicKey;
+  let traderVault: PublicKey;
+  let traderVaultTokenAccount: PublicKey;
+  let liquidatorVault: PublicKey;
+  let liquidatorVaultTokenAccount: PublicKey;
+  let mintStats: PublicKey;
+  let callerAuthority: PublicKey;
+
+  const findVault = (owner: PublicKey) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), owner.toBuffer()],
+      vaultProgram.programId
+    )[0];
+
+  const findPosition = (owner: PublicKey, positionId: anchor.BN) =>
+    PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("position"),
+        owner.toBuffer(),
+        positionId.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    )[0];
+
+  const allowMint = (mint: PublicKey) =>
+    vaultProgram.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          vaultProgram.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  const initializeVault = async (owner: Keypair) => {
+    const vault = findVault(owner.publicKey);
+    const vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    await vaultProgram.methods
+      .initializeVault()
+      .accounts({
+        user: owner.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+    return { vault, vaultTokenAccount };
+  };
+
+  before(async () => {
+    for (const wallet of [trader, liquidator]) {
+      const sig = await provider.connection.requestAirdrop(
+        wallet.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(sig);
+    }
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      vaultProgram.programId
+    );
+    [vaultEventAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("__event_authority")],
+      vaultProgram.programId
+    );
+    [callerAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_caller")],
+      program.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await vaultProgram.methods
+        .initializeVaultAuthority([vaultProgram.programId, program.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    await allowMint(mint);
+    [mintStats] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_stats"), mint.toBuffer()],
+      vaultProgram.programId
+    );
+
+    ({ vault: traderVault, vaultTokenAccount: traderVaultTokenAccount } =
+      await initializeVault(trader));
+    ({ vault: liquidatorVault, vaultTokenAccount: liquidatorVaultTokenAccount } =
+      await initializeVault(liquidator));
+
+    const traderTokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      mint,
+      trader.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      traderTokenAccount.address,
+      admin.publicKey,
+      depositAmount.toNumber()
+    );
+
+    await vaultProgram.methods
+      .deposit(depositAmount)
+      .accounts({
+        user: trader.publicKey,
+        vault: traderVault,
+        userTokenAccount: traderTokenAccount.address,
+        vaultTokenAccount: traderVaultTokenAccount,
+        mint,
+        vaultAuthority: traderVault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([trader])
+      .rpc();
+  });
+
+  const openPosition = async (positionId: anchor.BN) => {
+    const position = findPosition(trader.publicKey, positionId);
+
+    await program.methods
+      .openPosition(positionId, collateral)
+      .accounts({
+        owner: trader.publicKey,
+        position,
+        vault: traderVault,
+        vaultTokenAccount: traderVaultTokenAccount,
+        vaultMintStats: mintStats,
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: vaultProgram.programId,
+        positionManagerProgram: program.programId,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([trader])
+      .rpc();
+
+    return position;
+  };
+
+  it("Locks collateral when a position is opened", async () => {
+    const position = await openPosition(new anchor.BN(1));
+
+    const vault = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(vault.lockedBalance.toNumber(), 400_000);
+    assert.equal(vault.availableBalance.toNumber(), 600_000);
+    assert.equal(vault.totalBalance.toNumber(), 1_000_000);
+    // deposit + lock
+    assert.equal(vault.sequence.toNumber(), 2);
+    const stats = await vaultProgram.account.mintStats.fetch(mintStats);
+    assert.equal(stats.totalLocked.toNumber(), 400_000);
+
+    const positionAccount = await program.account.position.fetch(position);
+    assert.equal(positionAccount.collateral.toNumber(), 400_000);
+    assert.equal(positionAccount.vault.toString(), traderVault.toString());
+  });
+
+  it("Unlocks collateral when a position is closed", async () => {
+    const position = findPosition(trader.publicKey, new anchor.BN(1));
+
+    await program.methods
+      .closePosition()
+      .accounts({
+        owner: trader.publicKey,
+        position,
+        vault: traderVault,
+        vaultTokenAccount: traderVaultTokenAccount,
+        vaultMintStats: mintStats,
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: vaultProgram.programId,
+        positionManagerProgram: program.programId,
+      })
+      .signers([trader])
+      .rpc();
+
+    const vault = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(vault.lockedBalance.toNumber(), 0);
+    assert.equal(vault.availableBalance.toNumber(), 1_000_000);
+    assert.isNull(await provider.connection.getAccountInfo(position));
+    const stats = await vaultProgram.account.mintStats.fetch(mintStats);
+    assert.equal(stats.totalLocked.toNumber(), 0);
+  });
+
+  it("Rejects locking more than the available balance", async () => {
+    const positionId = new anchor.BN(2);
+    try {
+      await program.methods
+        .openPosition(positionId, new anchor.BN(5_000_000))
+        .accounts({
+          owner: trader.publicKey,
+          position: findPosition(trader.publicKey, positionId),
+          vault: traderVault,
+          vaultTokenAccount: traderVaultTokenAccount,
+          vaultMintStats: mintStats,
+          vaultAuthority,
+          callerAuthority,
+          vaultEventAuthority,
+          collateralVaultProgram: vaultProgram.programId,
+          positionManagerProgram: program.programId,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([trader])
+        .rpc();
+      assert.fail("open_position should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientAvailableBalance");
+    }
+  });
+
+  it("Rejects a top-level lock that names the position manager", async () => {
+    // Naming an authorized program is not enough; only it can sign for its
+    // caller PDA
+    try {
+      await vaultProgram.methods
+        .lockCollateral(collateral)
+        .accounts({
+          vault: traderVault,
+          vaultTokenAccount: traderVaultTokenAccount,
+          mintStats,
+          vaultAuthority,
+          callerProgram: program.programId,
+          callerAuthority: liquidator.publicKey,
+        })
+        .signers([liquidator])
+        .rpc();
+      assert.fail("lock_collateral should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+
+    const vault = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(vault.lockedBalance.toNumber(), 0);
+  });
+
+  const liquidate = (position: PublicKey, keeper: PublicKey = admin.publicKey) =>
+    program.methods
+      .liquidatePosition()
+      .accounts({
+        keeper,
+        liquidator: liquidator.publicKey,
+        position,
+        vault: traderVault,
+        liquidatorVault,
+        vaultTokenAccount: traderVaultTokenAccount,
+        liquidatorVaultTokenAccount,
+        mint,
+        vaultMintStats: mintStats,
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: vaultProgram.programId,
+        positionManagerProgram: program.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([liquidator])
+      .rpc();
+
+  it("Rejects liquidation by anyone but the keeper", async () => {
+    // Without a health check, an arbitrary signer could otherwise take any
+    // open position's collateral into their own vault
+    const position = await openPosition(new anchor.BN(3));
+    try {
+      await liquidate(position, liquidator.publicKey);
+      assert.fail("liquidate_position should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedKeeper");
+    }
+
+    const vault = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(vault.lockedBalance.toNumber(), 400_000);
+  });
+
+  it("Rejects a direct transfer that names the position manager", async () => {
+    // Skipping the keeper check by calling the vault with the same accounts
+    // must fail too: only the position manager can sign for its caller PDA
+    try {
+      await vaultProgram.methods
+        .transferCollateral(new anchor.BN(100_000))
+        .accounts({
+          fromVault: traderVault,
+          toVault: liquidatorVault,
+          fromVaultTokenAccount: traderVaultTokenAccount,
+          toVaultTokenAccount: liquidatorVaultTokenAccount,
+          mint,
+          toVaultAuthority: liquidatorVault,
+          fromVaultAuthority: traderVault,
+          vaultAuthority,
+          callerProgram: program.programId,
+          callerAuthority: liquidator.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([liquidator])
+        .rpc();
+      assert.fail("transfer_collateral should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+
+    const vault = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(vault.availableBalance.toNumber(), 600_000);
+  });
+
+  it("Transfers locked collateral to the liquidator on liquidation", async () => {
+    const position = findPosition(trader.publicKey, new anchor.BN(3));
+
+    await liquidate(position);
+
+    const from = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(from.totalBalance.toNumber(), 600_000);
+    // deposit, lock, unlock, lock, unlock, transfer
+    assert.equal(from.sequence.toNumber(), 6);
+    assert.equal(from.lockedBalance.toNumber(), 0);
+    assert.equal(from.availableBalance.toNumber(), 600_000);
+
+    const to = await vaultProgram.account.collateralVault.fetch(liquidatorVault);
+    assert.equal(to.totalBalance.toNumber(), 400_000);
+    assert.equal(to.availableBalance.toNumber(), 400_000);
+
+    const tokenBalance = await provider.connection.getTokenAccountBalance(
+      liquidatorVaultTokenAccount
+    );
+    assert.equal(tokenBalance.value.amount, "400000");
+    assert.isNull(await provider.connection.getAccountInfo(position));
+  });
+
+  const keeperTransfer = (amount: number, keeper: Keypair | null = null) =>
+    program.methods
+      .transferCollateral(new anchor.BN(amount))
+      .accounts({
+        keeper: keeper ? keeper.publicKey : admin.publicKey,
+        fromVault: liquidatorVault,
+        toVault: traderVault,
+        fromVaultTokenAccount: liquidatorVaultTokenAccount,
+        toVaultTokenAccount: traderVaultTokenAccount,
+        mint,
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: vaultProgram.programId,
+        positionManagerProgram: program.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers(keeper ? [keeper] : [])
+      .rpc();
+
+  it("Rejects keeper transfers by anyone but the keeper", async () => {
+    try {
+      await keeperTransfer(100_000, liquidator);
+      assert.fail("transfer_collateral should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedKeeper");
+    }
+  });
+
+  it("Transfers available collateral for the keeper", async () => {
+    await keeperTransfer(100_000);
+
+    const from = await vaultProgram.account.collateralVault.fetch(liquidatorVault);
+    assert.equal(from.availableBalance.toNumber(), 300_000);
+    const to = await vaultProgram.account.collateralVault.fetch(traderVault);
+    assert.equal(to.availableBalance.toNumber(), 700_000);
+  });
+});
@@ -0,0 +1,261 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import { PositionManager } from "../target/types/position_manager";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAccount,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("settle-batch", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  // settle_batch only accepts CPIs signed by an authorized program, so the
+  // settlements go through the position manager
+  const positionManager = anchor.workspace.PositionManager as Program<PositionManager>;
+  const admin = provider.wallet;
+  const users = [Keypair.generate(), Keypair.generate(), Keypair.generate()];
+
+  let mint: PublicKey;
+  let vaultAuthority: PublicKey;
+  let vaultEventAuthority: PublicKey;
+  let callerAuthority: PublicKey;
+  const vaults: PublicKey[] = [];
+  const vaultTokenAccounts: PublicKey[] = [];
+
+  const settlementAccounts = () =>
+    [...vaults, ...vaultTokenAccounts].map((pubkey) => ({
+      pubkey,
+      isWritable: true,
+      isSigner: false,
+    }));
+
+  const settle = (deltas: number[]) =>
+    positionManager.methods
+      .settle(
+        users.map((user) => user.publicKey),
+        deltas.map((delta) => new anchor.BN(delta))
+      )
+      .accounts({
+        keeper: admin.publicKey,
+        mint,
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: program.programId,
+        positionManagerProgram: positionManager.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts(settlementAccounts())
+      .rpc();
+
+  before(async () => {
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+    [vaultEventAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("__event_authority")],
+      program.programId
+    );
+    [callerAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_caller")],
+      positionManager.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId, positionManager.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    for (const user of users) {
+      const sig = await provider.connection.requestAirdrop(
+        user.publicKey,
+        2 * anchor.web3.LAMPORTS_PER_SOL
+      );
+      await provider.connection.confirmTransaction(sig);
+
+      const [vault] = PublicKey.findProgramAddressSync(
+        [Buffer.from("vault"), user.publicKey.toBuffer()],
+        program.programId
+      );
+      const vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+      await program.methods
+        .initializeVault()
+        .accounts({
+          user: user.publicKey,
+          vault,
+          vaultTokenAccount,
+          mint,
+          vaultAuthorityPda: vault,
+          vaultAuthority,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const userTokenAccount = await getOrCreateAssociatedTokenAccount(
+        provider.connection,
+        admin.payer,
+        mint,
+        user.publicKey
+      );
+      await mintTo(
+        provider.connection,
+        admin.payer,
+        mint,
+        userTokenAccount.address,
+        admin.publicKey,
+        1_000_000
+      );
+
+      await program.methods
+        .deposit(new anchor.BN(1_000_000))
+        .accounts({
+          user: user.publicKey,
+          vault,
+          userTokenAccount: userTokenAccount.address,
+          vaultTokenAccount,
+          mint,
+          vaultAuthority: vault,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+
+      vaults.push(vault);
+      vaultTokenAccounts.push(vaultTokenAccount);
+    }
+  });
+
+  it("Rejects deltas that do not net to zero", async () => {
+    try {
+      await settle([-300_000, 100_000, 100_000]);
+      assert.fail("settle_batch should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "SettlementNotBalanced");
+    }
+  });
+
+  it("Rejects settlements not signed by the caller program", async () => {
+    // Naming an authorized program is not enough; only it can sign for its
+    // caller PDA
+    const impostor = Keypair.generate();
+    try {
+      await program.methods
+        .settleBatch([
+          new anchor.BN(-300_000),
+          new anchor.BN(100_000),
+          new anchor.BN(200_000),
+        ])
+        .accounts({
+          mint,
+          vaultAuthority,
+          callerProgram: positionManager.programId,
+          callerAuthority: impostor.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(settlementAccounts())
+        .signers([impostor])
+        .rpc();
+      assert.fail("settle_batch should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+
+    const vault = await program.account.collateralVault.fetch(vaults[0]);
+    assert.equal(vault.availableBalance.toNumber(), 1_000_000);
+  });
+
+  it("Settles one payer against two receivers", async () => {
+    await settle([-300_000, 100_000, 200_000]);
+
+    const expected = [700_000, 1_100_000, 1_200_000];
+    for (let i = 0; i < vaults.length; i++) {
+      const vault = await program.account.collateralVault.fetch(vaults[i]);
+      assert.equal(vault.totalBalance.toNumber(), expected[i]);
+      assert.equal(vault.availableBalance.toNumber(), expected[i]);
+
+      const tokenAccount = await getAccount(provider.connection, vaultTokenAccounts[i]);
+      assert.equal(Number(tokenAccount.amount), expected[i]);
+    }
+  });
+
+  it("Rejects settlements from anyone but the keeper", async () => {
+    const outsider = users[0];
+    try {
+      await positionManager.methods
+        .settle(
+          users.map((user) => user.publicKey),
+          [new anchor.BN(100_000), new anchor.BN(-50_000), new anchor.BN(-50_000)]
+        )
+        .accounts({
+          keeper: outsider.publicKey,
+          mint,
+          vaultAuthority,
+          callerAuthority,
+          vaultEventAuthority,
+          collateralVaultProgram: program.programId,
+          positionManagerProgram: positionManager.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .remainingAccounts(settlementAccounts())
+        .signers([outsider])
+        .rpc();
+      assert.fail("settle should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "UnauthorizedKeeper");
+    }
+  });
+
+  it("Rejects a payer without enough available balance", async () => {
+    try {
+      await settle([-800_000, 400_000, 400_000]);
+      assert.fail("settle_batch should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientAvailableBalance");
+    }
+  });
+});
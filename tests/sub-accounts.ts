@@ -0,0 +1,313 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import { PositionManager } from "../target/types/position_manager";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddress,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("sub-accounts", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  // Sub-account locks only accept CPIs signed by an authorized program, so
+  // they go through the position manager
+  const positionManager = anchor.workspace.PositionManager as Program<PositionManager>;
+  const admin = provider.wallet;
+  const user = Keypair.generate();
+
+  let mint: PublicKey;
+  let vault: PublicKey;
+  let vaultAuthority: PublicKey;
+  let vaultEventAuthority: PublicKey;
+  let callerAuthority: PublicKey;
+  let vaultTokenAccount: PublicKey;
+  let mintStats: PublicKey;
+
+  const totalLocked = async () =>
+    (await program.account.mintStats.fetch(mintStats)).totalLocked.toNumber();
+
+  const findSubAccount = (index: number) =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("sub_account"), vault.toBuffer(), Buffer.from([index])],
+      program.programId
+    )[0];
+
+  const allowMint = (mint: PublicKey) =>
+    program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+  const subAccountLock = (
+    method: "lockSubAccount" | "unlockSubAccount",
+    index: number,
+    amount: number
+  ) =>
+    positionManager.methods[method](new anchor.BN(amount))
+      .accounts({
+        keeper: admin.publicKey,
+        vault,
+        vaultTokenAccount,
+        vaultMintStats: mintStats,
+        subAccount: findSubAccount(index),
+        vaultAuthority,
+        callerAuthority,
+        vaultEventAuthority,
+        collateralVaultProgram: program.programId,
+        positionManagerProgram: positionManager.programId,
+      })
+      .rpc();
+
+  before(async () => {
+    const sig = await provider.connection.requestAirdrop(
+      user.publicKey,
+      2 * anchor.web3.LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+    [vaultEventAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("__event_authority")],
+      program.programId
+    );
+    [callerAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_caller")],
+      positionManager.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId, positionManager.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    await allowMint(mint);
+    [mintStats] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint_stats"), mint.toBuffer()],
+      program.programId
+    );
+
+    [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    vaultTokenAccount = await getAssociatedTokenAddress(mint, vault, true);
+
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: user.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const userTokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      mint,
+      user.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin.payer,
+      mint,
+      userTokenAccount.address,
+      admin.publicKey,
+      1_000_000
+    );
+
+    await program.methods
+      .deposit(new anchor.BN(1_000_000))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        userTokenAccount: userTokenAccount.address,
+        vaultTokenAccount,
+        mint,
+        vaultAuthority: vault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    for (const index of [0, 1]) {
+      await program.methods
+        .createSubAccount(index)
+        .accounts({
+          user: user.publicKey,
+          vault,
+          subAccount: findSubAccount(index),
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+    }
+  });
+
+  it("Allocates collateral from the main book to a sub-account", async () => {
+    await program.methods
+      .allocateToSubAccount(new anchor.BN(600_000))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        subAccount: findSubAccount(0),
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.availableBalance.toNumber(), 400_000);
+    assert.equal(vaultAccount.subAccountBalance.toNumber(), 600_000);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 1_000_000);
+
+    const subAccount = await program.account.subAccount.fetch(findSubAccount(0));
+    assert.equal(subAccount.availableBalance.toNumber(), 600_000);
+  });
+
+  it("Transfers between sub-accounts", async () => {
+    await program.methods
+      .transferBetweenSubAccounts(new anchor.BN(200_000))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        fromSubAccount: findSubAccount(0),
+        toSubAccount: findSubAccount(1),
+      })
+      .signers([user])
+      .rpc();
+
+    const from = await program.account.subAccount.fetch(findSubAccount(0));
+    const to = await program.account.subAccount.fetch(findSubAccount(1));
+    assert.equal(from.availableBalance.toNumber(), 400_000);
+    assert.equal(to.availableBalance.toNumber(), 200_000);
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.subAccountBalance.toNumber(), 600_000);
+  });
+
+  it("Locks collateral in a single sub-account", async () => {
+    await subAccountLock("lockSubAccount", 0, 300_000);
+
+    const subAccount = await program.account.subAccount.fetch(findSubAccount(0));
+    assert.equal(subAccount.lockedBalance.toNumber(), 300_000);
+    assert.equal(subAccount.availableBalance.toNumber(), 100_000);
+
+    // The main book and the other sub-account are unaffected
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.lockedBalance.toNumber(), 0);
+    assert.equal(vaultAccount.availableBalance.toNumber(), 400_000);
+    const other = await program.account.subAccount.fetch(findSubAccount(1));
+    assert.equal(other.availableBalance.toNumber(), 200_000);
+
+    // Sub-account locks count towards the mint's locked total
+    assert.equal(await totalLocked(), 300_000);
+  });
+
+  it("Rejects sub-account unlocks not signed by the caller program", async () => {
+    // The vault owner naming an authorized program cannot free collateral a
+    // position still relies on
+    try {
+      await program.methods
+        .unlockSubAccountCollateral(new anchor.BN(300_000))
+        .accounts({
+          vault,
+          vaultTokenAccount,
+          mintStats,
+          subAccount: findSubAccount(0),
+          vaultAuthority,
+          callerProgram: positionManager.programId,
+          callerAuthority: user.publicKey,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("unlock_sub_account_collateral should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "ConstraintSeeds");
+    }
+
+    const subAccount = await program.account.subAccount.fetch(findSubAccount(0));
+    assert.equal(subAccount.lockedBalance.toNumber(), 300_000);
+  });
+
+  it("Rejects releasing locked sub-account collateral", async () => {
+    try {
+      await program.methods
+        .releaseFromSubAccount(new anchor.BN(200_000))
+        .accounts({
+          user: user.publicKey,
+          vault,
+          subAccount: findSubAccount(0),
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("release_from_sub_account should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientAvailableBalance");
+    }
+  });
+
+  it("Releases unlocked collateral back to the main book", async () => {
+    await subAccountLock("unlockSubAccount", 0, 300_000);
+    assert.equal(await totalLocked(), 0);
+
+    await program.methods
+      .releaseFromSubAccount(new anchor.BN(400_000))
+      .accounts({
+        user: user.publicKey,
+        vault,
+        subAccount: findSubAccount(0),
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.availableBalance.toNumber(), 800_000);
+    assert.equal(vaultAccount.subAccountBalance.toNumber(), 200_000);
+    // deposit, allocate, transfer, lock, unlock, release
+    assert.equal(vaultAccount.sequence.toNumber(), 6);
+  });
+});
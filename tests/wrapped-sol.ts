@@ -0,0 +1,261 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { CollateralVault } from "../target/types/collateral_vault";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  NATIVE_MINT,
+  createAssociatedTokenAccount,
+  createMint,
+  getAssociatedTokenAddress,
+} from "@solana/spl-token";
+import { PublicKey, Keypair, SystemProgram, LAMPORTS_PER_SOL } from "@solana/web3.js";
+import { assert } from "chai";
+
+describe("wrapped-sol", () => {
+  const provider = anchor.AnchorProvider.env();
+  anchor.setProvider(provider);
+
+  const program = anchor.workspace.CollateralVault as Program<CollateralVault>;
+  const admin = provider.wallet;
+  const user = Keypair.generate();
+
+  let vault: PublicKey;
+  let vaultTokenAccount: PublicKey;
+  let vaultAuthority: PublicKey;
+
+  before(async () => {
+    const sig = await provider.connection.requestAirdrop(
+      user.publicKey,
+      5 * LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    [vaultAuthority] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault_authority")],
+      program.programId
+    );
+
+    // The collateral-vault suite normally initializes the authority; only do
+    // it here when this file runs on its own.
+    const existing = await provider.connection.getAccountInfo(vaultAuthority);
+    if (!existing) {
+      await program.methods
+        .initializeVaultAuthority([program.programId])
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    const [allowedMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from("allowed_mint"), NATIVE_MINT.toBuffer()],
+      program.programId
+    );
+    if (!(await provider.connection.getAccountInfo(allowedMint))) {
+      await program.methods
+        .addAllowedMint()
+        .accounts({
+          admin: admin.publicKey,
+          vaultAuthority,
+          allowedMint,
+          mint: NATIVE_MINT,
+          systemProgram: SystemProgram.programId,
+        })
+        .rpc();
+    }
+
+    [vault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), user.publicKey.toBuffer()],
+      program.programId
+    );
+    vaultTokenAccount = await getAssociatedTokenAddress(NATIVE_MINT, vault, true);
+
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: user.publicKey,
+        vault,
+        vaultTokenAccount,
+        mint: NATIVE_MINT,
+        vaultAuthorityPda: vault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+  });
+
+  it("Wraps deposited SOL", async () => {
+    const amount = new anchor.BN(2 * LAMPORTS_PER_SOL);
+
+    await program.methods
+      .depositSol(amount)
+      .accounts({
+        user: user.publicKey,
+        vault,
+        vaultTokenAccount,
+        nativeMint: NATIVE_MINT,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toString(), amount.toString());
+    assert.equal(vaultAccount.availableBalance.toString(), amount.toString());
+    assert.equal(vaultAccount.sequence.toNumber(), 1);
+
+    const tokenBalance = await provider.connection.getTokenAccountBalance(
+      vaultTokenAccount
+    );
+    assert.equal(tokenBalance.value.amount, amount.toString());
+  });
+
+  it("Unwraps withdrawn SOL to the owner", async () => {
+    const amount = new anchor.BN(LAMPORTS_PER_SOL);
+    const [unwrapAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("unwrap"), vault.toBuffer()],
+      program.programId
+    );
+    const before = await provider.connection.getBalance(user.publicKey);
+
+    await program.methods
+      .withdrawSol(amount)
+      .accounts({
+        user: user.publicKey,
+        vault,
+        vaultTokenAccount,
+        unwrapAccount,
+        nativeMint: NATIVE_MINT,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultAccount = await program.account.collateralVault.fetch(vault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), LAMPORTS_PER_SOL);
+    assert.equal(vaultAccount.totalWithdrawn.toNumber(), LAMPORTS_PER_SOL);
+    assert.equal(vaultAccount.sequence.toNumber(), 2);
+
+    // The user pays the fee but gets the unwrap account's rent back
+    const after = await provider.connection.getBalance(user.publicKey);
+    assert.isAbove(after - before, LAMPORTS_PER_SOL - 10_000);
+    assert.isNull(await provider.connection.getAccountInfo(unwrapAccount));
+  });
+
+  it("Rejects withdrawing more than the available balance", async () => {
+    const [unwrapAccount] = PublicKey.findProgramAddressSync(
+      [Buffer.from("unwrap"), vault.toBuffer()],
+      program.programId
+    );
+    try {
+      await program.methods
+        .withdrawSol(new anchor.BN(10 * LAMPORTS_PER_SOL))
+        .accounts({
+          user: user.publicKey,
+          vault,
+          vaultTokenAccount,
+          unwrapAccount,
+          nativeMint: NATIVE_MINT,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+      assert.fail("withdraw_sol should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "InsufficientAvailableBalance");
+    }
+  });
+
+  it("Rejects SOL deposits into a vault of another mint", async () => {
+    const tokenUser = Keypair.generate();
+    const sig = await provider.connection.requestAirdrop(
+      tokenUser.publicKey,
+      2 * LAMPORTS_PER_SOL
+    );
+    await provider.connection.confirmTransaction(sig);
+
+    const mint = await createMint(
+      provider.connection,
+      admin.payer,
+      admin.publicKey,
+      null,
+      6
+    );
+    await program.methods
+      .addAllowedMint()
+      .accounts({
+        admin: admin.publicKey,
+        vaultAuthority,
+        allowedMint: PublicKey.findProgramAddressSync(
+          [Buffer.from("allowed_mint"), mint.toBuffer()],
+          program.programId
+        )[0],
+        mint,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+
+    const [tokenVault] = PublicKey.findProgramAddressSync(
+      [Buffer.from("vault"), tokenUser.publicKey.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .initializeVault()
+      .accounts({
+        user: tokenUser.publicKey,
+        vault: tokenVault,
+        vaultTokenAccount: await getAssociatedTokenAddress(mint, tokenVault, true),
+        mint,
+        vaultAuthorityPda: tokenVault,
+        vaultAuthority,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([tokenUser])
+      .rpc();
+
+    // Anyone can create the vault's WSOL ATA; it must not be accepted as the
+    // vault's token account
+    const wsolAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      admin.payer,
+      NATIVE_MINT,
+      tokenVault,
+      undefined,
+      TOKEN_PROGRAM_ID,
+      ASSOCIATED_TOKEN_PROGRAM_ID,
+      true
+    );
+
+    try {
+      await program.methods
+        .depositSol(new anchor.BN(LAMPORTS_PER_SOL))
+        .accounts({
+          user: tokenUser.publicKey,
+          vault: tokenVault,
+          vaultTokenAccount: wsolAccount,
+          nativeMint: NATIVE_MINT,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([tokenUser])
+        .rpc();
+      assert.fail("deposit_sol should have failed");
+    } catch (err) {
+      assert.include(err.toString(), "NotNativeVault");
+    }
+
+    const vaultAccount = await program.account.collateralVault.fetch(tokenVault);
+    assert.equal(vaultAccount.totalBalance.toNumber(), 0);
+  });
+});